@@ -0,0 +1,138 @@
+use card_builder::{
+  file_db::{FileDbReader, FileDbWriter},
+  normalize::PunctuationWidth,
+  vocab::{read_vocab_from, UnknownWords, VocabFormat, VocabSource},
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use jieba_rs::Jieba;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{hint::black_box, time::Instant};
+
+const HSK_FIXTURE: &str = include_str!("fixtures/hsk.csv");
+const SENTENCES_FIXTURE: &str = include_str!("fixtures/sentences.txt");
+const ITERATIONS: usize = 10_000;
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+  prefix: Option<String>,
+  sentence: String,
+  suffix: Option<String>,
+}
+
+fn segment_and_analyze(c: &mut Criterion) {
+  let vocab = read_vocab_from(
+    HSK_FIXTURE.as_bytes(),
+    VocabSource::Hsk3,
+    VocabFormat::Csv,
+    PunctuationWidth::Full,
+    true,
+  )
+  .unwrap();
+  let segmenter = Jieba::new();
+  let sentences = SENTENCES_FIXTURE.lines().collect::<Vec<_>>();
+
+  let mut group = c.benchmark_group("corpus");
+  group.throughput(Throughput::Elements(ITERATIONS as u64));
+  group.sample_size(10);
+  group.bench_function("cut_and_analyze", |b| {
+    b.iter(|| {
+      for sentence in sentences.iter().cycle().take(ITERATIONS) {
+        let words = segmenter.cut(sentence, false);
+        let count = vocab
+          .analyze(&words, UnknownWords::Skip)
+          .map(|phrases| phrases.count());
+        black_box(count);
+      }
+    })
+  });
+  group.bench_function("cut_and_find_phrases", |b| {
+    b.iter(|| {
+      for sentence in sentences.iter().cycle().take(ITERATIONS) {
+        let words = segmenter.cut(sentence, false);
+        black_box(vocab.analyze_matches(sentence, &words, UnknownWords::Skip));
+      }
+    })
+  });
+  group.finish();
+}
+
+fn file_db_round_trip(c: &mut Criterion) {
+  // Removed with everything the writer leaves next to it, e.g. the manifest.
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("round-trip.txt");
+  let sentences = SENTENCES_FIXTURE.lines().collect::<Vec<_>>();
+  let record = |i: usize| Record {
+    prefix: Some(sentences[i % sentences.len()].to_string()),
+    sentence: sentences[(i + 1) % sentences.len()].to_string(),
+    suffix: None,
+  };
+
+  c.bench_function("file_db_round_trip", |b| {
+    b.iter_custom(|iters| {
+      let start = Instant::now();
+      let mut writer = FileDbWriter::new(&path).unwrap();
+      let ranges = (0..iters as usize)
+        .map(|i| writer.write(&record(i)).unwrap())
+        .collect::<Vec<_>>();
+      drop(writer);
+
+      let mut reader = FileDbReader::load(&path).unwrap();
+      for range in ranges {
+        black_box(reader.read::<Record>(range).unwrap());
+      }
+      start.elapsed()
+    })
+  });
+}
+
+/// Scattered reads like deck building does, from the buffered reader (with
+/// and without its cache) and the memory-mapped one.
+fn file_db_random_reads(c: &mut Criterion) {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("random-reads.txt");
+  let sentences = SENTENCES_FIXTURE.lines().collect::<Vec<_>>();
+  let mut writer = FileDbWriter::new(&path).unwrap();
+  let ranges = (0..ITERATIONS)
+    .map(|i| {
+      let record = Record {
+        prefix: Some(sentences[i % sentences.len()].to_string()),
+        sentence: sentences[(i + 1) % sentences.len()].to_string(),
+        suffix: None,
+      };
+      writer.write(&record).unwrap()
+    })
+    .collect::<Vec<_>>();
+  writer.finish().unwrap();
+  let mut rng = StdRng::seed_from_u64(0);
+  let reads = (0..ITERATIONS)
+    .map(|_| ranges[rng.gen_range(0..ranges.len())].clone())
+    .collect::<Vec<_>>();
+
+  let mut group = c.benchmark_group("file_db_random_reads");
+  group.throughput(Throughput::Elements(ITERATIONS as u64));
+  let mut bench = |name: &str, mut reader: FileDbReader| {
+    group.bench_function(name, |b| {
+      b.iter(|| {
+        for range in &reads {
+          black_box(reader.read::<Record>(range.clone()).unwrap());
+        }
+      })
+    });
+  };
+  bench(
+    "buffered",
+    FileDbReader::with_cache_capacity(&path, 0).unwrap(),
+  );
+  bench("buffered_cached", FileDbReader::load(&path).unwrap());
+  bench("mmap", FileDbReader::load_mmap(&path).unwrap());
+  group.finish();
+}
+
+criterion_group!(
+  benches,
+  segment_and_analyze,
+  file_db_round_trip,
+  file_db_random_reads
+);
+criterion_main!(benches);
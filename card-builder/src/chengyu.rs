@@ -0,0 +1,28 @@
+use ahash::HashSet;
+use flate2::read::GzDecoder;
+use std::{io::Read, sync::LazyLock};
+
+/// Common four-character idioms, one per line, gzip-compressed.
+const CHENGYU_LIST: &[u8] = include_bytes!("../data/chengyu.txt.gz");
+
+static CHENGYU: LazyLock<HashSet<String>> = LazyLock::new(|| {
+  let mut list = String::new();
+  GzDecoder::new(CHENGYU_LIST)
+    .read_to_string(&mut list)
+    .expect("the bundled chengyu list is valid gzip");
+  list.lines().map(str::to_string).collect()
+});
+
+fn is_han(c: char) -> bool {
+  matches!(c,
+    '\u{3400}'..='\u{4DBF}'
+    | '\u{4E00}'..='\u{9FFF}'
+    | '\u{F900}'..='\u{FAFF}'
+    | '\u{20000}'..='\u{2FA1F}')
+}
+
+/// Whether `phrase` is a chengyu (成语): exactly four Han characters, and in
+/// the bundled idiom list.
+pub fn is_chengyu(phrase: &str) -> bool {
+  phrase.chars().count() == 4 && phrase.chars().all(is_han) && CHENGYU.contains(phrase)
+}
@@ -0,0 +1,207 @@
+use crate::error::{bail, Result};
+use regex::Regex;
+use std::iter;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A regex matching runs of any of `delimiters`, for [`split_sentences`].
+pub fn sentence_delimiters(delimiters: &str) -> Result<Regex> {
+  if delimiters.is_empty() {
+    bail!("sentence delimiters must not be empty");
+  }
+  let class = delimiters
+    .chars()
+    .map(|c| regex::escape(c.encode_utf8(&mut [0; 4])))
+    .collect::<String>();
+  Ok(Regex::new(&format!("[{class}]+"))?)
+}
+
+/// The non-empty sentences of `text`, split on `delimiters`.
+pub fn split_sentences<'a>(text: &'a str, delimiters: &Regex) -> Vec<&'a str> {
+  split_delimited(text, delimiters)
+    .into_iter()
+    .map(|(sentence, _)| sentence)
+    .collect()
+}
+
+/// Like [`split_sentences`], with the delimiters that end each sentence, so
+/// neighbouring sentences can be joined back as they were written. The last
+/// sentence's delimiter is empty if the text doesn't end with one.
+pub fn split_delimited<'a>(text: &'a str, delimiters: &Regex) -> Vec<(&'a str, &'a str)> {
+  let mut sentences = Vec::new();
+  let mut start = 0;
+  let ends = delimiters
+    .find_iter(text)
+    .map(|delimiter| delimiter.range())
+    .chain(iter::once(text.len()..text.len()));
+  for end in ends {
+    let sentence = text[start..end.start].trim();
+    if !sentence.is_empty() {
+      sentences.push((sentence, &text[end.clone()]));
+    }
+    start = end.end;
+  }
+  sentences
+}
+
+/// Joins adjacent sentences from [`split_delimited`] back into one, each
+/// followed by its own delimiter except the last.
+pub fn join_delimited<'a>(sentences: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+  let mut text = String::new();
+  let mut last_delimiter = "";
+  for (sentence, delimiter) in sentences {
+    text.push_str(last_delimiter);
+    text.push_str(sentence);
+    last_delimiter = delimiter;
+  }
+  text
+}
+
+/// Byte offset of each word in the sentence the words were cut from.
+pub fn token_starts(words: &[&str]) -> Vec<usize> {
+  words
+    .iter()
+    .scan(0, |start, word| {
+      let token = *start;
+      *start += word.len();
+      Some(token)
+    })
+    .collect()
+}
+
+/// Where `phrase` starts in `sentence`. With the sentence's token starts, the
+/// first occurrence that begins and ends on a token boundary is preferred, so
+/// a word inside a longer word isn't clozed.
+pub fn phrase_location(sentence: &str, phrase: &str, tokens: &[usize]) -> Option<usize> {
+  let is_boundary = |i: usize| i == sentence.len() || tokens.binary_search(&i).is_ok();
+  let mut locs = sentence.match_indices(phrase).map(|(i, _)| i);
+  let first = locs.next()?;
+  if tokens.is_empty() || is_boundary(first) && is_boundary(first + phrase.len()) {
+    return Some(first);
+  }
+  Some(
+    locs
+      .find(|i| is_boundary(*i) && is_boundary(i + phrase.len()))
+      .unwrap_or(first),
+  )
+}
+
+/// HTML-escapes `text` and also encodes the braces and colons Anki's cloze
+/// parser treats as syntax, so `{{`, `}}`, and `::` in corpus text can't
+/// open, close, or add a hint to a deletion.
+pub fn escape_text(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in html_escape::encode_safe(text).chars() {
+    match c {
+      '{' => escaped.push_str("&#123;"),
+      '}' => escaped.push_str("&#125;"),
+      ':' => escaped.push_str("&#58;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// Replaces the phrase at `loc` with `hole`.
+///
+/// Snippets store raw corpus text, so every piece is escaped here. The
+/// pieces are escaped separately so the markup in `hole` is never touched.
+pub fn fill_hole(sentence: &str, phrase: &str, loc: usize, hole: &str) -> String {
+  let (before, rest) = sentence.split_at(loc);
+  let after = &rest[phrase.len()..];
+  format!("{}{hole}{}", escape_text(before), escape_text(after))
+}
+
+/// Replaces the phrase at `loc` with an Anki cloze deletion of it.
+pub fn make_cloze(sentence: &str, phrase: &str, loc: usize) -> String {
+  let hole = format!("{{{{c1::{}}}}}", escape_text(phrase));
+  fill_hole(sentence, phrase, loc, &hole)
+}
+
+/// How much of `sentence` a cloze of `phrase` hides, by grapheme count. A
+/// small ratio makes for a cloze that's easy to guess from context.
+pub fn cloze_ratio(sentence: &str, phrase: &str) -> f64 {
+  let sentence_len = sentence.graphemes(true).count();
+  if sentence_len == 0 {
+    return 0.;
+  }
+  phrase.graphemes(true).count() as f64 / sentence_len as f64
+}
+
+/// Whether `phrase`, where [`phrase_location`] would cloze it, opens or
+/// closes `sentence`, which makes for a weaker cloze.
+pub fn phrase_at_edge(sentence: &str, phrase: &str, tokens: &[usize]) -> bool {
+  match phrase_location(sentence, phrase, tokens) {
+    Some(loc) => loc == 0 || loc + phrase.len() == sentence.len(),
+    None => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_sentences_on_delimiters() {
+    let delimiters = sentence_delimiters("。！？").unwrap();
+    assert_eq!(
+      split_sentences("我喜欢你。 你呢？？好！", &delimiters),
+      vec!["我喜欢你", "你呢", "好"]
+    );
+    assert!(split_sentences("。。", &delimiters).is_empty());
+    assert!(sentence_delimiters("").is_err());
+  }
+
+  #[test]
+  fn rejoins_sentences_with_their_own_delimiters() {
+    let delimiters = sentence_delimiters("。！？").unwrap();
+    let sentences = split_delimited("我喜欢你！ 你呢？？好", &delimiters);
+    assert_eq!(
+      sentences,
+      [("我喜欢你", "！"), ("你呢", "？？"), ("好", "")]
+    );
+    assert_eq!(join_delimited(sentences.clone()), "我喜欢你！你呢？？好");
+    assert_eq!(join_delimited(sentences[1..2].to_vec()), "你呢");
+  }
+
+  #[test]
+  fn make_cloze_wraps_the_phrase() {
+    let sentence = "我喜欢学习中文";
+    let loc = sentence.find("学习").unwrap();
+    assert_eq!(make_cloze(sentence, "学习", loc), "我喜欢{{c1::学习}}中文");
+  }
+
+  #[test]
+  fn make_cloze_escapes_cloze_syntax() {
+    let sentence = "<b>{{c2::x}}</b>中文";
+    let loc = sentence.find("中文").unwrap();
+    assert_eq!(
+      make_cloze(sentence, "中文", loc),
+      "&lt;b&gt;&#123;&#123;c2&#58;&#58;x&#125;&#125;&lt;&#x2F;b&gt;{{c1::中文}}"
+    );
+  }
+
+  #[test]
+  fn cloze_ratio_counts_graphemes() {
+    assert_eq!(cloze_ratio("我喜欢学习中文", "中文"), 2. / 7.);
+    assert_eq!(cloze_ratio("", "中文"), 0.);
+  }
+
+  #[test]
+  fn phrase_location_prefers_whole_words() {
+    // 学 alone appears first inside 学生, then as its own word.
+    let sentence = "学生学";
+    let tokens = token_starts(&["学生", "学"]);
+    assert_eq!(phrase_location(sentence, "学", &tokens), Some(6));
+    assert_eq!(phrase_location(sentence, "学", &[]), Some(0));
+  }
+
+  #[test]
+  fn phrase_at_edge_uses_the_clozed_occurrence() {
+    // 学 opens the sentence inside 学生, but is clozed as the middle word.
+    let sentence = "学生学中文";
+    let tokens = token_starts(&["学生", "学", "中文"]);
+    assert!(!phrase_at_edge(sentence, "学", &tokens));
+    assert!(phrase_at_edge(sentence, "学", &[]));
+    assert!(phrase_at_edge(sentence, "中文", &tokens));
+  }
+}
@@ -0,0 +1,347 @@
+use crate::{
+  deck::{CardTemplate, NoteKind},
+  error::{Context, Result},
+};
+use clap::ValueEnum;
+use serde::{de::Error, Deserialize, Deserializer};
+use std::{
+  collections::BTreeMap,
+  fs,
+  path::{Path, PathBuf},
+};
+use tracing::warn;
+
+/// Settings read from `zhlearn.toml`. Command-line flags override them.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+  pub paths: PathsConfig,
+  pub corpus: CorpusConfig,
+  pub deck: DeckConfig,
+  pub template: TemplateConfig,
+  /// Labels and weights for corpus files, as `[[sources]]` tables.
+  pub sources: Vec<SourceConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PathsConfig {
+  /// Word list file, unless `--vocab-path` is given.
+  pub vocab: Option<PathBuf>,
+  /// Corpus files, unless `--corpus` is given.
+  pub corpus: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CorpusConfig {
+  /// Corpus entries scoring below this are skipped.
+  pub min_score: Option<f64>,
+  /// Shortest sentence, in graphemes, that can become a card.
+  pub min_length: Option<usize>,
+  /// Longest sentence, in graphemes, unless `--max-length` is given.
+  pub max_length: Option<usize>,
+  /// Unless `--snippets-per-phrase` is given.
+  pub snippets_per_phrase: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct DeckConfig {
+  /// Unless `--cards-per-deck` is given.
+  pub cards_per_deck: Option<usize>,
+  /// Replaces the word list's base for deck IDs. Anki identifies decks by
+  /// ID, so changing it makes new decks rather than updating old ones.
+  pub id_base: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct TemplateConfig {
+  /// Stylesheet that replaces the built-in card CSS, inline.
+  pub css: Option<String>,
+  /// Stylesheet that replaces the built-in card CSS, read from a file.
+  pub css_path: Option<PathBuf>,
+  /// Cloze card templates, unless `--template-dir` is given.
+  pub dir: Option<PathBuf>,
+  /// Id of the cloze note type, unless `--model-id` is given.
+  pub model_id: Option<i64>,
+  /// What each card shows, unless `--card-style` is given.
+  pub card_style: Option<CardStyle>,
+  /// Offsets the note type IDs, unless `--model-version` is given.
+  pub model_version: Option<i64>,
+  /// Cloze card templates, as `[template.cloze]`; they replace those of
+  /// `dir` side by side, unless `--template-dir` is given.
+  pub cloze: NoteTemplateConfig,
+  /// Basic card templates, as `[template.basic]`.
+  pub basic: NoteTemplateConfig,
+  /// Reading passage templates, as `[template.reading]`.
+  pub reading: NoteTemplateConfig,
+}
+
+/// Replacements for a note type's built-in templates, each side given inline
+/// or read from a file.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct NoteTemplateConfig {
+  /// Question template, inline.
+  pub front: Option<String>,
+  /// Question template, read from a file.
+  pub front_path: Option<PathBuf>,
+  /// Answer template, inline.
+  pub back: Option<String>,
+  /// Answer template, read from a file.
+  pub back_path: Option<PathBuf>,
+}
+
+impl NoteTemplateConfig {
+  /// Whether any side is replaced.
+  pub fn is_set(&self) -> bool {
+    self.front.is_some()
+      || self.front_path.is_some()
+      || self.back.is_some()
+      || self.back_path.is_some()
+  }
+
+  /// `template` with the sides given here replaced, checked for `kind`.
+  /// Inline sides are named after `config`, the file they came from.
+  pub fn apply(
+    &self,
+    mut template: CardTemplate,
+    kind: NoteKind,
+    config: &Path,
+  ) -> Result<CardTemplate> {
+    let side = |inline: &Option<String>, path: &Option<PathBuf>| -> Result<_> {
+      Ok(match (inline, path) {
+        (Some(html), _) => Some((html.clone(), config.to_path_buf())),
+        (None, Some(path)) => {
+          let html =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+          Some((html, path.clone()))
+        }
+        (None, None) => None,
+      })
+    };
+    let mut paths = (config.to_path_buf(), config.to_path_buf());
+    if let Some((front, path)) = side(&self.front, &self.front_path)? {
+      template.front = front;
+      paths.0 = path;
+    }
+    if let Some((back, path)) = side(&self.back, &self.back_path)? {
+      template.back = back;
+      paths.1 = path;
+    }
+    template.check(kind, &paths.0, &paths.1)?;
+    Ok(template)
+  }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CardStyle {
+  /// One sentence per card with a single phrase clozed.
+  Sentence,
+  /// A passage of 3-5 adjacent sentences per card, with every phrase of the
+  /// deck's level clozed.
+  #[serde(rename = "reading")]
+  #[value(name = "reading")]
+  ReadingPassage,
+}
+
+/// A corpus file's label, which is stored with its snippets and tagged on
+/// their notes, and how strongly its snippets are preferred at each level.
+#[derive(Deserialize)]
+pub struct SourceConfig {
+  pub path: PathBuf,
+  pub label: String,
+  /// Multipliers on the ranking score of this source's snippets, keyed by
+  /// level, e.g. `weights = { 1 = 2.0, 6 = 0.5 }`. Unlisted levels use 1.0.
+  #[serde(default, deserialize_with = "parse_weights")]
+  pub weights: BTreeMap<usize, f64>,
+}
+
+/// TOML keys are always strings, so level numbers are parsed here.
+fn parse_weights<'de, D>(deserializer: D) -> Result<BTreeMap<usize, f64>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  BTreeMap::<String, f64>::deserialize(deserializer)?
+    .into_iter()
+    .map(|(level, weight)| {
+      let level = level
+        .parse()
+        .map_err(|_| D::Error::custom(format!("invalid level {level:?}")))?;
+      Ok((level, weight))
+    })
+    .collect()
+}
+
+impl Config {
+  /// Reads the config at `path`, or the defaults if there is no such file.
+  /// Unknown keys are logged and ignored, so a typo doesn't stop a build.
+  ///
+  /// Relative paths inside the file are resolved against its directory.
+  pub fn load(path: &Path) -> Result<Self> {
+    if !path.exists() {
+      return Ok(Config::default());
+    }
+    let contents = fs::read_to_string(path)?;
+    let deserializer = toml::Deserializer::new(&contents);
+    let mut config: Config = serde_ignored::deserialize(deserializer, |key| {
+      warn!(%key, path = %path.display(), "ignoring unknown config key");
+    })
+    .with_context(|| format!("invalid config {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    if let Some(vocab) = &mut config.paths.vocab {
+      *vocab = dir.join(&*vocab);
+    }
+    for corpus in &mut config.paths.corpus {
+      *corpus = dir.join(&*corpus);
+    }
+    if let Some(css_path) = &mut config.template.css_path {
+      *css_path = dir.join(&*css_path);
+    }
+    if let Some(template_dir) = &mut config.template.dir {
+      *template_dir = dir.join(&*template_dir);
+    }
+    for template in [
+      &mut config.template.cloze,
+      &mut config.template.basic,
+      &mut config.template.reading,
+    ] {
+      for path in [&mut template.front_path, &mut template.back_path]
+        .into_iter()
+        .flatten()
+      {
+        *path = dir.join(&*path);
+      }
+    }
+    for source in &mut config.sources {
+      source.path = dir.join(&source.path);
+    }
+    Ok(config)
+  }
+
+  /// The `[[sources]]` entry for a corpus file, if any. Paths are compared
+  /// after resolving them, so `../corpus/a.jsonl` and `corpus/a.jsonl` can
+  /// name the same file.
+  pub fn source(&self, path: &Path) -> Option<&SourceConfig> {
+    let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let path = resolve(path);
+    self
+      .sources
+      .iter()
+      .find(|source| resolve(&source.path) == path)
+  }
+
+  /// The card CSS, given inline or read from `css_path`.
+  pub fn css(&self) -> Result<Option<String>> {
+    if let Some(css) = &self.template.css {
+      return Ok(Some(css.clone()));
+    }
+    self
+      .template
+      .css_path
+      .as_ref()
+      .map(|path| fs::read_to_string(path).with_context(|| format!("reading {}", path.display())))
+      .transpose()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn load_resolves_paths_and_ignores_unknown_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("zhlearn.toml");
+    fs::write(
+      &path,
+      r#"
+        colour = "red"
+
+        [paths]
+        vocab = "hsk.csv"
+        corpus = ["corpus/a.jsonl"]
+
+        [corpus]
+        min_score = 0.5
+        min_lenght = 4
+
+        [deck]
+        cards_per_deck = 20
+
+        [template]
+        css = ".card { color: red; }"
+      "#,
+    )
+    .unwrap();
+
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.paths.vocab, Some(dir.path().join("hsk.csv")));
+    assert_eq!(config.paths.corpus, [dir.path().join("corpus/a.jsonl")]);
+    assert_eq!(config.corpus.min_score, Some(0.5));
+    assert_eq!(config.corpus.min_length, None);
+    assert_eq!(config.deck.cards_per_deck, Some(20));
+    assert_eq!(
+      config.css().unwrap().as_deref(),
+      Some(".card { color: red; }")
+    );
+  }
+
+  #[test]
+  fn templates_are_replaced_inline_or_from_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("zhlearn.toml");
+    fs::write(
+      &path,
+      r#"
+        [template.basic]
+        front = "{{Blanked}}?"
+
+        [template.reading]
+        back_path = "reading-back.html"
+      "#,
+    )
+    .unwrap();
+    fs::write(dir.path().join("reading-back.html"), "{{Passage}}").unwrap();
+
+    let config = Config::load(&path).unwrap();
+    assert!(!config.template.cloze.is_set());
+    let basic = config
+      .template
+      .basic
+      .apply(
+        CardTemplate::builtin(NoteKind::Basic),
+        NoteKind::Basic,
+        &path,
+      )
+      .unwrap();
+    assert_eq!(basic.front, "{{Blanked}}?");
+    assert_eq!(basic.back, CardTemplate::builtin(NoteKind::Basic).back);
+    let reading = config
+      .template
+      .reading
+      .apply(
+        CardTemplate::builtin(NoteKind::Reading),
+        NoteKind::Reading,
+        &path,
+      )
+      .unwrap();
+    assert_eq!(reading.back, "{{Passage}}");
+
+    // Sides are checked against the note type they replace.
+    let err = config
+      .template
+      .basic
+      .apply(
+        CardTemplate::builtin(NoteKind::Cloze),
+        NoteKind::Cloze,
+        &path,
+      )
+      .err()
+      .unwrap();
+    assert!(err.to_string().contains("no {{cloze:Sentence}}"), "{err}");
+  }
+}
@@ -0,0 +1,722 @@
+use crate::{
+  error::{bail, Context, Result},
+  wiki::{strip_wikitext, WikiPages},
+};
+use bzip2::read::MultiBzDecoder;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use indicatif::ProgressBar;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::{
+  fmt,
+  fs::{self, File},
+  io::{self, BufRead, BufReader, Read},
+  iter,
+  ops::RangeInclusive,
+  path::{Path, PathBuf},
+  sync::LazyLock,
+  time::UNIX_EPOCH,
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// One document of the corpus.
+#[derive(Deserialize)]
+pub struct CorpusEntry {
+  pub text: String,
+  /// Quality score. Lines from other tools (e.g. piped over stdin) often
+  /// have no such metadata, so a missing score counts as 1.0.
+  #[serde(default = "default_score")]
+  pub score: f64,
+  /// An English translation of the text, shown on the back of its cards.
+  #[serde(default)]
+  pub translation: Option<String>,
+  /// The document's id in the corpus, if it has one, to trace cards back to
+  /// it.
+  #[serde(default, alias = "doc_id", deserialize_with = "deserialize_id")]
+  pub id: Option<String>,
+  /// The 1-based line the entry starts on, for line-based formats.
+  #[serde(skip)]
+  pub line: Option<u64>,
+}
+
+fn default_score() -> f64 {
+  1.0
+}
+
+/// Ids may be strings or numbers.
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+  Ok(
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+      None | Some(serde_json::Value::Null) => None,
+      Some(serde_json::Value::String(id)) => Some(id),
+      Some(id) => Some(id.to_string()),
+    },
+  )
+}
+
+/// How a corpus source is turned into [`CorpusEntry`]s.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, ValueEnum)]
+pub enum CorpusFormat {
+  /// One JSON object with `text` and optionally `score` per line.
+  Jsonl,
+  /// One plain-text document per line, with a score of 1.0.
+  Text,
+  /// Plain-text documents separated by blank lines, with a score of 1.0.
+  /// The lines of a paragraph are joined without spaces, as Chinese prose
+  /// wrapped across lines would be.
+  Paragraphs,
+  /// A MediaWiki XML dump such as zhwiki-latest-pages-articles.xml.bz2; each
+  /// article becomes a document with its markup stripped.
+  Wiki,
+  /// A Tatoeba TSV export: either sentences.csv (`id`, `lang`, `text`), of
+  /// which only Mandarin rows are read, or a Mandarin-English sentence pairs
+  /// download (`id`, `text`, `translation id`, `translation`).
+  Tatoeba,
+}
+
+fn parse_text(line: &str) -> Option<CorpusEntry> {
+  let text = line.trim();
+  (!text.is_empty()).then(|| CorpusEntry {
+    text: text.to_string(),
+    score: 1.0,
+    translation: None,
+    id: None,
+    line: None,
+  })
+}
+
+struct TatoebaRow {
+  line: u64,
+  id: String,
+  text: String,
+  translation: Option<String>,
+}
+
+fn parse_tatoeba(line_no: u64, line: &str) -> Result<Option<TatoebaRow>> {
+  let fields = line.split('\t').collect::<Vec<_>>();
+  Ok(match fields[..] {
+    [id, lang, text] => (lang == "cmn").then(|| TatoebaRow {
+      line: line_no,
+      id: id.to_string(),
+      text: text.to_string(),
+      translation: None,
+    }),
+    [id, text, _, translation] => Some(TatoebaRow {
+      line: line_no,
+      id: id.to_string(),
+      text: text.to_string(),
+      translation: Some(translation.trim().to_string()).filter(|t| !t.is_empty()),
+    }),
+    _ => bail!(
+      "expected 3 or 4 tab-separated fields, found {}",
+      fields.len()
+    ),
+  })
+}
+
+/// An input's documents. The outer result fails if the input can't be read;
+/// the inner one fails for a single document that can't be parsed, which
+/// callers can skip.
+pub type CorpusEntries = Box<dyn Iterator<Item = Result<Result<CorpusEntry>>>>;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Compression {
+  None,
+  Gzip,
+  Zstd,
+  Bzip2,
+}
+
+fn compression_of(magic: &[u8]) -> Compression {
+  if magic.starts_with(GZIP_MAGIC) {
+    Compression::Gzip
+  } else if magic.starts_with(ZSTD_MAGIC) {
+    Compression::Zstd
+  } else if magic.starts_with(BZIP2_MAGIC) {
+    Compression::Bzip2
+  } else {
+    Compression::None
+  }
+}
+
+/// Identifies a corpus file's compression from its magic bytes, so it
+/// doesn't matter what the file is named.
+pub fn detect_compression(path: &Path) -> Result<Compression> {
+  let mut magic = Vec::with_capacity(4);
+  File::open(path)?.take(4).read_to_end(&mut magic)?;
+  Ok(compression_of(&magic))
+}
+
+/// Wraps `reader` in a decoder if its first bytes are a compression magic
+/// number.
+fn decompress(mut reader: Box<dyn BufRead>) -> Result<Box<dyn BufRead>> {
+  Ok(match compression_of(reader.fill_buf()?) {
+    Compression::None => reader,
+    Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+    Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+    Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+  })
+}
+
+impl CorpusFormat {
+  /// Reads entries in this format from the lines of `reader`, which is
+  /// already decompressed.
+  pub fn read(self, reader: Box<dyn BufRead>) -> CorpusEntries {
+    match self {
+      CorpusFormat::Jsonl => Box::new(reader.lines().zip(1..).map(|(line, line_no)| {
+        let line = line?;
+        Ok(
+          serde_json::from_str(&line)
+            .map(|entry| CorpusEntry {
+              line: Some(line_no),
+              ..entry
+            })
+            .with_context(|| format!("line {line_no}")),
+        )
+      })),
+      CorpusFormat::Text => Box::new(reader.lines().zip(1..).filter_map(
+        |(line, line_no)| match line {
+          Ok(line) => {
+            let entry = parse_text(&line)?;
+            Some(Ok(Ok(CorpusEntry {
+              line: Some(line_no),
+              ..entry
+            })))
+          }
+          Err(err) => Some(Err(err.into())),
+        },
+      )),
+      CorpusFormat::Paragraphs => {
+        let mut lines = reader.lines().zip(1..);
+        Box::new(iter::from_fn(move || {
+          let mut paragraph: Option<CorpusEntry> = None;
+          for (line, line_no) in lines.by_ref() {
+            let line = match line {
+              Ok(line) => line,
+              Err(err) => return Some(Err(err.into())),
+            };
+            match (parse_text(&line), &mut paragraph) {
+              (Some(entry), Some(paragraph)) => paragraph.text.push_str(&entry.text),
+              (Some(entry), None) => {
+                paragraph = Some(CorpusEntry {
+                  line: Some(line_no),
+                  ..entry
+                })
+              }
+              (None, Some(_)) => break,
+              (None, None) => {}
+            }
+          }
+          paragraph.map(|paragraph| Ok(Ok(paragraph)))
+        }))
+      }
+      CorpusFormat::Wiki => Box::new(WikiPages::new(reader).map(|page| {
+        Ok(Ok(CorpusEntry {
+          text: strip_wikitext(&page?),
+          score: 1.0,
+          translation: None,
+          id: None,
+          line: None,
+        }))
+      })),
+      // A sentence with several translations is repeated on consecutive
+      // rows of a pairs download; only its first translation is kept.
+      CorpusFormat::Tatoeba => {
+        let mut rows = reader
+          .lines()
+          .zip(1..)
+          .filter_map(|(line, line_no)| match line {
+            Ok(line) => parse_tatoeba(line_no, &line)
+              .with_context(|| format!("line {line_no}"))
+              .transpose()
+              .map(Ok),
+            Err(err) => Some(Err(err.into())),
+          })
+          .peekable();
+        Box::new(iter::from_fn(move || {
+          let mut row = match rows.next()? {
+            Ok(Ok(row)) => row,
+            Ok(Err(err)) => return Some(Ok(Err(err))),
+            Err(err) => return Some(Err(err)),
+          };
+          while let Some(Ok(Ok(next))) =
+            rows.next_if(|next| matches!(next, Ok(Ok(next)) if next.id == row.id))
+          {
+            row.translation = row.translation.or(next.translation);
+          }
+          Some(Ok(Ok(CorpusEntry {
+            text: row.text,
+            score: 1.0,
+            translation: row.translation,
+            id: Some(row.id),
+            line: Some(row.line),
+          })))
+        }))
+      }
+    }
+  }
+}
+
+/// Where corpus lines are read from.
+#[derive(Clone, Debug)]
+pub enum CorpusSource {
+  File(PathBuf),
+  /// Standard input, given on the command line as `-`.
+  Stdin,
+}
+
+impl CorpusSource {
+  pub fn from_path(path: PathBuf) -> Self {
+    if path.as_os_str() == "-" {
+      CorpusSource::Stdin
+    } else {
+      CorpusSource::File(path)
+    }
+  }
+
+  pub fn path(&self) -> Option<&Path> {
+    match self {
+      CorpusSource::File(path) => Some(path),
+      CorpusSource::Stdin => None,
+    }
+  }
+
+  /// The size of the source in bytes, as stored (i.e. compressed), if it
+  /// isn't a stream.
+  pub fn len(&self) -> Result<Option<u64>> {
+    Ok(match self {
+      CorpusSource::File(path) => Some(fs::metadata(path)?.len()),
+      CorpusSource::Stdin => None,
+    })
+  }
+
+  /// Opens the source for line-by-line reading, decompressing it on the fly
+  /// if needed. `progress` advances by the stored bytes read, so it tracks
+  /// compressed files accurately too.
+  pub fn open(&self, progress: &ProgressBar) -> Result<Box<dyn BufRead>> {
+    let reader: Box<dyn BufRead> = match self {
+      CorpusSource::File(path) => Box::new(BufReader::new(progress.wrap_read(File::open(path)?))),
+      CorpusSource::Stdin => Box::new(BufReader::new(progress.wrap_read(io::stdin().lock()))),
+    };
+    decompress(reader)
+  }
+}
+
+/// A corpus source, the format of its lines, and the label its snippets
+/// carry.
+#[derive(Clone, Debug)]
+pub struct CorpusInput {
+  pub source: CorpusSource,
+  pub format: CorpusFormat,
+  pub label: String,
+}
+
+impl CorpusInput {
+  /// Parses a command-line corpus argument. A format prefix such as `text:`
+  /// or `tatoeba:` overrides `default_format` for that path, e.g.
+  /// `text:../novel.txt`. The label defaults to the file name.
+  pub fn parse(arg: &str, default_format: CorpusFormat) -> Self {
+    let (format, path) = match arg.split_once(':') {
+      Some(("jsonl", path)) => (CorpusFormat::Jsonl, path),
+      Some(("text", path)) => (CorpusFormat::Text, path),
+      Some(("paragraphs", path)) => (CorpusFormat::Paragraphs, path),
+      Some(("wiki", path)) => (CorpusFormat::Wiki, path),
+      Some(("tatoeba", path)) => (CorpusFormat::Tatoeba, path),
+      _ => (default_format, arg),
+    };
+    let source = CorpusSource::from_path(PathBuf::from(path));
+    let label = match source.path().and_then(Path::file_stem) {
+      Some(stem) => stem.to_string_lossy().into_owned(),
+      None => "stdin".to_string(),
+    };
+    CorpusInput {
+      source,
+      format,
+      label,
+    }
+  }
+}
+
+impl CorpusInput {
+  /// Identifies the input by format, path, size, and modification time, so
+  /// an index built from it can tell when it has changed. Stdin can't be
+  /// told apart from one run to the next.
+  pub fn fingerprint(&self) -> Result<String> {
+    let name = format!("{:?}:{}", self.format, self.source);
+    let CorpusSource::File(path) = &self.source else {
+      return Ok(name);
+    };
+    let metadata = fs::metadata(path).with_context(|| format!("reading {}", path.display()))?;
+    let modified = metadata
+      .modified()?
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_nanos();
+    Ok(format!("{name} {} {modified}", metadata.len()))
+  }
+
+  pub fn entries(&self, progress: &ProgressBar) -> Result<CorpusEntries> {
+    Ok(self.format.read(self.source.open(progress)?))
+  }
+}
+
+/// The entries of `input`, read lazily, with the input named in every
+/// error. An entry that can't be parsed is yielded as an inner error and
+/// reading goes on; after an error reading the input itself, reading stops.
+pub fn input_entries(input: &CorpusInput, progress: &ProgressBar) -> CorpusEntries {
+  let source = input.source.clone();
+  let entries = match input.entries(progress) {
+    Ok(entries) => entries,
+    Err(err) => Box::new(iter::once(Err(err))),
+  };
+  let mut failed = false;
+  Box::new(entries.map_while(move |entry| {
+    if failed {
+      return None;
+    }
+    failed = entry.is_err();
+    let context = || format!("reading {source}");
+    Some(match entry {
+      Ok(entry) => Ok(entry.with_context(context)),
+      Err(err) => Err(err).with_context(context),
+    })
+  }))
+}
+
+/// Every entry of the corpus files at `paths`, in order, read lazily and
+/// decompressed as needed. Paths are JSON lines unless they carry a format
+/// prefix, as in [`CorpusInput::parse`].
+///
+/// A line that can't be parsed is yielded as an error and reading goes on
+/// with the next one. A file that can't be opened or read is yielded as an
+/// error too, and the rest of that file is skipped. See [`input_entries`].
+pub fn corpus_entries(paths: &[PathBuf]) -> impl Iterator<Item = Result<CorpusEntry>> {
+  let inputs = paths
+    .iter()
+    .map(|path| CorpusInput::parse(&path.to_string_lossy(), CorpusFormat::Jsonl))
+    .collect::<Vec<_>>();
+  inputs.into_iter().flat_map(|input| {
+    input_entries(&input, &ProgressBar::hidden()).map(|entry| entry.and_then(|entry| entry))
+  })
+}
+
+impl fmt::Display for CorpusSource {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CorpusSource::File(path) => write!(f, "{}", path.display()),
+      CorpusSource::Stdin => write!(f, "<stdin>"),
+    }
+  }
+}
+
+/// Most of a sentence that may be markup before it's rejected.
+pub const MARKUP_THRESHOLD: f64 = 0.2;
+/// How many sentences a reading passage has.
+pub const PASSAGE_SENTENCES: RangeInclusive<usize> = 3..=5;
+
+/// Drops zero-width characters and collapses runs of whitespace.
+pub fn clean_text(text: &str) -> String {
+  static RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+  let text = text.replace(
+    ['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}'],
+    "",
+  );
+  RE.replace_all(text.trim(), " ").into_owned()
+}
+
+/// Stands in for each piece of markup [`mark_markup`] removes, so a document
+/// can be split into sentences first and each judged by its markup after.
+pub const MARKUP_MARK: char = '\u{FFFC}';
+
+/// Replaces each leftover HTML tag, entity, and markdown marker in `text`
+/// with one [`MARKUP_MARK`]. Done before sentence splitting, so tags like
+/// `<br/>` can't end up as sentences of their own and entities can't be
+/// split apart.
+pub fn mark_markup(text: &str) -> String {
+  static RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)<[^<>]*>|&(?:[a-zA-Z]+|#\d+);|\*\*|__|`+|^#+").unwrap());
+  let mark = MARKUP_MARK.to_string();
+  RE.replace_all(text, mark.as_str()).into_owned()
+}
+
+/// Removes the marks [`mark_markup`] left in a sentence, or returns `None` if
+/// marks make up more than `MARKUP_THRESHOLD` of it. Marks at either end were
+/// tags between sentences, so they don't count, and a sentence of nothing but
+/// markup is rejected.
+pub fn strip_markup(sentence: &str) -> Option<String> {
+  let sentence = sentence.trim_matches(|c: char| c == MARKUP_MARK || c.is_whitespace());
+  let total = sentence.chars().filter(|c| !c.is_whitespace()).count();
+  let markup = sentence.matches(MARKUP_MARK).count();
+  if total == 0 || markup as f64 > total as f64 * MARKUP_THRESHOLD {
+    return None;
+  }
+  Some(clean_text(&sentence.replace(MARKUP_MARK, "")))
+}
+
+/// Fraction of a sentence's letters and digits that are Latin letters or
+/// Arabic digits, in either ASCII or full-width form. Punctuation and
+/// whitespace are not counted.
+pub fn latin_ratio(sentence: &str) -> f64 {
+  let is_latin =
+    |c: char| c.is_ascii_alphanumeric() || matches!(c, '０'..='９' | 'Ａ'..='Ｚ' | 'ａ'..='ｚ');
+  let (latin, total) = sentence
+    .chars()
+    .filter(|c| c.is_alphanumeric())
+    .fold((0, 0), |(latin, total), c| {
+      (latin + is_latin(c) as usize, total + 1)
+    });
+  if total == 0 {
+    0.
+  } else {
+    latin as f64 / total as f64
+  }
+}
+
+/// Whether a sentence could plausibly teach anything. Rejects sentences that
+/// are mostly ASCII, contain a URL, or have no letters at all (dates, phone
+/// numbers, runs of punctuation).
+pub fn sentence_is_meaningful(s: &str) -> bool {
+  let graphemes = s
+    .graphemes(true)
+    .filter(|g| !g.chars().all(char::is_whitespace))
+    .collect::<Vec<_>>();
+  let ascii = graphemes.iter().filter(|g| g.is_ascii()).count();
+  if ascii * 2 > graphemes.len() {
+    return false;
+  }
+  if s.contains("http://") || s.contains("https://") || s.contains("www.") {
+    return false;
+  }
+  // CJK ideographs count as alphabetic, so this only fails for sentences
+  // made entirely of digits, punctuation, and spaces.
+  s.chars().any(char::is_alphabetic)
+}
+
+/// The context of sentence `i` of a document: up to `n` sentences on each
+/// side, stopping early at the edge of the document or at a rejected
+/// sentence (`None`). Both are empty if `i` is past the end.
+pub fn context<T>(sentences: &[Option<T>], i: usize, n: usize) -> (&[Option<T>], &[Option<T>]) {
+  let before = sentences.get(..i).unwrap_or_default();
+  let after = sentences.get(i + 1..).unwrap_or_default();
+  let before_len = before
+    .iter()
+    .rev()
+    .take(n)
+    .take_while(|s| s.is_some())
+    .count();
+  let after_len = after.iter().take(n).take_while(|s| s.is_some()).count();
+  (&before[before.len() - before_len..], &after[..after_len])
+}
+
+/// Splits a document's sentences into passages of adjacent accepted
+/// sentences. Rejected sentences (`None`) end a passage, and long runs are
+/// divided evenly so every passage has `PASSAGE_SENTENCES` sentences.
+pub fn passages<T>(sentences: &[Option<T>]) -> impl Iterator<Item = &[Option<T>]> {
+  sentences
+    .split(Option::is_none)
+    .flat_map(|run| {
+      let count = run.len().div_ceil(*PASSAGE_SENTENCES.end()).max(1);
+      run.chunks(run.len().div_ceil(count).max(1))
+    })
+    .filter(|passage| PASSAGE_SENTENCES.contains(&passage.len()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cloze::{sentence_delimiters, split_sentences};
+  use flate2::{write::GzEncoder, Compression as GzLevel};
+  use std::io::Write;
+
+  fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("card-builder-corpus-{}-{name}", std::process::id()))
+  }
+
+  #[test]
+  fn open_decompresses_by_content() {
+    let text = "{\"text\": \"我喜欢你\"}\n{\"text\": \"学习中文\"}\n";
+    // Named .jsonl to check the magic bytes decide, not the extension.
+    let gzipped = temp_path("gzipped.jsonl");
+    let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), GzLevel::default());
+    encoder.write_all(text.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+    let zstded = temp_path("zstded.jsonl");
+    fs::write(&zstded, zstd::encode_all(text.as_bytes(), 0).unwrap()).unwrap();
+
+    let read = |path: &Path| {
+      let compression = detect_compression(path).unwrap();
+      let lines = CorpusSource::File(path.to_owned())
+        .open(&ProgressBar::hidden())
+        .unwrap()
+        .lines()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+      (compression, lines)
+    };
+    let (gzip, gzip_lines) = read(&gzipped);
+    let (zstd, zstd_lines) = read(&zstded);
+    let _ = fs::remove_file(&gzipped);
+    let _ = fs::remove_file(&zstded);
+
+    assert_eq!((gzip, zstd), (Compression::Gzip, Compression::Zstd));
+    let expected = text.lines().map(String::from).collect::<Vec<_>>();
+    assert_eq!(gzip_lines, expected);
+    assert_eq!(zstd_lines, expected);
+  }
+
+  /// `text` split into sentences the way `build_corpus` splits it.
+  fn clean_sentences(text: &str) -> Vec<Option<String>> {
+    let delimiters = sentence_delimiters("。！？").unwrap();
+    let text = clean_text(&mark_markup(text));
+    split_sentences(&text, &delimiters)
+      .into_iter()
+      .map(strip_markup)
+      .collect()
+  }
+
+  #[test]
+  fn strip_markup_inside_a_sentence() {
+    assert_eq!(
+      clean_sentences("我喜欢<br/>学习中文。你呢？"),
+      [Some("我喜欢学习中文".into()), Some("你呢".into())]
+    );
+    assert_eq!(
+      clean_sentences("<p>今天很好。</p><p>**明天**也好。</p>"),
+      [Some("今天很好".into()), Some("明天也好".into()), None]
+    );
+  }
+
+  #[test]
+  fn strip_markup_rejects_markup_sentences() {
+    assert_eq!(
+      clean_sentences("<div><br/></div>。我喜欢你。"),
+      [None, Some("我喜欢你".into())]
+    );
+    assert_eq!(clean_sentences("我<b>很</b><i>好</i>。"), [None]);
+  }
+
+  #[test]
+  fn context_stops_at_edges_and_rejected_sentences() {
+    let sentences = [Some("一"), Some("二"), None, Some("三"), Some("四")];
+    assert_eq!(context(&sentences, 1, 2), (&sentences[..1], &[][..]));
+    assert_eq!(context(&sentences, 3, 2), (&[][..], &sentences[4..]));
+  }
+
+  #[test]
+  fn context_of_empty_and_single_sentence_entries() {
+    assert!(clean_sentences("").is_empty());
+    assert_eq!(context::<&str>(&[], 0, 1), (&[][..], &[][..]));
+    let single = clean_sentences("我喜欢你。");
+    assert_eq!(context(&single, 0, 1), (&[][..], &[][..]));
+  }
+
+  #[test]
+  fn reads_any_buffered_reader() {
+    // As stdin would be read: a stream with no path to sniff or size.
+    let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+    encoder
+      .write_all("我喜欢你\n\n  学习中文 \n".as_bytes())
+      .unwrap();
+    let stream = io::Cursor::new(encoder.finish().unwrap());
+    let reader = decompress(Box::new(stream)).unwrap();
+    let entries = CorpusFormat::Text
+      .read(reader)
+      .map(|entry| {
+        let entry = entry.unwrap().unwrap();
+        (entry.text, entry.line)
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      entries,
+      [
+        ("我喜欢你".to_string(), Some(1)),
+        ("学习中文".to_string(), Some(3))
+      ]
+    );
+  }
+
+  #[test]
+  fn reads_blank_line_separated_paragraphs() {
+    let text = "\n我喜欢\n  你。\n\n\n学习中文\n \n你好";
+    let entries = CorpusFormat::Paragraphs
+      .read(Box::new(io::Cursor::new(text)))
+      .map(|entry| {
+        let entry = entry.unwrap().unwrap();
+        (entry.text, entry.line)
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      entries,
+      [
+        ("我喜欢你。".to_string(), Some(2)),
+        ("学习中文".to_string(), Some(6)),
+        ("你好".to_string(), Some(8))
+      ]
+    );
+  }
+
+  #[test]
+  fn corpus_entries_reads_gzip_and_skips_malformed_lines() {
+    let plain = temp_path("plain.jsonl");
+    fs::write(
+      &plain,
+      "{\"text\": \"我喜欢你\", \"score\": 0.5}\nnot json\n{\"text\": \"你好\", \"id\": 7}\n",
+    )
+    .unwrap();
+    let gzipped = temp_path("gzipped.jsonl.gz");
+    let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), GzLevel::default());
+    encoder
+      .write_all("{\"text\": \"学习中文\"}\n".as_bytes())
+      .unwrap();
+    encoder.finish().unwrap();
+
+    let entries = corpus_entries(&[plain.clone(), gzipped.clone()]).collect::<Vec<_>>();
+    let _ = fs::remove_file(&plain);
+    let _ = fs::remove_file(&gzipped);
+
+    assert_eq!(entries.len(), 4);
+    let first = entries[0].as_ref().unwrap();
+    assert_eq!(
+      (first.text.as_str(), first.score, first.line),
+      ("我喜欢你", 0.5, Some(1))
+    );
+    let Err(err) = &entries[1] else {
+      panic!("expected the malformed line to fail")
+    };
+    assert_eq!(err.to_string(), format!("reading {}", plain.display()));
+    assert_eq!(
+      std::error::Error::source(err).unwrap().to_string(),
+      "line 2"
+    );
+    let third = entries[2].as_ref().unwrap();
+    assert_eq!((third.id.as_deref(), third.line), (Some("7"), Some(3)));
+    let fourth = entries[3].as_ref().unwrap();
+    assert_eq!((fourth.text.as_str(), fourth.score), ("学习中文", 1.0));
+  }
+
+  #[test]
+  fn corpus_entries_reports_missing_files_and_continues() {
+    let plain = temp_path("after-missing.txt");
+    fs::write(&plain, "你好\n\n再见\n").unwrap();
+    let paths = [
+      temp_path("missing.jsonl"),
+      PathBuf::from(format!("text:{}", plain.display())),
+    ];
+
+    let entries = corpus_entries(&paths).collect::<Vec<_>>();
+    let _ = fs::remove_file(&plain);
+
+    assert_eq!(entries.len(), 3);
+    assert!(entries[0].is_err());
+    let texts = entries[1..]
+      .iter()
+      .map(|entry| entry.as_ref().unwrap().text.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(texts, ["你好", "再见"]);
+  }
+}
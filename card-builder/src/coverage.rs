@@ -0,0 +1,87 @@
+use crate::{
+  error::Result,
+  vocab::{PhraseIdx, Vocab, VocabLevel},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeMap,
+  fs::File,
+  io::{BufReader, BufWriter},
+  path::Path,
+};
+
+/// How many snippets the corpus produced for each phrase.
+#[derive(Serialize, Deserialize)]
+pub struct CoverageReport {
+  /// Snippet counts keyed by level, then by phrase form. Phrases with no
+  /// snippets are included with a count of 0.
+  pub levels: BTreeMap<usize, BTreeMap<String, usize>>,
+  pub stats: CoverageStats,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CoverageStats {
+  pub total_phrases: usize,
+  pub covered: usize,
+  pub median_snippets: f64,
+}
+
+impl CoverageStats {
+  /// Percentage of phrases with at least one snippet.
+  pub fn covered_pct(&self) -> f64 {
+    if self.total_phrases == 0 {
+      100.
+    } else {
+      self.covered as f64 / self.total_phrases as f64 * 100.
+    }
+  }
+}
+
+impl CoverageReport {
+  /// Builds a report over every phrase at one of `included` that the learner
+  /// doesn't already know.
+  pub fn new(
+    vocab: &Vocab,
+    included: &[VocabLevel],
+    snippet_count: impl Fn(PhraseIdx) -> usize,
+  ) -> Self {
+    let mut levels: BTreeMap<usize, BTreeMap<String, usize>> = BTreeMap::new();
+    for (idx, phrase) in vocab.phrases.iter_enumerated() {
+      if vocab.is_known(idx) || !included.contains(&phrase.level) {
+        continue;
+      }
+      *levels
+        .entry(phrase.level.0)
+        .or_default()
+        .entry(phrase.form.clone())
+        .or_default() += snippet_count(idx);
+    }
+
+    let mut counts = levels
+      .values()
+      .flat_map(|phrases| phrases.values().copied())
+      .collect::<Vec<_>>();
+    counts.sort_unstable();
+    let median_snippets = match counts.len() {
+      0 => 0.,
+      n if n % 2 == 0 => (counts[n / 2 - 1] + counts[n / 2]) as f64 / 2.,
+      n => counts[n / 2] as f64,
+    };
+    let stats = CoverageStats {
+      total_phrases: counts.len(),
+      covered: counts.iter().filter(|n| **n > 0).count(),
+      median_snippets,
+    };
+
+    CoverageReport { levels, stats }
+  }
+
+  pub fn load(path: &Path) -> Result<Self> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+  }
+
+  pub fn write(&self, path: &Path) -> Result<()> {
+    serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+    Ok(())
+  }
+}
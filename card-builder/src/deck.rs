@@ -0,0 +1,799 @@
+use crate::{
+  chengyu::is_chengyu,
+  cloze::{escape_text, fill_hole, make_cloze, phrase_location, split_sentences},
+  error::{Context, Error, Result},
+  grammar::{detect_grammar_patterns, GrammarTag},
+  pinyin::pinyin_to_colored_html,
+  vocab::{Vocab, VocabLevel, VocabPhrase},
+};
+use ahash::HashMap;
+use clap::ValueEnum;
+use genanki_rs::{Field, Model, ModelType, Note, Template};
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path, sync::LazyLock};
+
+/// A corpus sentence stored for a phrase, with its context.
+#[derive(Deserialize, Serialize)]
+pub struct Snippet {
+  pub prefix: Option<String>,
+  pub sentence: String,
+  pub suffix: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub translation: Option<String>,
+  /// Label of the corpus file the sentence came from.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<String>,
+  /// For reading-passage cards, the passage's sentences; `sentence` then
+  /// holds them joined.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub passage: Vec<String>,
+  /// Byte offsets where each jieba word of `sentence` starts, so a cloze can
+  /// be placed on a whole word rather than any matching substring.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub tokens: Vec<usize>,
+  /// Where the sentence was read from, so bad cards can be traced back to
+  /// their corpus entry.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_file: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_line: Option<u64>,
+  /// The corpus entry's own id, if it has one.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source_id: Option<String>,
+  /// The corpus entry's quality score.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub score: Option<f64>,
+  /// The share of the sentence's words outside the word list; see
+  /// [`Vocab::unknown_ratio`](crate::vocab::Vocab::unknown_ratio).
+  #[serde(default)]
+  pub unknown_ratio: f32,
+}
+
+impl Snippet {
+  /// Where the snippet came from, e.g. `corpus/part-0.jsonl:12 #doc-3`, for
+  /// a card's hidden Source field.
+  pub fn provenance(&self) -> String {
+    let mut provenance = self.source_file.clone().unwrap_or_default();
+    if let Some(line) = self.source_line {
+      provenance.push_str(&format!(":{line}"));
+    }
+    if let Some(id) = &self.source_id {
+      if !provenance.is_empty() {
+        provenance.push(' ');
+      }
+      provenance.push_str(&format!("#{id}"));
+    }
+    provenance
+  }
+}
+
+// Anki identifies note types by ID. Importing a deck whose note type ID is
+// already in the collection reuses that note type, so changed CSS or
+// templates may not show up, and notes whose fields no longer match get
+// mapped onto a copy. `--model-version` shifts every ID by
+// `MODEL_VERSION_STRIDE` per version to import as fresh note types instead.
+//
+// The IDs themselves change whenever the fields do, so a default build never
+// reuses a note type with other fields. 1122338855-1122338857 were used
+// while fields were still being added, and are retired.
+const MODEL_ID: i64 = 1122348855;
+const BASIC_MODEL_ID: i64 = 1122348856;
+const READING_MODEL_ID: i64 = 1122348857;
+const MODEL_VERSION_STRIDE: i64 = 100;
+
+/// Styles shared by every note type, unless the config overrides them.
+pub const CSS: &str = r#"
+.card {
+  font-family: arial;
+  font-size: 24px;
+  text-align: center;
+  color: black;
+  background-color: white;
+}
+
+.context {
+  font-size: 80%;
+  padding: 0.5rem 0;
+}
+
+.cloze {
+  font-weight: bold;
+  color: blue;
+}
+
+.nightMode .cloze {color: lightblue;}
+
+.pinyin {
+  font-size: 80%;
+}
+
+.tone-1 { color: #e74c3c; }
+.tone-2 { color: #27ae60; }
+.tone-3 { color: #2980b9; }
+.tone-4 { color: #8e44ad; }
+.tone-0 { color: #7f8c8d; }
+
+.word {
+  font-weight: bold;
+}
+
+.definition {
+  font-size: 80%;
+}
+
+.translation {
+  font-size: 70%;
+  font-style: italic;
+}
+
+.frequency {
+  font-size: 60%;
+  color: #7f8c8d;
+}
+
+.passage {
+  max-width: 40em;
+  margin: 0 auto;
+  text-align: left;
+  line-height: 1.8;
+}"#;
+
+// New fields go at the end: Anki matches note fields by position, so
+// reordering them would scramble notes imported earlier.
+const CLOZE_FIELDS: [&str; 10] = [
+  "Sentence",
+  "Prefix",
+  "Suffix",
+  "Pinyin",
+  "Audio",
+  "Translation",
+  "Source",
+  "Frequency",
+  "Word",
+  "Definition",
+];
+const BASIC_FIELDS: [&str; 10] = [
+  "Blanked",
+  "Sentence",
+  "Word",
+  "Prefix",
+  "Suffix",
+  "Pinyin",
+  "Audio",
+  "Translation",
+  "Source",
+  "Frequency",
+];
+const READING_FIELDS: [&str; 4] = ["Passage", "Translation", "Audio", "Source"];
+/// Fields Anki fills in itself, which any template may use.
+const SPECIAL_FIELDS: [&str; 7] = [
+  "FrontSide",
+  "Tags",
+  "Type",
+  "Deck",
+  "Subdeck",
+  "Card",
+  "CardFlag",
+];
+const CLOZE_FRONT: &str =
+  "<div class=context>{{Prefix}}</div> {{cloze:Sentence}} <div class=context>{{Suffix}}</div>";
+/// The front plus answer-only details, each shown only when it's filled in.
+const CLOZE_BACK: &str =
+  "<div class=context>{{Prefix}}</div> {{cloze:Sentence}} <div class=context>{{Suffix}}</div>\
+{{#Word}}<hr id=answer><div class=word>{{Word}}</div>{{/Word}}\
+{{#Pinyin}}<div class=pinyin>{{Pinyin}}</div>{{/Pinyin}}\
+{{#Definition}}<div class=definition>{{Definition}}</div>{{/Definition}}\
+{{#Translation}}<div class=translation>{{Translation}}</div>{{/Translation}}\
+{{#Frequency}}<div class=frequency>{{Frequency}}</div>{{/Frequency}}\
+{{Audio}}";
+const BASIC_FRONT: &str =
+  "<div class=context>{{Prefix}}</div> {{Blanked}} <div class=context>{{Suffix}}</div>";
+const BASIC_BACK: &str =
+  "<div class=context>{{Prefix}}</div> {{Sentence}} <div class=context>{{Suffix}}</div>\
+<hr id=answer><div class=cloze>{{Word}}</div><div class=pinyin>{{Pinyin}}</div>\
+<div class=translation>{{Translation}}</div><div class=frequency>{{Frequency}}</div>{{Audio}}";
+const READING_FRONT: &str = "<div class=passage>{{cloze:Passage}}</div>";
+const READING_BACK: &str =
+  "<div class=passage>{{cloze:Passage}}</div><div class=translation>{{Translation}}</div>{{Audio}}";
+
+/// The note types cards are built with, as far as their templates go.
+#[derive(Clone, Copy)]
+pub enum NoteKind {
+  Cloze,
+  Basic,
+  Reading,
+}
+
+impl NoteKind {
+  fn fields(self) -> &'static [&'static str] {
+    match self {
+      NoteKind::Cloze => &CLOZE_FIELDS,
+      NoteKind::Basic => &BASIC_FIELDS,
+      NoteKind::Reading => &READING_FIELDS,
+    }
+  }
+
+  /// What the front must show for its cards to have anything to recall.
+  fn prompt(self) -> &'static str {
+    match self {
+      NoteKind::Cloze => "{{cloze:Sentence}}",
+      NoteKind::Basic => "{{Blanked}}",
+      NoteKind::Reading => "{{cloze:Passage}}",
+    }
+  }
+}
+
+/// The question and answer templates of a note type.
+pub struct CardTemplate {
+  pub front: String,
+  pub back: String,
+}
+
+impl CardTemplate {
+  /// The built-in templates of `kind`.
+  pub fn builtin(kind: NoteKind) -> Self {
+    let (front, back) = match kind {
+      NoteKind::Cloze => (CLOZE_FRONT, CLOZE_BACK),
+      NoteKind::Basic => (BASIC_FRONT, BASIC_BACK),
+      NoteKind::Reading => (READING_FRONT, READING_BACK),
+    };
+    CardTemplate {
+      front: front.to_string(),
+      back: back.to_string(),
+    }
+  }
+
+  /// Reads `front.html` and `back.html` from `dir`, checked as in
+  /// [`CardTemplate::check`].
+  pub fn load(kind: NoteKind, dir: &Path) -> Result<Self> {
+    let read = |name: &str| {
+      let path = dir.join(name);
+      fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))
+    };
+    let template = CardTemplate {
+      front: read("front.html")?,
+      back: read("back.html")?,
+    };
+    template.check(kind, &dir.join("front.html"), &dir.join("back.html"))?;
+    Ok(template)
+  }
+
+  /// Checks that the front shows the note type's prompt, such as
+  /// `{{cloze:Sentence}}`, and that both sides only use its fields. Errors
+  /// name `front_path` or `back_path`, wherever each side came from.
+  pub fn check(&self, kind: NoteKind, front_path: &Path, back_path: &Path) -> Result<()> {
+    if !self.front.contains(kind.prompt()) {
+      return Err(Error::BadTemplate {
+        path: front_path.to_path_buf(),
+        reason: format!(
+          "has no {}, so its cards would have nothing to recall",
+          kind.prompt()
+        ),
+      });
+    }
+    for (path, html) in [(front_path, &self.front), (back_path, &self.back)] {
+      if let Some(field) = field_references(html)
+        .find(|field| !kind.fields().contains(field) && !SPECIAL_FIELDS.contains(field))
+      {
+        return Err(Error::BadTemplate {
+          path: path.to_path_buf(),
+          reason: format!(
+            "uses {{{{{field}}}}}, which isn't a field; the fields are {}",
+            kind.fields().join(", ")
+          ),
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The templates of every note type.
+pub struct CardTemplates {
+  pub cloze: CardTemplate,
+  pub basic: CardTemplate,
+  pub reading: CardTemplate,
+}
+
+impl Default for CardTemplates {
+  fn default() -> Self {
+    CardTemplates {
+      cloze: CardTemplate::builtin(NoteKind::Cloze),
+      basic: CardTemplate::builtin(NoteKind::Basic),
+      reading: CardTemplate::builtin(NoteKind::Reading),
+    }
+  }
+}
+
+/// The fields `html` refers to, without Anki's section markers and filters,
+/// so `{{#Pinyin}}` and `{{cloze:Sentence}}` give `Pinyin` and `Sentence`.
+fn field_references(html: &str) -> impl Iterator<Item = &str> {
+  static REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{([^{}]+)\}\}").unwrap());
+  REFERENCE.captures_iter(html).map(|captures| {
+    let reference = captures.get(1).unwrap().as_str().trim();
+    let reference = reference.trim_start_matches(['#', '^', '/']);
+    reference.rsplit(':').next().unwrap().trim()
+  })
+}
+
+fn model(
+  id: i64,
+  name: &str,
+  kind: NoteKind,
+  template: &CardTemplate,
+  css: &str,
+  model_type: ModelType,
+) -> Model {
+  let card_name = match kind {
+    NoteKind::Cloze => "Cloze",
+    NoteKind::Basic => "Production",
+    NoteKind::Reading => "Reading",
+  };
+  Model::new_with_options(
+    id,
+    name,
+    kind.fields().iter().copied().map(Field::new).collect(),
+    vec![Template::new(card_name)
+      .qfmt(&template.front)
+      .afmt(&template.back)],
+    Some(css),
+    Some(model_type),
+    None,
+    None,
+    None,
+  )
+}
+
+/// The note types cards are built with.
+pub struct CardModels {
+  pub cloze: Model,
+  pub basic: Model,
+  pub reading: Model,
+}
+
+impl CardModels {
+  /// Note types styled with `css`. Bumping `version` gives them new ids, so
+  /// Anki imports them as new note types instead of merging templates.
+  ///
+  /// `cloze_id` replaces the cloze note type's id. Anki recognizes note
+  /// types by id alone, so cards from a changed `cloze` template should get
+  /// their own.
+  pub fn new(css: &str, version: i64, templates: &CardTemplates, cloze_id: Option<i64>) -> Self {
+    let offset = version * MODEL_VERSION_STRIDE;
+    CardModels {
+      cloze: model(
+        cloze_id.unwrap_or(MODEL_ID + offset),
+        "Cloze (zhlearn)",
+        NoteKind::Cloze,
+        &templates.cloze,
+        css,
+        ModelType::Cloze,
+      ),
+      basic: model(
+        BASIC_MODEL_ID + offset,
+        "Basic (zhlearn)",
+        NoteKind::Basic,
+        &templates.basic,
+        css,
+        ModelType::FrontBack,
+      ),
+      reading: model(
+        READING_MODEL_ID + offset,
+        "Reading (zhlearn)",
+        NoteKind::Reading,
+        &templates.reading,
+        css,
+        ModelType::Cloze,
+      ),
+    }
+  }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CardType {
+  /// Anki cloze note with the target phrase as the deletion.
+  Cloze,
+  /// Front/back note: blanked sentence on the front, full sentence and word on the back.
+  Basic,
+}
+
+/// How [`build_card`] and [`build_passage_card`] lay out notes.
+pub struct CardOptions {
+  pub card_type: CardType,
+  pub models: CardModels,
+  /// Splits word-list example sentences, which may hold several.
+  pub sentence_delimiters: Regex,
+}
+
+/// Where a card's sentence came from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SnippetOrigin {
+  Corpus,
+  /// The word list's own example sentence.
+  Fallback,
+}
+
+impl SnippetOrigin {
+  fn tag(self) -> &'static str {
+    match self {
+      SnippetOrigin::Corpus => "zhlearn::corpus",
+      SnippetOrigin::Fallback => "zhlearn::fallback",
+    }
+  }
+}
+
+/// A GUID that depends only on the kind of note, the clozed phrase, and its
+/// sentence, so rebuilding a deck (even with a different corpus around the
+/// sentence) updates existing notes in Anki instead of duplicating them.
+/// Notes of different types never share a GUID, since Anki can't update a
+/// note into another type.
+pub fn note_guid(note_type: &str, phrase: &str, sentence: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(note_type);
+  hasher.update([0]);
+  hasher.update(phrase);
+  hasher.update([0]);
+  hasher.update(sentence);
+  let mut guid = format!("{:x}", hasher.finalize());
+  guid.truncate(10);
+  guid
+}
+
+fn grammar_tag(tag: GrammarTag) -> String {
+  format!("grammar::{}", tag.name())
+}
+
+/// The sentence of `snippet` to cloze `phrase` in, and where in it the
+/// phrase starts, or `None` if no sentence of the snippet contains it.
+fn cloze_target<'a>(
+  snippet: &'a Snippet,
+  phrase: &str,
+  delimiters: &Regex,
+) -> Option<(&'a str, usize)> {
+  // Corpus sentences are already split, and their tokens are offsets into
+  // the whole sentence; word-list examples may hold several sentences.
+  let sentences = match snippet.tokens.is_empty() {
+    true => split_sentences(&snippet.sentence, delimiters),
+    false => vec![snippet.sentence.as_str()],
+  };
+  sentences
+    .into_iter()
+    .find_map(|s| Some((s, phrase_location(s, phrase, &snippet.tokens)?)))
+}
+
+/// The sentence fields of a card for the `phrase` at `loc` in `sentence`:
+/// the deletion of a cloze card, or the blanked front and the revealed back
+/// of a basic card.
+fn sentence_fields(
+  sentence: &str,
+  phrase: &str,
+  loc: usize,
+  card_type: CardType,
+) -> (String, Option<String>) {
+  match card_type {
+    CardType::Cloze => (make_cloze(sentence, phrase, loc), None),
+    CardType::Basic => {
+      let word = html_escape::encode_safe(phrase);
+      let blanked = fill_hole(sentence, phrase, loc, "<span class=cloze>[...]</span>");
+      let full = fill_hole(
+        sentence,
+        phrase,
+        loc,
+        &format!("<span class=cloze>{word}</span>"),
+      );
+      (blanked, Some(full))
+    }
+  }
+}
+
+/// The field a card for `phrase` in `snippet` hides the phrase in: the
+/// deletion of a cloze card or the front of a basic card. `None` if no
+/// sentence of the snippet contains the phrase.
+pub fn cloze_field(snippet: &Snippet, phrase: &str, opts: &CardOptions) -> Option<String> {
+  let (sentence, loc) = cloze_target(snippet, phrase, &opts.sentence_delimiters)?;
+  Some(sentence_fields(sentence, phrase, loc, opts.card_type).0)
+}
+
+/// The passage of `snippet` with every unknown phrase of `level` clozed,
+/// numbered by phrase so repeats of a word share a deletion. `None` if no
+/// such phrase is found.
+pub fn passage_field(snippet: &Snippet, vocab: &Vocab, level: VocabLevel) -> Option<String> {
+  let mut cloze_numbers = HashMap::default();
+  let passage = passage_sentences(snippet)
+    .iter()
+    .map(|sentence| {
+      let mut html = String::new();
+      let mut end = 0;
+      for (idx, range) in vocab.find_phrases(sentence) {
+        if vocab.phrases.value(idx).level != level || vocab.is_known(idx) {
+          continue;
+        }
+        let next = cloze_numbers.len() + 1;
+        let n = *cloze_numbers.entry(idx).or_insert(next);
+        html.push_str(&escape_text(&sentence[end..range.start]));
+        html.push_str(&format!(
+          "{{{{c{n}::{}}}}}",
+          escape_text(&sentence[range.clone()])
+        ));
+        end = range.end;
+      }
+      html.push_str(&escape_text(&sentence[end..]));
+      format!("<p>{html}</p>")
+    })
+    .collect::<String>();
+  (!cloze_numbers.is_empty()).then_some(passage)
+}
+
+/// The sentences of a reading passage, or the snippet's own sentence if it
+/// has no passage.
+fn passage_sentences(snippet: &Snippet) -> &[String] {
+  match snippet.passage.is_empty() {
+    true => std::slice::from_ref(&snippet.sentence),
+    false => snippet.passage.as_slice(),
+  }
+}
+
+/// A selected card as plain text, for `--format json` and `--dry-run`.
+#[derive(Serialize)]
+pub struct Card {
+  /// The field that hides the phrase, as the note would hold it; see
+  /// [`cloze_field`] and [`passage_field`].
+  pub cloze: String,
+  pub sentence: String,
+  pub phrase: String,
+  pub level: usize,
+  pub prefix: Option<String>,
+  pub suffix: Option<String>,
+  pub has_context: bool,
+  pub pinyin: Option<String>,
+}
+
+impl Card {
+  pub fn new(snippet: &Snippet, phrase: &VocabPhrase, cloze: String) -> Self {
+    Card {
+      cloze,
+      sentence: snippet.sentence.clone(),
+      phrase: phrase.form.clone(),
+      level: phrase.level.0,
+      prefix: snippet.prefix.clone(),
+      suffix: snippet.suffix.clone(),
+      has_context: snippet.prefix.is_some() || snippet.suffix.is_some(),
+      pinyin: phrase.pinyin.clone(),
+    }
+  }
+}
+
+/// A note clozing `phrase` in the snippet's sentence, or `None` if the
+/// sentence doesn't contain it.
+pub fn build_card(
+  snippet: &Snippet,
+  phrase: &VocabPhrase,
+  origin: SnippetOrigin,
+  audio: &str,
+  frequency: &str,
+  opts: &CardOptions,
+) -> Option<Note> {
+  let mut tags = vec![
+    format!("{}::level-{}", phrase.source.slug(), phrase.level.0),
+    origin.tag().to_string(),
+  ];
+  if let Some(pos) = phrase.pos {
+    tags.push(format!("pos::{}", pos.name()));
+  }
+  if is_chengyu(&phrase.form) {
+    tags.push("zhlearn::chengyu".to_string());
+  }
+  if let Some(source) = &snippet.source {
+    // Anki splits tags on whitespace.
+    tags.push(format!(
+      "source::{}",
+      source.replace(char::is_whitespace, "_")
+    ));
+  }
+  let note_type = match opts.card_type {
+    CardType::Cloze => "cloze",
+    CardType::Basic => "basic",
+  };
+  let guid = note_guid(note_type, &phrase.form, &snippet.sentence);
+
+  // Tone colors would give the pronunciation away, so pinyin only appears
+  // on the answer side of the templates.
+  let pinyin = phrase
+    .pinyin
+    .as_deref()
+    .map(pinyin_to_colored_html)
+    .unwrap_or_default();
+  let definition = html_escape::encode_safe(phrase.definition.as_deref().unwrap_or(""));
+
+  let phrase = phrase.form.as_str();
+  let (sentence, loc) = cloze_target(snippet, phrase, &opts.sentence_delimiters)?;
+  tags.extend(
+    detect_grammar_patterns(sentence)
+      .into_iter()
+      .map(grammar_tag),
+  );
+  let prefix = html_escape::encode_safe(snippet.prefix.as_deref().unwrap_or(""));
+  let suffix = html_escape::encode_safe(snippet.suffix.as_deref().unwrap_or(""));
+  let translation = html_escape::encode_safe(snippet.translation.as_deref().unwrap_or(""));
+  let provenance = snippet.provenance();
+  let provenance = html_escape::encode_text(&provenance);
+  let word = html_escape::encode_safe(phrase);
+  let note = match sentence_fields(sentence, phrase, loc, opts.card_type) {
+    (cloze, None) => Note::new(
+      opts.models.cloze.clone(),
+      vec![
+        &cloze,
+        &prefix,
+        &suffix,
+        &pinyin,
+        audio,
+        &translation,
+        &provenance,
+        frequency,
+        &word,
+        &definition,
+      ],
+    ),
+    (blanked, Some(full)) => Note::new(
+      opts.models.basic.clone(),
+      vec![
+        &blanked,
+        &full,
+        &word,
+        &prefix,
+        &suffix,
+        &pinyin,
+        audio,
+        &translation,
+        &provenance,
+        frequency,
+      ],
+    ),
+  }
+  .unwrap();
+  Some(note.tags(tags).guid(guid))
+}
+
+/// A reading-passage note clozing the passage as [`passage_field`] does.
+/// Returns `None` if it has nothing to cloze, since Anki can't make cards
+/// from a cloze note without deletions.
+pub fn build_passage_card(
+  snippet: &Snippet,
+  vocab: &Vocab,
+  level: VocabLevel,
+  origin: SnippetOrigin,
+  audio: &str,
+  opts: &CardOptions,
+) -> Option<Note> {
+  let mut tags = vec![
+    format!("{}::level-{}", vocab.source.slug(), level.0),
+    origin.tag().to_string(),
+  ];
+  if let Some(source) = &snippet.source {
+    tags.push(format!(
+      "source::{}",
+      source.replace(char::is_whitespace, "_")
+    ));
+  }
+  let guid = note_guid(
+    "reading",
+    &vocab.source.level_name(level),
+    &snippet.sentence,
+  );
+
+  let grammar = passage_sentences(snippet)
+    .iter()
+    .flat_map(|sentence| detect_grammar_patterns(sentence))
+    .unique()
+    .collect::<Vec<_>>();
+  tags.extend(grammar.into_iter().map(grammar_tag));
+  let passage = passage_field(snippet, vocab, level)?;
+  let translation = html_escape::encode_safe(snippet.translation.as_deref().unwrap_or(""));
+  let provenance = snippet.provenance();
+
+  let note = Note::new(
+    opts.models.reading.clone(),
+    vec![
+      &passage,
+      &translation,
+      audio,
+      &html_escape::encode_text(&provenance),
+    ],
+  )
+  .unwrap()
+  .tags(tags)
+  .guid(guid);
+  Some(note)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn load(front: &str, back: &str) -> Result<CardTemplate> {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("front.html"), front).unwrap();
+    fs::write(dir.path().join("back.html"), back).unwrap();
+    CardTemplate::load(NoteKind::Cloze, dir.path())
+  }
+
+  #[test]
+  fn template_checks_field_references() {
+    let template = load(
+      "{{cloze:Sentence}}",
+      "{{FrontSide}}{{#Pinyin}}<p>{{text:Pinyin}}</p>{{/Pinyin}}",
+    )
+    .unwrap();
+    assert_eq!(template.front, "{{cloze:Sentence}}");
+
+    let err = load("{{Sentence}}", "").err().unwrap();
+    assert!(matches!(err, Error::BadTemplate { .. }), "{err:?}");
+    assert!(err.to_string().contains("no {{cloze:Sentence}}"), "{err}");
+
+    let err = load("{{cloze:Sentence}}", "{{Meaning}}").err().unwrap();
+    assert!(err.to_string().contains("{{Meaning}}"), "{err}");
+  }
+
+  #[test]
+  fn builtin_templates_pass_their_checks() {
+    for kind in [NoteKind::Cloze, NoteKind::Basic, NoteKind::Reading] {
+      let template = CardTemplate::builtin(kind);
+      template
+        .check(kind, Path::new("front"), Path::new("back"))
+        .unwrap();
+    }
+    // Each note type has its own fields.
+    let template = CardTemplate {
+      front: "{{cloze:Passage}}".to_string(),
+      back: "{{Pinyin}}".to_string(),
+    };
+    let err = template
+      .check(NoteKind::Reading, Path::new("front"), Path::new("back"))
+      .unwrap_err();
+    assert!(err.to_string().starts_with("back uses {{Pinyin}}"), "{err}");
+  }
+
+  #[test]
+  fn note_guid_depends_on_note_type() {
+    let guid = note_guid("cloze", "学习", "我喜欢学习中文");
+    assert_eq!(guid.len(), 10);
+    assert_eq!(guid, note_guid("cloze", "学习", "我喜欢学习中文"));
+    assert_ne!(guid, note_guid("basic", "学习", "我喜欢学习中文"));
+    assert_ne!(guid, note_guid("cloze", "中文", "我喜欢学习中文"));
+  }
+
+  #[test]
+  fn cloze_needs_the_phrase_in_one_sentence() {
+    let snippet = Snippet {
+      prefix: None,
+      sentence: "我们今天去学。校长在吗？".to_string(),
+      suffix: None,
+      translation: None,
+      source: None,
+      passage: Vec::new(),
+      tokens: Vec::new(),
+      source_file: None,
+      source_line: None,
+      source_id: None,
+      score: None,
+      unknown_ratio: 0.,
+    };
+    let delimiters = crate::cloze::sentence_delimiters("。！？").unwrap();
+    let (sentence, loc) = cloze_target(&snippet, "今天", &delimiters).unwrap();
+    assert_eq!(
+      sentence_fields(sentence, "今天", loc, CardType::Cloze),
+      ("我们{{c1::今天}}去学".to_string(), None)
+    );
+    assert_eq!(
+      sentence_fields(sentence, "今天", loc, CardType::Basic),
+      (
+        "我们<span class=cloze>[...]</span>去学".to_string(),
+        Some("我们<span class=cloze>今天</span>去学".to_string())
+      )
+    );
+    assert_eq!(cloze_target(&snippet, "学校", &delimiters), None);
+  }
+}
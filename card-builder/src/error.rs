@@ -0,0 +1,113 @@
+use crate::file_db::FileDbError;
+use std::{
+  fmt::Display,
+  io,
+  num::{ParseIntError, TryFromIntError},
+  path::PathBuf,
+};
+
+/// Everything that can go wrong in the library. The binary wraps these in
+/// `anyhow` errors; library callers can match on them.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  #[error(transparent)]
+  Json(#[from] serde_json::Error),
+  #[error(transparent)]
+  Csv(#[from] csv::Error),
+  #[error(transparent)]
+  Toml(#[from] toml::de::Error),
+  #[error(transparent)]
+  Bincode(#[from] bincode::Error),
+  #[error(transparent)]
+  Sqlite(#[from] rusqlite::Error),
+  #[error(transparent)]
+  Xml(#[from] quick_xml::Error),
+  #[error(transparent)]
+  Jieba(#[from] jieba_rs::Error),
+  #[error(transparent)]
+  Matcher(#[from] aho_corasick::BuildError),
+  #[error(transparent)]
+  Regex(#[from] regex::Error),
+  #[error(transparent)]
+  FileDb(#[from] FileDbError),
+  #[error(transparent)]
+  IntOverflow(#[from] TryFromIntError),
+  #[error(transparent)]
+  ParseInt(#[from] ParseIntError),
+  /// A word list level that its list doesn't have, like HSK level 10.
+  #[error("{list} has no level {level:?}")]
+  UnknownLevel { list: &'static str, level: String },
+  /// A phrase index or build checkpoint that doesn't go with the word list,
+  /// corpus, options, or database it was used with. `reason` says which,
+  /// and what to do about it.
+  #[error("{} {reason}", path.display())]
+  Mismatch { path: PathBuf, reason: String },
+  /// A snippet database or index that no build has written yet.
+  #[error("{} does not exist; build the corpus first", path.display())]
+  MissingDatabase { path: PathBuf },
+  /// A card template Anki couldn't make sensible cards from.
+  #[error("{} {reason}", path.display())]
+  BadTemplate { path: PathBuf, reason: String },
+  /// Input that was read fine but can't be used in some other way.
+  #[error("{0}")]
+  Invalid(String),
+  /// Another error, with a note on what was being done when it happened.
+  /// [`Error::root`] sees through it to the error underneath.
+  #[error("{context}")]
+  Context {
+    context: String,
+    #[source]
+    source: Box<Error>,
+  },
+}
+
+impl Error {
+  /// The error under any added context, to match on what went wrong.
+  pub fn root(&self) -> &Error {
+    match self {
+      Error::Context { source, .. } => source.root(),
+      err => err,
+    }
+  }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns early with an [`Error::Invalid`], like `anyhow::bail!`.
+macro_rules! bail {
+  ($($arg:tt)*) => {
+    return Err($crate::error::Error::Invalid(format!($($arg)*)))
+  };
+}
+pub(crate) use bail;
+
+/// Adds context to errors, like `anyhow::Context`.
+pub trait Context<T> {
+  fn context(self, context: impl Display) -> Result<T>;
+  fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+  fn context(self, context: impl Display) -> Result<T> {
+    self.with_context(|| context)
+  }
+
+  fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+    self.map_err(|err| Error::Context {
+      context: f().to_string(),
+      source: Box::new(err.into()),
+    })
+  }
+}
+
+impl<T> Context<T> for Option<T> {
+  fn context(self, context: impl Display) -> Result<T> {
+    self.ok_or_else(|| Error::Invalid(context.to_string()))
+  }
+
+  fn with_context<C: Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+    self.ok_or_else(|| Error::Invalid(f().to_string()))
+  }
+}
@@ -1,60 +1,1316 @@
-use anyhow::Result;
-use serde::{de::DeserializeOwned, Serialize};
+use crate::error::{Context, Result};
+use lru::LruCache;
+use memmap2::Mmap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-  fs::File,
-  io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+  fs::{self, File, OpenOptions},
+  io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+  iter,
+  num::NonZeroUsize,
   ops::Range,
-  path::Path,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
 };
+use zstd::bulk::{Compressor, Decompressor};
+
+/// Size of the CRC32 that prefixes each record when checksums are on.
+const CHECKSUM_LEN: usize = 4;
+/// Size of the length that prefixes each record when length prefixes are on.
+const LENGTH_LEN: usize = 4;
+
+/// Every database starts with a header saying how its records are laid out:
+/// the magic bytes, a format version, flags, and where the compression
+/// dictionary is stored, if there is one.
+const MAGIC: &[u8; 4] = b"ZHDB";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 20;
+const FLAG_CHECKSUMS: u8 = 1;
+const FLAG_COMPRESSED: u8 = 2;
+const FLAG_LENGTH_PREFIX: u8 = 4;
+
+/// In compressed databases, each record's first byte says how it is stored.
+/// Records compressed with zstd are then followed by their JSON length.
+const CODEC_ZSTD: u8 = 1;
+const CODEC_ZSTD_DICT: u8 = 2;
+
+const ZSTD_LEVEL: i32 = 3;
+/// Records are small and very alike, so a dictionary trained on the first
+/// few thousand compresses the rest much better than zstd does on its own.
+const DICT_SAMPLES: usize = 2000;
+const DICT_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileDbError {
+  #[error(
+    "record at bytes {range:?} is corrupt: expected checksum {expected:#010x}, got {actual:#010x}"
+  )]
+  ChecksumMismatch {
+    range: Range<u64>,
+    expected: u32,
+    actual: u32,
+  },
+  #[error("record at bytes {range:?} is truncated")]
+  Truncated { range: Range<u64> },
+  #[error("{} doesn't match its manifest; it was truncated or changed after it was written", path.display())]
+  ManifestMismatch { path: PathBuf },
+  #[error("not a snippet database, or one from an older version; rebuild it")]
+  NotADatabase,
+  #[error("unsupported snippet database version {0}")]
+  UnsupportedVersion(u8),
+  #[error("{} was written with {found:?}, not {expected:?}", path.display())]
+  ConfigMismatch {
+    path: PathBuf,
+    found: FileDbConfig,
+    expected: FileDbConfig,
+  },
+  #[error("the database was written without length prefixes, so it can't be scanned")]
+  NotScannable,
+  #[error("range {range:?} is past the end of the database")]
+  OutOfBounds { range: Range<u64> },
+  #[error("record needs a compression dictionary the database doesn't have")]
+  MissingDictionary,
+  #[error("record at bytes {range:?} has unknown codec {codec}")]
+  UnknownCodec { range: Range<u64>, codec: u8 },
+  #[error("no snippet database at {}", path.display())]
+  NoShards { path: PathBuf },
+  #[error("no shard {0} in the database")]
+  NoSuchShard(u16),
+  #[error("a thread panicked while writing to the database")]
+  Poisoned,
+  #[error("database writer is still shared with another thread")]
+  StillShared,
+}
+
+/// Summary of a finished database, saved next to it as `<file>.manifest` so
+/// readers can tell whether the file is still the one that was written.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+  pub bytes: u64,
+  /// Unknown if records were appended to a database without a manifest.
+  pub records: Option<u64>,
+  pub sha256: String,
+}
+
+fn manifest_path(path: &Path) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_owned();
+  name.push(".manifest");
+  path.with_file_name(name)
+}
+
+/// The manifest of the database at `path`, if it has one.
+pub fn read_manifest(path: impl AsRef<Path>) -> Result<Option<Manifest>> {
+  let path = manifest_path(path.as_ref());
+  if !path.exists() {
+    return Ok(None);
+  }
+  let manifest = serde_json::from_reader(BufReader::new(File::open(&path)?))
+    .with_context(|| format!("reading {}", path.display()))?;
+  Ok(Some(manifest))
+}
+
+/// The length and SHA-256 of the file at `path`.
+fn hash_file(path: &Path) -> Result<(u64, String)> {
+  let mut hasher = Sha256::new();
+  let bytes = io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+  Ok((bytes, format!("{:x}", hasher.finalize())))
+}
+
+/// Checks the database at `path` against its manifest. Databases without
+/// one, e.g. from a writer that wasn't finished, aren't checked.
+fn verify_manifest(path: &Path) -> Result<()> {
+  let Some(manifest) = read_manifest(path)? else {
+    return Ok(());
+  };
+  let (bytes, sha256) = hash_file(path)?;
+  if bytes != manifest.bytes || sha256 != manifest.sha256 {
+    return Err(
+      FileDbError::ManifestMismatch {
+        path: path.to_owned(),
+      }
+      .into(),
+    );
+  }
+  Ok(())
+}
+
+/// How records are laid out on disk. The writer records this in the file's
+/// header, and readers follow it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileDbConfig {
+  /// Prefix each record with a CRC32 of its JSON, checked on every read.
+  pub checksums: bool,
+  /// Compress each record with zstd.
+  pub compression: bool,
+  /// Prefix each record with its length, so the database can be scanned
+  /// with [`FileDbReader::iter`] without knowing the records' ranges.
+  pub length_prefix: bool,
+}
+
+impl Default for FileDbConfig {
+  fn default() -> Self {
+    FileDbConfig {
+      checksums: true,
+      compression: false,
+      length_prefix: false,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+struct Header {
+  config: FileDbConfig,
+  dict: Option<Range<u64>>,
+}
+
+impl Header {
+  fn encode(&self) -> [u8; HEADER_LEN] {
+    let mut flags = 0;
+    if self.config.checksums {
+      flags |= FLAG_CHECKSUMS;
+    }
+    if self.config.compression {
+      flags |= FLAG_COMPRESSED;
+    }
+    if self.config.length_prefix {
+      flags |= FLAG_LENGTH_PREFIX;
+    }
+    let dict = self.dict.clone().unwrap_or(0..0);
+    let mut bytes = [0; HEADER_LEN];
+    bytes[..4].copy_from_slice(MAGIC);
+    bytes[4] = FORMAT_VERSION;
+    bytes[5] = flags;
+    bytes[8..16].copy_from_slice(&dict.start.to_le_bytes());
+    bytes[16..20].copy_from_slice(&u32::try_from(dict.end - dict.start).unwrap().to_le_bytes());
+    bytes
+  }
+
+  fn decode(bytes: &[u8]) -> Result<Self> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+      return Err(FileDbError::NotADatabase.into());
+    }
+    if bytes[4] != FORMAT_VERSION {
+      return Err(FileDbError::UnsupportedVersion(bytes[4]).into());
+    }
+    let config = FileDbConfig {
+      checksums: bytes[5] & FLAG_CHECKSUMS != 0,
+      compression: bytes[5] & FLAG_COMPRESSED != 0,
+      length_prefix: bytes[5] & FLAG_LENGTH_PREFIX != 0,
+    };
+    let start = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    let len = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+    Ok(Header {
+      config,
+      dict: (len > 0).then(|| start..start + u64::from(len)),
+    })
+  }
+
+  fn read(file: &mut File) -> Result<Self> {
+    let mut bytes = [0; HEADER_LEN];
+    file
+      .read_exact(&mut bytes)
+      .context("snippet database is missing its header")?;
+    Self::decode(&bytes)
+  }
+}
+
+fn read_dict(file: &mut File, range: &Range<u64>) -> Result<Vec<u8>> {
+  let mut dict = vec![0; usize::try_from(range.end - range.start)?];
+  file.seek(SeekFrom::Start(range.start))?;
+  file.read_exact(&mut dict)?;
+  Ok(dict)
+}
+
+struct Compression {
+  compressor: Compressor<'static>,
+  has_dict: bool,
+  /// Concatenated JSON of the first records and their sizes, kept to train
+  /// a dictionary. `None` once training is done or has failed.
+  samples: Option<(Vec<u8>, Vec<usize>)>,
+}
+
+impl Compression {
+  fn new(dict: Option<&[u8]>) -> Result<Self> {
+    Ok(match dict {
+      Some(dict) => Compression {
+        compressor: Compressor::with_dictionary(ZSTD_LEVEL, dict)?,
+        has_dict: true,
+        samples: None,
+      },
+      None => Compression {
+        compressor: Compressor::new(ZSTD_LEVEL)?,
+        has_dict: false,
+        samples: Some(Default::default()),
+      },
+    })
+  }
+
+  fn encode(&mut self, json: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    out.push(match self.has_dict {
+      true => CODEC_ZSTD_DICT,
+      false => CODEC_ZSTD,
+    });
+    out.extend_from_slice(&u32::try_from(json.len())?.to_le_bytes());
+    out.extend_from_slice(&self.compressor.compress(json)?);
+    Ok(())
+  }
+
+  /// Adds a record to the training samples, returning a dictionary once
+  /// there are enough of them.
+  fn train(&mut self, json: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some((samples, sizes)) = &mut self.samples else {
+      return Ok(None);
+    };
+    samples.extend_from_slice(json);
+    sizes.push(json.len());
+    if sizes.len() < DICT_SAMPLES {
+      return Ok(None);
+    }
+    let dict = zstd::dict::from_continuous(samples, sizes, DICT_SIZE);
+    self.samples = None;
+    // Training fails on samples too uniform to learn from; compressing
+    // without a dictionary still works.
+    let Ok(dict) = dict else { return Ok(None) };
+    self.compressor = Compressor::with_dictionary(ZSTD_LEVEL, &dict)?;
+    self.has_dict = true;
+    Ok(Some(dict))
+  }
+}
 
 pub struct FileDbWriter {
   writer: BufWriter<File>,
+  path: PathBuf,
+  records: Option<u64>,
+  header: Header,
   byte_pos: u64,
+  record_bytes: u64,
+  json_bytes: u64,
+  compression: Option<Compression>,
   buf: Vec<u8>,
+  json: Vec<u8>,
 }
 
 impl FileDbWriter {
   pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+    Self::create(path, FileDbConfig::default())
+  }
+
+  pub fn create(path: impl AsRef<Path>, config: FileDbConfig) -> Result<Self> {
+    let path = path.as_ref();
+    let header = Header { config, dict: None };
+    let mut file = File::create(path)?;
+    file.write_all(&header.encode())?;
+    Self::from_file(file, path, header, None, Some(0))
+  }
+
+  /// Opens an existing database to add records after the ones already in it.
+  /// Ranges start from the file's current length, so they stay valid
+  /// alongside ranges from earlier writers to the same file. Fails if the
+  /// database was written with a different config.
+  pub fn append(path: impl AsRef<Path>, config: FileDbConfig) -> Result<Self> {
+    let path = path.as_ref();
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut header = Header::read(&mut file)?;
+    if header.config != config {
+      return Err(
+        FileDbError::ConfigMismatch {
+          path: path.to_owned(),
+          found: header.config,
+          expected: config,
+        }
+        .into(),
+      );
+    }
+    // A dictionary past the end of the file was cut off along with the
+    // records after it, e.g. when resuming from a checkpoint.
+    let len = file.metadata()?.len();
+    let dict = match header.dict.clone() {
+      Some(range) if range.end <= len => Some(read_dict(&mut file, &range)?),
+      Some(_) => {
+        header.dict = None;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header.encode())?;
+        None
+      }
+      None => None,
+    };
+    file.seek(SeekFrom::End(0))?;
+    // The manifest is rewritten when the writer is finished.
+    let records = read_manifest(path)?.and_then(|manifest| manifest.records);
+    let manifest = manifest_path(path);
+    if manifest.exists() {
+      fs::remove_file(manifest)?;
+    }
+    Self::from_file(file, path, header, dict, records)
+  }
+
+  fn from_file(
+    file: File,
+    path: &Path,
+    header: Header,
+    dict: Option<Vec<u8>>,
+    records: Option<u64>,
+  ) -> Result<Self> {
+    let byte_pos = file.metadata()?.len();
+    let compression = match header.config.compression {
+      true => Some(Compression::new(dict.as_deref())?),
+      false => None,
+    };
     Ok(FileDbWriter {
-      writer: BufWriter::new(File::create(path)?),
-      byte_pos: 0,
+      writer: BufWriter::new(file),
+      path: path.to_owned(),
+      records,
+      header,
+      byte_pos,
+      record_bytes: 0,
+      json_bytes: 0,
+      compression,
       buf: Vec::new(),
+      json: Vec::new(),
     })
   }
 
   pub fn write<T: Serialize>(&mut self, obj: &T) -> Result<Range<u64>> {
+    self.json.clear();
+    serde_json::to_writer(&mut self.json, obj)?;
+
     self.buf.clear();
-    serde_json::to_writer(&mut self.buf, obj)?;
+    if self.header.config.length_prefix {
+      // Filled in below once the record's size is known.
+      self.buf.extend_from_slice(&[0; LENGTH_LEN]);
+    }
+    if self.header.config.checksums {
+      self
+        .buf
+        .extend_from_slice(&crc32fast::hash(&self.json).to_le_bytes());
+    }
+    match &mut self.compression {
+      Some(compression) => compression.encode(&self.json, &mut self.buf)?,
+      None => self.buf.extend_from_slice(&self.json),
+    }
+    if self.header.config.length_prefix {
+      let len = u32::try_from(self.buf.len() - LENGTH_LEN)?;
+      self.buf[..LENGTH_LEN].copy_from_slice(&len.to_le_bytes());
+    }
     self.writer.write_all(&self.buf)?;
 
     let start = self.byte_pos;
     let len = u64::try_from(self.buf.len()).unwrap();
     let range = start..(start + len);
     self.byte_pos += len;
+    self.record_bytes += len;
+    self.records = self.records.map(|records| records + 1);
+    self.json_bytes += u64::try_from(self.json.len()).unwrap();
+
+    let dict = match &mut self.compression {
+      Some(compression) => compression.train(&self.json)?,
+      None => None,
+    };
+    if let Some(dict) = dict {
+      self.write_dict(&dict)?;
+    }
 
     Ok(range)
   }
+
+  /// Stores a newly trained dictionary after the records so far and points
+  /// the header at it.
+  fn write_dict(&mut self, dict: &[u8]) -> Result<()> {
+    self.writer.write_all(dict)?;
+    let start = self.byte_pos;
+    self.byte_pos += u64::try_from(dict.len()).unwrap();
+    self.header.dict = Some(start..self.byte_pos);
+
+    self.writer.flush()?;
+    let file = self.writer.get_mut();
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&self.header.encode())?;
+    file.seek(SeekFrom::Start(self.byte_pos))?;
+    Ok(())
+  }
+
+  /// Bytes of records written by this writer as stored, after any
+  /// compression.
+  pub fn record_bytes(&self) -> u64 {
+    self.record_bytes
+  }
+
+  /// Bytes of JSON in the records written by this writer, before any
+  /// compression.
+  pub fn json_bytes(&self) -> u64 {
+    self.json_bytes
+  }
+
+  /// Writes buffered records through to the file and returns its length, so
+  /// a caller can record how much of the file is complete.
+  pub fn flush(&mut self) -> Result<u64> {
+    self.writer.flush()?;
+    Ok(self.byte_pos)
+  }
+
+  /// Flushes buffered records and writes the database's manifest, which it
+  /// returns. Unlike dropping the writer, this reports any error from the
+  /// final flush.
+  pub fn finish(mut self) -> Result<Manifest> {
+    self.writer.flush()?;
+    let (bytes, sha256) = hash_file(&self.path)?;
+    let manifest = Manifest {
+      bytes,
+      records: self.records,
+      sha256,
+    };
+    let mut writer = BufWriter::new(File::create(manifest_path(&self.path))?);
+    serde_json::to_writer(&mut writer, &manifest)?;
+    writer.flush()?;
+    Ok(manifest)
+  }
+}
+
+/// A [`FileDbWriter`] that can be cloned and written to from several threads.
+/// Each write holds the lock while it appends, so records never interleave and
+/// their ranges match what's in the file.
+#[derive(Clone)]
+pub struct SharedFileDbWriter(Arc<Mutex<FileDbWriter>>);
+
+impl SharedFileDbWriter {
+  pub fn new(writer: FileDbWriter) -> Self {
+    SharedFileDbWriter(Arc::new(Mutex::new(writer)))
+  }
+
+  pub fn write<T: Serialize>(&self, obj: &T) -> Result<Range<u64>> {
+    self.0.lock().map_err(|_| FileDbError::Poisoned)?.write(obj)
+  }
+
+  /// Returns the underlying writer, e.g. to [`FileDbWriter::finish`] it. Fails
+  /// if any clones of this writer are still alive.
+  pub fn into_inner(self) -> Result<FileDbWriter> {
+    let mutex = Arc::try_unwrap(self.0).map_err(|_| FileDbError::StillShared)?;
+    Ok(mutex.into_inner().map_err(|_| FileDbError::Poisoned)?)
+  }
+}
+
+/// Where a record is in a database split into shard files, one per writer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShardRange {
+  pub shard: u16,
+  pub range: Range<u64>,
+}
+
+/// The file holding shard `shard` of the database at `base`, e.g.
+/// `phrases.shard-3.txt` for `phrases.txt`.
+pub fn shard_path(base: impl AsRef<Path>, shard: u16) -> PathBuf {
+  let base = base.as_ref();
+  let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+  let name = match base.extension() {
+    Some(ext) => format!("{stem}.shard-{shard}.{}", ext.to_string_lossy()),
+    None => format!("{stem}.shard-{shard}"),
+  };
+  base.with_file_name(name)
+}
+
+/// Deletes the shards of the database at `base` from `from` on, e.g. those
+/// left over from an earlier build with more shards.
+pub fn remove_shards(base: impl AsRef<Path>, from: u16) -> Result<()> {
+  for shard in from..=u16::MAX {
+    let path = shard_path(&base, shard);
+    if !path.exists() {
+      break;
+    }
+    let manifest = manifest_path(&path);
+    if manifest.exists() {
+      fs::remove_file(manifest)?;
+    }
+    fs::remove_file(path)?;
+  }
+  Ok(())
+}
+
+/// A [`FileDbWriter`] for one shard, whose ranges say which shard they're in.
+pub struct ShardWriter {
+  shard: u16,
+  writer: FileDbWriter,
 }
 
+impl ShardWriter {
+  pub fn new(shard: u16, writer: FileDbWriter) -> Self {
+    ShardWriter { shard, writer }
+  }
+
+  pub fn write<T: Serialize>(&mut self, obj: &T) -> Result<ShardRange> {
+    Ok(ShardRange {
+      shard: self.shard,
+      range: self.writer.write(obj)?,
+    })
+  }
+
+  /// See [`FileDbWriter::flush`].
+  pub fn flush(&mut self) -> Result<u64> {
+    self.writer.flush()
+  }
+
+  pub fn into_inner(self) -> FileDbWriter {
+    self.writer
+  }
+}
+
+/// Number of records [`FileDbReader::load`] keeps cached.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Records' JSON keyed by `(start, end)` byte offsets.
+type RecordCache = LruCache<(u64, u64), Box<[u8]>>;
+
 pub struct FileDbReader {
-  reader: BufReader<File>,
+  source: ReaderSource,
   buf: Vec<u8>,
+  /// Recently read records by range, so records that many phrases share
+  /// aren't re-read from disk.
+  cache: Option<RecordCache>,
+  header: Header,
+  len: u64,
+  decoder: RecordDecoder,
+}
+
+enum ReaderSource {
+  Buffered {
+    reader: BufReader<File>,
+    /// The reader's offset in the file, so nearby reads can move within the
+    /// buffer instead of seeking, which would discard it. `None` after a
+    /// failed read, which leaves the offset unknown.
+    pos: Option<u64>,
+  },
+  /// The whole file mapped into memory, so reads are just slices.
+  Mapped(Mmap),
 }
 
 impl FileDbReader {
   pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    Self::with_cache_capacity(path, DEFAULT_CACHE_CAPACITY)
+  }
+
+  /// Like [`FileDbReader::load`], caching up to `capacity` records; 0
+  /// disables the cache.
+  pub fn with_cache_capacity(path: impl AsRef<Path>, capacity: usize) -> Result<Self> {
+    verify_manifest(path.as_ref())?;
+    let mut file = File::open(path)?;
+    let header = Header::read(&mut file)?;
+    let dict = header
+      .dict
+      .as_ref()
+      .map(|range| read_dict(&mut file, range))
+      .transpose()?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(FileDbReader {
+      len: file.metadata()?.len(),
+      source: ReaderSource::Buffered {
+        reader: BufReader::new(file),
+        pos: Some(0),
+      },
+      buf: Vec::new(),
+      cache: NonZeroUsize::new(capacity).map(LruCache::new),
+      decoder: RecordDecoder::new(header.config, dict.as_deref())?,
+      header,
+    })
+  }
+
+  /// Memory-maps the database, which makes scattered reads much cheaper than
+  /// seeking. Falls back to [`FileDbReader::load`] where mapping fails.
+  pub fn load_mmap(path: impl AsRef<Path>) -> Result<Self> {
+    verify_manifest(path.as_ref())?;
+    let file = File::open(&path)?;
+    // SAFETY: the database is only appended to while it is being built, not
+    // while it is read, so the mapped bytes don't change underneath us.
+    let Ok(map) = (unsafe { Mmap::map(&file) }) else {
+      return Self::load(path);
+    };
+    let header = Header::decode(&map)?;
+    let dict = match &header.dict {
+      Some(range) => Some(
+        map
+          .get(usize::try_from(range.start)?..usize::try_from(range.end)?)
+          .ok_or_else(|| FileDbError::OutOfBounds {
+            range: range.clone(),
+          })?,
+      ),
+      None => None,
+    };
+    let decoder = RecordDecoder::new(header.config, dict)?;
     Ok(FileDbReader {
-      reader: BufReader::new(File::open(path)?),
+      len: map.len() as u64,
+      source: ReaderSource::Mapped(map),
       buf: Vec::new(),
+      cache: None,
+      header,
+      decoder,
     })
   }
 
+  /// The layout the database was written with.
+  pub fn config(&self) -> FileDbConfig {
+    self.header.config
+  }
+
   pub fn read<T: DeserializeOwned>(&mut self, range: Range<u64>) -> Result<T> {
-    self.reader.seek(SeekFrom::Start(range.start))?;
-    self.buf.clear();
-    (&mut self.reader)
-      .take(range.end - range.start)
-      .read_to_end(&mut self.buf)?;
-    Ok(serde_json::from_slice(&self.buf)?)
+    let key = (range.start, range.end);
+    if let Some(json) = self.cache.as_mut().and_then(|cache| cache.get(&key)) {
+      return Ok(serde_json::from_slice(json)?);
+    }
+
+    let bytes = self.source.read(range.clone(), &mut self.buf)?;
+    let json = self.decoder.decode(range.clone(), bytes)?;
+    let value = serde_json::from_slice(json)?;
+    if let Some(cache) = &mut self.cache {
+      cache.put(key, json.into());
+    }
+    Ok(value)
+  }
+
+  /// Reads every record in file order, with the range the writer returned
+  /// for it. Needs a database written with [`FileDbConfig::length_prefix`].
+  pub fn iter<T: DeserializeOwned>(
+    &mut self,
+  ) -> impl Iterator<Item = Result<(Range<u64>, T)>> + '_ {
+    let mut next = Some(HEADER_LEN as u64);
+    iter::from_fn(move || {
+      let start = next.take()?;
+      let record = self.record_at(start).and_then(|range| {
+        let Some(range) = range else { return Ok(None) };
+        next = Some(range.end);
+        let value = self.read(range.clone())?;
+        Ok(Some((range, value)))
+      });
+      record.transpose()
+    })
+  }
+
+  /// Calls `f` on every record in file order; see [`FileDbReader::iter`].
+  pub fn scan_all<T: DeserializeOwned, F: FnMut(T)>(&mut self, mut f: F) -> Result<()> {
+    for record in self.iter() {
+      f(record?.1);
+    }
+    Ok(())
+  }
+
+  /// The range of the record starting at `start`, or `None` at the end of
+  /// the database.
+  fn record_at(&mut self, mut start: u64) -> Result<Option<Range<u64>>> {
+    if !self.header.config.length_prefix {
+      return Err(FileDbError::NotScannable.into());
+    }
+    if let Some(dict) = self.header.dict.as_ref().filter(|dict| dict.start == start) {
+      start = dict.end;
+    }
+    if start >= self.len {
+      return Ok(None);
+    }
+    let prefix = start..start + LENGTH_LEN as u64;
+    let len = self.source.read(prefix.clone(), &mut self.buf)?;
+    let len = <[u8; LENGTH_LEN]>::try_from(len).map_err(|_| FileDbError::Truncated {
+      range: prefix.clone(),
+    })?;
+    Ok(Some(start..prefix.end + u64::from(u32::from_le_bytes(len))))
+  }
+
+  /// Reads the records at `ranges`, returned in the same order. The reads
+  /// themselves happen in file order, so they are mostly sequential.
+  pub fn read_many<T: DeserializeOwned>(&mut self, ranges: &[Range<u64>]) -> Result<Vec<T>> {
+    let mut order = (0..ranges.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| ranges[*i].start);
+    let mut values = (0..ranges.len()).map(|_| None).collect::<Vec<_>>();
+    for i in order {
+      values[i] = Some(self.read(ranges[i].clone())?);
+    }
+    Ok(values.into_iter().map(Option::unwrap).collect())
+  }
+}
+
+impl ReaderSource {
+  /// The stored bytes in `range`, read into `buf` if they aren't mapped.
+  fn read<'a>(&'a mut self, range: Range<u64>, buf: &'a mut Vec<u8>) -> Result<&'a [u8]> {
+    match self {
+      ReaderSource::Mapped(map) => {
+        let bytes = usize::try_from(range.start)?..usize::try_from(range.end)?;
+        Ok(map.get(bytes).ok_or(FileDbError::OutOfBounds { range })?)
+      }
+      ReaderSource::Buffered { reader, pos } => {
+        match pos.take() {
+          Some(pos) => reader.seek_relative(range.start as i64 - pos as i64)?,
+          None => {
+            reader.seek(SeekFrom::Start(range.start))?;
+          }
+        }
+        buf.clear();
+        let len = reader.take(range.end - range.start).read_to_end(buf)?;
+        *pos = Some(range.start + len as u64);
+        Ok(buf)
+      }
+    }
+  }
+}
+
+/// Turns records as stored back into their JSON, following the header.
+struct RecordDecoder {
+  length_prefix: bool,
+  checksums: bool,
+  /// Decompressors for records compressed without and with the dictionary,
+  /// if the database is compressed.
+  zstd: Option<(Decompressor<'static>, Option<Decompressor<'static>>)>,
+  json: Vec<u8>,
+}
+
+impl RecordDecoder {
+  fn new(config: FileDbConfig, dict: Option<&[u8]>) -> Result<Self> {
+    let zstd = match config.compression {
+      true => Some((
+        Decompressor::new()?,
+        dict.map(Decompressor::with_dictionary).transpose()?,
+      )),
+      false => None,
+    };
+    Ok(RecordDecoder {
+      length_prefix: config.length_prefix,
+      checksums: config.checksums,
+      zstd,
+      json: Vec::new(),
+    })
+  }
+
+  /// Decompresses the record at `range` if need be, checks its checksum if
+  /// it has one, and returns its JSON.
+  fn decode<'a>(&'a mut self, range: Range<u64>, bytes: &'a [u8]) -> Result<&'a [u8]> {
+    let truncated = || FileDbError::Truncated {
+      range: range.clone(),
+    };
+    let bytes = match self.length_prefix {
+      true => {
+        let (len, rest) = bytes
+          .split_first_chunk::<LENGTH_LEN>()
+          .ok_or_else(truncated)?;
+        if u32::from_le_bytes(*len) as usize != rest.len() {
+          return Err(truncated().into());
+        }
+        rest
+      }
+      false => bytes,
+    };
+    let (crc, bytes) = match self.checksums {
+      true => {
+        let (crc, rest) = bytes
+          .split_first_chunk::<CHECKSUM_LEN>()
+          .ok_or_else(truncated)?;
+        (Some(u32::from_le_bytes(*crc)), rest)
+      }
+      false => (None, bytes),
+    };
+
+    let json = match &mut self.zstd {
+      None => bytes,
+      Some((plain, with_dict)) => {
+        let (&codec, rest) = bytes.split_first().ok_or_else(truncated)?;
+        let (len, compressed) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+        let decompressor = match codec {
+          CODEC_ZSTD => plain,
+          CODEC_ZSTD_DICT => with_dict.as_mut().ok_or(FileDbError::MissingDictionary)?,
+          _ => return Err(FileDbError::UnknownCodec { range, codec }.into()),
+        };
+        let len = usize::try_from(u32::from_le_bytes(*len))?;
+        self.json = decompressor.decompress(compressed, len)?;
+        &self.json
+      }
+    };
+
+    if let Some(expected) = crc {
+      let actual = crc32fast::hash(json);
+      if actual != expected {
+        return Err(
+          FileDbError::ChecksumMismatch {
+            range,
+            expected,
+            actual,
+          }
+          .into(),
+        );
+      }
+    }
+    Ok(json)
+  }
+}
+
+/// Reads a database split into shard files, routing each read to its shard.
+pub struct ShardedFileDbReader {
+  shards: Vec<FileDbReader>,
+}
+
+impl ShardedFileDbReader {
+  /// Opens every shard of the database at `base` with `open`, e.g.
+  /// [`FileDbReader::load`].
+  pub fn open(
+    base: impl AsRef<Path>,
+    mut open: impl FnMut(PathBuf) -> Result<FileDbReader>,
+  ) -> Result<Self> {
+    let mut shards = Vec::new();
+    for shard in 0..=u16::MAX {
+      let path = shard_path(&base, shard);
+      if !path.exists() {
+        break;
+      }
+      let reader = open(path.clone()).with_context(|| format!("opening {}", path.display()))?;
+      shards.push(reader);
+    }
+    if shards.is_empty() {
+      return Err(
+        FileDbError::NoShards {
+          path: base.as_ref().to_owned(),
+        }
+        .into(),
+      );
+    }
+    Ok(ShardedFileDbReader { shards })
+  }
+
+  pub fn shards(&self) -> &[FileDbReader] {
+    &self.shards
+  }
+
+  pub fn read<T: DeserializeOwned>(&mut self, range: ShardRange) -> Result<T> {
+    self
+      .shards
+      .get_mut(usize::from(range.shard))
+      .ok_or(FileDbError::NoSuchShard(range.shard))?
+      .read(range.range)
+  }
+
+  /// Like [`FileDbReader::read_many`], reading each shard in file order.
+  pub fn read_many<T: DeserializeOwned>(&mut self, ranges: &[ShardRange]) -> Result<Vec<T>> {
+    let mut order = (0..ranges.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| (ranges[*i].shard, ranges[*i].range.start));
+    let mut values = (0..ranges.len()).map(|_| None).collect::<Vec<_>>();
+    for i in order {
+      values[i] = Some(self.read(ranges[i].clone())?);
+    }
+    Ok(values.into_iter().map(Option::unwrap).collect())
+  }
+
+  /// Reads every record of every shard in order; see [`FileDbReader::iter`].
+  pub fn iter<T: DeserializeOwned>(
+    &mut self,
+  ) -> impl Iterator<Item = Result<(ShardRange, T)>> + '_ {
+    self.shards.iter_mut().zip(0..).flat_map(|(reader, shard)| {
+      reader
+        .iter()
+        .map(move |record| record.map(|(range, value)| (ShardRange { shard, range }, value)))
+    })
+  }
+
+  /// Calls `f` on every record, shard by shard; see [`FileDbReader::iter`].
+  pub fn scan_all<T: DeserializeOwned, F: FnMut(T)>(&mut self, mut f: F) -> Result<()> {
+    for shard in &mut self.shards {
+      shard.scan_all(&mut f)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::error::Error;
+
+  fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("card-builder-db-{}-{name}", std::process::id()))
+  }
+
+  #[test]
+  fn corrupt_record_fails_checksum() {
+    let path = temp_path("corrupt.txt");
+    let mut writer = FileDbWriter::new(&path).unwrap();
+    let range = writer.write(&"我喜欢学习中文").unwrap();
+    // Without a manifest, the corruption is only caught by the checksum.
+    drop(writer);
+
+    let mut bytes = fs::read(&path).unwrap();
+    let last = usize::try_from(range.end).unwrap() - 2;
+    bytes[last] ^= 1;
+    fs::write(&path, bytes).unwrap();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let err = reader.read::<String>(range.clone()).unwrap_err();
+    let _ = fs::remove_file(&path);
+    assert!(
+      matches!(&err, Error::FileDb(FileDbError::ChecksumMismatch { range: r, .. }) if *r == range),
+      "{err}"
+    );
+  }
+
+  #[test]
+  fn finish_reports_written_size() {
+    let path = temp_path("finish.txt");
+    let mut writer = FileDbWriter::new(&path).unwrap();
+    let record_bytes = ["我", "喜欢", "学习中文"]
+      .iter()
+      .map(|record| {
+        let range = writer.write(record).unwrap();
+        range.end - range.start
+      })
+      .sum::<u64>();
+    assert_eq!(writer.record_bytes(), record_bytes);
+
+    let manifest = writer.finish().unwrap();
+    let len = fs::metadata(&path).unwrap().len();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(manifest.bytes, HEADER_LEN as u64 + record_bytes);
+    assert_eq!(manifest.records, Some(3));
+    assert_eq!(len, manifest.bytes);
+  }
+
+  #[test]
+  fn append_keeps_earlier_ranges() {
+    let path = temp_path("append.txt");
+    let config = FileDbConfig::default();
+    let mut writer = FileDbWriter::create(&path, config).unwrap();
+    let mut ranges = vec![writer.write(&"一").unwrap(), writer.write(&"二").unwrap()];
+    drop(writer);
+
+    let mut writer = FileDbWriter::append(&path, config).unwrap();
+    ranges.push(writer.write(&"三").unwrap());
+    writer.finish().unwrap();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let records = ranges
+      .into_iter()
+      .map(|range| reader.read::<String>(range).unwrap())
+      .collect::<Vec<_>>();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(records, ["一", "二", "三"]);
+  }
+
+  #[test]
+  fn read_many_keeps_request_order() {
+    let path = temp_path("read-many.txt");
+    let mut writer = FileDbWriter::new(&path).unwrap();
+    let ranges = (0..5)
+      .map(|i| writer.write(&i).unwrap())
+      .collect::<Vec<_>>();
+    writer.finish().unwrap();
+
+    let order = [3, 0, 4, 1, 2];
+    let shuffled = order.iter().map(|i| ranges[*i].clone()).collect::<Vec<_>>();
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let records = reader.read_many::<usize>(&shuffled).unwrap();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(records, order);
+  }
+
+  #[test]
+  fn cache_serves_records_read_before() {
+    let path = temp_path("cache.txt");
+    let mut writer = FileDbWriter::new(&path).unwrap();
+    let range = writer.write(&"我喜欢学习中文").unwrap();
+    // Far enough past the first record that reading the last one moves the
+    // file reader's buffer off it.
+    writer.write(&"字".repeat(4096)).unwrap();
+    let far = writer.write(&"中文").unwrap();
+    writer.finish().unwrap();
+
+    let mut cached = FileDbReader::load(&path).unwrap();
+    let mut uncached = FileDbReader::with_cache_capacity(&path, 0).unwrap();
+    for reader in [&mut cached, &mut uncached] {
+      assert_eq!(
+        reader.read::<String>(range.clone()).unwrap(),
+        "我喜欢学习中文"
+      );
+      reader.read::<String>(far.clone()).unwrap();
+    }
+
+    // Change the first record underneath both readers: only the cached one
+    // still has the bytes it read before.
+    let mut bytes = fs::read(&path).unwrap();
+    let last = usize::try_from(range.end).unwrap() - 2;
+    bytes[last] ^= 1;
+    fs::write(&path, bytes).unwrap();
+
+    let still_cached = cached.read::<String>(range.clone());
+    let reread = uncached.read::<String>(range.clone());
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(still_cached.unwrap(), "我喜欢学习中文");
+    let err = reread.unwrap_err();
+    assert!(
+      matches!(&err, Error::FileDb(FileDbError::ChecksumMismatch { .. })),
+      "{err}"
+    );
+  }
+
+  #[test]
+  fn shared_writer_ranges_read_back_their_records() {
+    let path = temp_path("shared.txt");
+    let shared = SharedFileDbWriter::new(FileDbWriter::new(&path).unwrap());
+    let written = std::thread::scope(|scope| {
+      let threads = (0..4)
+        .map(|thread| {
+          let writer = shared.clone();
+          scope.spawn(move || {
+            (0..50)
+              .map(|i| {
+                let record = format!("{thread}-{i}");
+                (writer.write(&record).unwrap(), record)
+              })
+              .collect::<Vec<_>>()
+          })
+        })
+        .collect::<Vec<_>>();
+      threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect::<Vec<_>>()
+    });
+
+    let clone = shared.clone();
+    let still_shared = shared.into_inner();
+    assert!(matches!(
+      still_shared,
+      Err(Error::FileDb(FileDbError::StillShared))
+    ));
+    clone.into_inner().unwrap().finish().unwrap();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let read = written
+      .iter()
+      .map(|(range, _)| reader.read::<String>(range.clone()).unwrap())
+      .collect::<Vec<_>>();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(read.len(), 200);
+    let records = written.into_iter().map(|(_, record)| record);
+    assert_eq!(read, records.collect::<Vec<_>>());
+  }
+
+  const COMPRESSED: FileDbConfig = FileDbConfig {
+    checksums: true,
+    compression: true,
+    length_prefix: true,
+  };
+
+  /// Varied enough records for zstd to train a dictionary on.
+  fn sentence(i: usize) -> String {
+    let words = ["我", "喜欢", "学习", "中文", "朋友", "今天", "天气", "很好"];
+    let mut sentence = format!("第{i}句：");
+    for j in 0..(i % 7 + 3) {
+      sentence.push_str(words[(i * 31 + j * 17) % words.len()]);
+    }
+    sentence
+  }
+
+  /// The codec byte of the compressed record at `range`.
+  fn codec(path: &Path, range: &Range<u64>) -> u8 {
+    let bytes = fs::read(path).unwrap();
+    bytes[usize::try_from(range.start).unwrap() + LENGTH_LEN + CHECKSUM_LEN]
+  }
+
+  #[test]
+  fn compressed_records_round_trip_through_the_dictionary() {
+    let path = temp_path("compressed.txt");
+    let mut writer = FileDbWriter::create(&path, COMPRESSED).unwrap();
+    let count = DICT_SAMPLES + 500;
+    let ranges = (0..count)
+      .map(|i| writer.write(&sentence(i)).unwrap())
+      .collect::<Vec<_>>();
+    writer.finish().unwrap();
+    let expected = (0..count).map(sentence).collect::<Vec<_>>();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let dict = reader.header.dict.clone().unwrap();
+    assert_eq!(dict.start, ranges[DICT_SAMPLES - 1].end);
+    assert_eq!(ranges[DICT_SAMPLES].start, dict.end);
+    assert_eq!(codec(&path, &ranges[0]), CODEC_ZSTD);
+    assert_eq!(codec(&path, &ranges[count - 1]), CODEC_ZSTD_DICT);
+
+    assert_eq!(reader.read_many::<String>(&ranges).unwrap(), expected);
+    let (scanned_ranges, scanned): (Vec<_>, Vec<String>) = reader
+      .iter()
+      .collect::<Result<Vec<_>>>()
+      .unwrap()
+      .into_iter()
+      .unzip();
+    assert_eq!(scanned_ranges, ranges);
+    assert_eq!(scanned, expected);
+
+    let mut mapped = FileDbReader::load_mmap(&path).unwrap();
+    let mapped_records = mapped.read_many::<String>(&ranges).unwrap();
+    let mut mapped_scanned = Vec::new();
+    mapped
+      .scan_all(|record: String| mapped_scanned.push(record))
+      .unwrap();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(mapped_records, expected);
+    assert_eq!(mapped_scanned, expected);
+  }
+
+  #[test]
+  fn append_continues_with_the_dictionary() {
+    let path = temp_path("append-dict.txt");
+    let mut writer = FileDbWriter::create(&path, COMPRESSED).unwrap();
+    let mut ranges = (0..DICT_SAMPLES)
+      .map(|i| writer.write(&sentence(i)).unwrap())
+      .collect::<Vec<_>>();
+    writer.finish().unwrap();
+
+    let mut writer = FileDbWriter::append(&path, COMPRESSED).unwrap();
+    ranges.extend((DICT_SAMPLES..DICT_SAMPLES + 10).map(|i| writer.write(&sentence(i)).unwrap()));
+    writer.finish().unwrap();
+    let codec = codec(&path, ranges.last().unwrap());
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let records = reader.read_many::<String>(&ranges).unwrap();
+    let scanned = reader
+      .iter::<String>()
+      .map(|record| record.unwrap().0)
+      .collect::<Vec<_>>();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(codec, CODEC_ZSTD_DICT);
+    assert_eq!(
+      records,
+      (0..DICT_SAMPLES + 10).map(sentence).collect::<Vec<_>>()
+    );
+    assert_eq!(scanned, ranges);
+  }
+
+  #[test]
+  fn append_drops_a_truncated_dictionary() {
+    let path = temp_path("truncated-dict.txt");
+    let mut writer = FileDbWriter::create(&path, COMPRESSED).unwrap();
+    let mut ranges = (0..DICT_SAMPLES - 1)
+      .map(|i| writer.write(&sentence(i)).unwrap())
+      .collect::<Vec<_>>();
+    // A checkpoint taken before the dictionary was trained and written.
+    let checkpoint = writer.flush().unwrap();
+    for i in DICT_SAMPLES - 1..DICT_SAMPLES + 10 {
+      writer.write(&sentence(i)).unwrap();
+    }
+    drop(writer);
+    File::options()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_len(checkpoint)
+      .unwrap();
+
+    let mut writer = FileDbWriter::append(&path, COMPRESSED).unwrap();
+    ranges.extend((0..10).map(|i| writer.write(&sentence(i)).unwrap()));
+    writer.finish().unwrap();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let dict = reader.header.dict.clone();
+    let records = reader.read_many::<String>(&ranges).unwrap();
+    let scanned = reader
+      .iter::<String>()
+      .map(|record| record.unwrap().0)
+      .collect::<Vec<_>>();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(dict, None);
+    let expected = (0..DICT_SAMPLES - 1).chain(0..10).map(sentence);
+    assert_eq!(records, expected.collect::<Vec<_>>());
+    assert_eq!(scanned, ranges);
+  }
+
+  #[test]
+  fn iter_returns_the_writers_ranges() {
+    let path = temp_path("iter.txt");
+    let config = FileDbConfig {
+      length_prefix: true,
+      ..FileDbConfig::default()
+    };
+    let writer = FileDbWriter::create(&path, config).unwrap();
+    writer.finish().unwrap();
+    let mut reader = FileDbReader::load(&path).unwrap();
+    assert_eq!(reader.iter::<String>().count(), 0);
+
+    let mut writer = FileDbWriter::append(&path, config).unwrap();
+    let ranges = ["我", "喜欢", "学习中文"]
+      .iter()
+      .map(|record| writer.write(record).unwrap())
+      .collect::<Vec<_>>();
+    writer.finish().unwrap();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let scanned = reader.iter::<String>().collect::<Result<Vec<_>>>().unwrap();
+    let mut records = Vec::new();
+    reader
+      .scan_all(|record: String| records.push(record))
+      .unwrap();
+    let len = fs::metadata(&path).unwrap().len();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert_eq!(
+      scanned,
+      [
+        (ranges[0].clone(), "我".to_string()),
+        (ranges[1].clone(), "喜欢".to_string()),
+        (ranges[2].clone(), "学习中文".to_string()),
+      ]
+    );
+    assert_eq!(ranges[2].end, len);
+    assert_eq!(records, ["我", "喜欢", "学习中文"]);
+  }
+
+  #[test]
+  fn iter_needs_length_prefixes() {
+    let path = temp_path("not-scannable.txt");
+    let mut writer = FileDbWriter::new(&path).unwrap();
+    writer.write(&"我喜欢学习中文").unwrap();
+    writer.finish().unwrap();
+
+    let mut reader = FileDbReader::load(&path).unwrap();
+    let scanned = reader.iter::<String>().collect::<Vec<_>>();
+    let scan_all = reader.scan_all(|_: String| {});
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    assert!(matches!(
+      scanned.as_slice(),
+      [Err(Error::FileDb(FileDbError::NotScannable))]
+    ));
+    assert!(matches!(
+      scan_all,
+      Err(Error::FileDb(FileDbError::NotScannable))
+    ));
+  }
+
+  #[test]
+  fn load_checks_the_manifest() {
+    let path = temp_path("manifest.txt");
+    let mut writer = FileDbWriter::new(&path).unwrap();
+    let range = writer.write(&"我喜欢学习中文").unwrap();
+    writer.write(&"你呢").unwrap();
+    writer.finish().unwrap();
+    let bytes = fs::read(&path).unwrap();
+
+    let mut flipped = bytes.clone();
+    flipped[usize::try_from(range.end).unwrap() - 2] ^= 1;
+    let truncated = &bytes[..usize::try_from(range.end).unwrap()];
+    let errs = [flipped.as_slice(), truncated]
+      .into_iter()
+      .map(|changed| {
+        fs::write(&path, changed).unwrap();
+        (
+          FileDbReader::load(&path).err(),
+          FileDbReader::load_mmap(&path).err(),
+        )
+      })
+      .collect::<Vec<_>>();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(manifest_path(&path));
+    for (load, load_mmap) in errs {
+      for err in [load, load_mmap] {
+        assert!(
+          matches!(&err, Some(Error::FileDb(FileDbError::ManifestMismatch { path: p })) if *p == path),
+          "{err:?}"
+        );
+      }
+    }
   }
 }
@@ -0,0 +1,113 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// A grammatical structure worth studying on its own, found by
+/// [`detect_grammar_patterns`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GrammarTag {
+  /// 把 moving the object before the verb: 我把书放在桌子上。
+  Ba,
+  /// 被 passive: 杯子被他打破了。
+  Bei,
+  /// 比 comparative: 今天比昨天冷。
+  Bi,
+  /// 是……的 emphasizing a detail of a past event: 我是坐飞机来的。
+  ShiDe,
+  /// 连……都/也 "even": 他连一个字都没写。
+  LianDou,
+  /// 越……越 "the more … the more": 雨越下越大。
+  YueYue,
+}
+
+impl GrammarTag {
+  pub const ALL: [GrammarTag; 6] = [
+    GrammarTag::Ba,
+    GrammarTag::Bei,
+    GrammarTag::Bi,
+    GrammarTag::ShiDe,
+    GrammarTag::LianDou,
+    GrammarTag::YueYue,
+  ];
+
+  /// The tag's name in card tags, as in `grammar::ba-construction`.
+  pub fn name(self) -> &'static str {
+    match self {
+      GrammarTag::Ba => "ba-construction",
+      GrammarTag::Bei => "bei-passive",
+      GrammarTag::Bi => "bi-comparative",
+      GrammarTag::ShiDe => "shi-de",
+      GrammarTag::LianDou => "lian-dou",
+      GrammarTag::YueYue => "yue-yue",
+    }
+  }
+
+  fn pattern(self) -> &'static Regex {
+    // Each rule leaves out the common words that share the marker's
+    // character, like the measure word in 一把椅子 or 被子 "quilt".
+    static BA: LazyLock<Regex> = LazyLock::new(|| {
+      Regex::new(r"(?:^|[^一二两三四五六七八九十几这那每半])把[^握手戏关柄]").unwrap()
+    });
+    static BEI: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"被[^子窝褥单]").unwrap());
+    static BI: LazyLock<Regex> =
+      LazyLock::new(|| Regex::new(r"(?:^|[^对相无类])比[^赛如例分较方喻率]").unwrap());
+    static SHI_DE: LazyLock<Regex> =
+      LazyLock::new(|| Regex::new(r"是[^，。！？,!?；;]{1,15}的[。！？!?]*$").unwrap());
+    static LIAN_DOU: LazyLock<Regex> =
+      LazyLock::new(|| Regex::new(r"连[^，。！？,!?；;]{1,10}[都也]").unwrap());
+    static YUE_YUE: LazyLock<Regex> =
+      LazyLock::new(|| Regex::new(r"越[^，。！？,!?；;]{1,8}越").unwrap());
+    match self {
+      GrammarTag::Ba => &BA,
+      GrammarTag::Bei => &BEI,
+      GrammarTag::Bi => &BI,
+      GrammarTag::ShiDe => &SHI_DE,
+      GrammarTag::LianDou => &LIAN_DOU,
+      GrammarTag::YueYue => &YUE_YUE,
+    }
+  }
+}
+
+/// The grammar patterns `sentence` appears to use. These are simple
+/// character rules rather than a parse, so they can misfire on unusual
+/// sentences.
+pub fn detect_grammar_patterns(sentence: &str) -> Vec<GrammarTag> {
+  let sentence = sentence.trim();
+  GrammarTag::ALL
+    .into_iter()
+    .filter(|tag| tag.pattern().is_match(sentence))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn detects_each_pattern() {
+    let cases = [
+      ("我把书放在桌子上。", GrammarTag::Ba),
+      ("杯子被他打破了。", GrammarTag::Bei),
+      ("今天比昨天冷。", GrammarTag::Bi),
+      ("我是坐飞机来的。", GrammarTag::ShiDe),
+      ("他连一个字都没写。", GrammarTag::LianDou),
+      ("雨越下越大。", GrammarTag::YueYue),
+    ];
+    for (sentence, tag) in cases {
+      assert_eq!(detect_grammar_patterns(sentence), vec![tag], "{sentence}");
+    }
+  }
+
+  #[test]
+  fn ignores_look_alike_words() {
+    for sentence in [
+      "他买了一把椅子。",
+      "我们要把握机会。",
+      "这条被子很暖和。",
+      "我们去看比赛吧。",
+      "这个比较贵。",
+      "他是老师。",
+    ] {
+      assert!(detect_grammar_patterns(sentence).is_empty(), "{sentence}");
+    }
+  }
+}
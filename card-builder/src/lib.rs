@@ -0,0 +1,19 @@
+pub mod chengyu;
+pub mod cloze;
+pub mod config;
+pub mod corpus;
+pub mod coverage;
+pub mod deck;
+pub mod error;
+pub mod file_db;
+pub mod grammar;
+pub mod normalize;
+pub mod pinyin;
+pub mod segment;
+pub mod store;
+#[cfg(feature = "audio")]
+pub mod tts;
+pub mod vocab;
+pub mod wiki;
+
+pub use error::{Error, Result};
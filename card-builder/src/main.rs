@@ -1,103 +1,79 @@
-use self::file_db::FileDbWriter;
-use ahash::HashMap;
-use anyhow::Result;
-use file_db::FileDbReader;
-use genanki_rs::{Deck, Field, Model, ModelType, Note, Template};
-use indexical::{define_index_type, map::DenseRefIndexMap, IndexedDomain};
-use indicatif::{ProgressBar, ProgressIterator, ProgressStyle};
-use jieba_rs::Jieba;
-use rand::{seq::SliceRandom, thread_rng};
+use ahash::{HashMap, HashSet};
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "audio")]
+use card_builder::tts::{AudioCache, CommandBackend};
+use card_builder::{
+  chengyu::is_chengyu,
+  cloze::{
+    cloze_ratio, escape_text, fill_hole, join_delimited, phrase_at_edge, phrase_location,
+    sentence_delimiters, split_delimited, token_starts,
+  },
+  config::{CardStyle, Config, NoteTemplateConfig},
+  corpus::{
+    clean_text, context, input_entries, latin_ratio, mark_markup, passages, sentence_is_meaningful,
+    strip_markup, CorpusFormat, CorpusInput,
+  },
+  coverage::CoverageReport,
+  deck::{
+    build_card, build_passage_card, cloze_field, passage_field, Card, CardModels, CardOptions,
+    CardTemplate, CardTemplates, CardType, NoteKind, Snippet, SnippetOrigin, CSS,
+  },
+  file_db::{FileDbConfig, FileDbReader, ShardedFileDbReader, DEFAULT_CACHE_CAPACITY},
+  normalize::{normalize, PunctuationWidth},
+  segment::Segmenter,
+  store::{BuildCursor, FileStore, FileStorePaths, OpenShard, SnippetStore, SqliteStore, UsedKeys},
+  vocab::{
+    read_frequency_list, read_vocab, DedupPolicy, Matcher, PartOfSpeech, PhraseIdx, UnknownWords,
+    Vocab, VocabFormat, VocabLevel, VocabPhrase, VocabSource,
+  },
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use genanki_rs::{Deck, Package};
+use indicatif::{MultiProgress, ProgressBar, ProgressIterator, ProgressStyle};
+use itertools::Itertools;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use regex::Regex;
-use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-  fs::File,
-  io::{BufRead, BufReader},
-  ops::Range,
-  sync::LazyLock,
+  cmp::{Ordering, Reverse},
+  collections::BTreeMap,
+  fs::{self, File},
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  ops::RangeInclusive,
+  path::{Path, PathBuf},
+  time::Instant,
 };
+use tracing::{debug, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
 use unicode_segmentation::UnicodeSegmentation;
 
-mod file_db;
+/// The default corpus, in the data directory.
+const CORPUS_PATHS: &[&str] = &[
+  "corpus/part-0000.jsonl",
+  "corpus/part-0001.jsonl",
+  "corpus/part-0002.jsonl",
+  "corpus/part-0003.jsonl",
+  "corpus/part-0004.jsonl",
+  "corpus/part-0005.jsonl",
+];
 
-#[derive(PartialEq, Debug, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
-struct HskLevel(usize);
+/// Base path of the snippet database, which is split into one shard file
+/// per corpus input, e.g. `phrases.shard-0.txt`.
+const PHRASES_PATH: &str = "phrases.txt";
+const INDEX_PATH: &str = "phrases.idx";
+const CHECKPOINT_PATH: &str = "phrases.checkpoint";
+/// The snippet database with `--store sqlite`.
+const SQLITE_PATH: &str = "snippets.sqlite";
+const DECKS_DIR: &str = "decks";
 
-fn parse_level<'de, D>(deserializer: D) -> Result<HskLevel, D::Error>
-where
-  D: Deserializer<'de>,
-{
-  let buf = String::deserialize(deserializer)?;
-  if buf == "7-9" {
-    Ok(HskLevel(7))
-  } else {
-    match buf.parse::<usize>() {
-      Ok(n) => Ok(HskLevel(n)),
-      Err(e) => Err(serde::de::Error::custom(e)),
-    }
+fn file_store_paths(data_dir: &Path) -> FileStorePaths {
+  FileStorePaths {
+    db: data_dir.join(PHRASES_PATH),
+    index: data_dir.join(INDEX_PATH),
+    checkpoint: data_dir.join(CHECKPOINT_PATH),
   }
 }
 
-#[derive(Deserialize, Hash, PartialEq, Eq, Clone)]
-struct HskPhrase {
-  #[serde(rename = "Simplified")]
-  simplified: String,
-  #[serde(rename = "Level", deserialize_with = "parse_level")]
-  level: HskLevel,
-}
-
-fn hsk_levels() -> impl DoubleEndedIterator<Item = HskLevel> {
-  (1..=7).map(HskLevel)
-}
-
-define_index_type! {
-  struct PhraseIdx for HskPhrase = u16;
-}
-
-#[derive(Deserialize)]
-struct CorpusEntry {
-  text: String,
-  score: f64,
-}
-
-const HSK_PATH: &str = "../hsk30-expanded.csv";
-
-struct Hsk {
-  phrases: IndexedDomain<HskPhrase>,
-  levels: HashMap<HskLevel, HashMap<String, PhraseIdx>>,
-}
-
-fn read_hsk() -> Result<Hsk> {
-  let file = BufReader::new(File::open(HSK_PATH)?);
-  let mut rdr = csv::Reader::from_reader(file);
-  let phrases = rdr
-    .deserialize()
-    .map(|r| r.map_err(Into::into))
-    .collect::<Result<Vec<HskPhrase>>>()?;
-  let phrases = IndexedDomain::from_iter(phrases);
-  let levels = hsk_levels()
-    .map(|level| {
-      let level_phrases = phrases
-        .iter_enumerated()
-        .filter(|(_, phrase)| phrase.level == level)
-        .map(|(idx, phrase)| (phrase.simplified.clone(), idx))
-        .collect::<HashMap<_, _>>();
-      (level, level_phrases)
-    })
-    .collect::<HashMap<_, _>>();
-  Ok(Hsk { phrases, levels })
-}
-
-const CORPUS_PATHS: &[&str] = &[
-  "../corpus/part-0000.jsonl",
-  "../corpus/part-0001.jsonl",
-  "../corpus/part-0002.jsonl",
-  "../corpus/part-0003.jsonl",
-  "../corpus/part-0004.jsonl",
-  "../corpus/part-0005.jsonl",
-];
-
-const PHRASES_PATH: &str = "../phrases.txt";
-
 fn progress_bar(count: usize) -> ProgressBar {
   ProgressBar::new(count as u64).with_style(
     ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {eta}")
@@ -105,261 +81,2228 @@ fn progress_bar(count: usize) -> ProgressBar {
   )
 }
 
-fn split_sentences(text: &'_ str) -> Vec<&'_ str> {
-  static RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[。！？]+").unwrap());
-  RE.split(text)
-    .filter(|s| !s.is_empty())
-    .map(|s| s.trim())
-    .collect()
-}
-
+/// Default lowest corpus score an entry can have and still be read.
 const SCORE_THRESHOLD: f64 = 0.8;
+/// How often, in corpus lines, to check whether every phrase has enough snippets.
+const CHECK_INTERVAL: usize = 10_000;
+/// How often, in entries of one corpus input, to checkpoint the build for
+/// `--resume`.
+const CHECKPOINT_INTERVAL: usize = 100_000;
+/// Multiple of `--snippets-per-phrase` past which a phrase stops collecting.
+const SNIPPET_CEILING_FACTOR: usize = 5;
+/// Default shortest sentence, in graphemes, that can become a card.
 const LEN_THRESHOLD: usize = 10;
+/// Share of words outside the word list past which a sentence ranks lower.
+const MAX_UNKNOWN_RATIO: f32 = 0.4;
+const DEFAULT_SNIPPETS_PER_PHRASE: usize = 50;
+const DEFAULT_CARDS_PER_DECK: usize = 50;
 
-impl Hsk {
-  fn analyze<'a>(&'a self, words: &'a [&str]) -> Option<impl Iterator<Item = PhraseIdx> + 'a> {
-    let word_analysis = words
-      .iter()
-      .map(|word| {
-        hsk_levels()
-          .rev()
-          .find_map(|level| Some((level, *self.levels[&level].get(*word)?)))
-      })
-      .collect::<Option<Vec<_>>>()?;
-    let level = *word_analysis.iter().map(|(level, _)| level).max()?;
-    Some(
-      word_analysis
-        .into_iter()
-        .map(|(_, idx)| idx)
-        .filter(move |idx| self.phrases.value(*idx).level == level),
+/// The phrases among `phrases` at one of `levels` still collecting snippets.
+fn wanted_phrases(
+  vocab: &Vocab,
+  store: &mut impl SnippetStore,
+  phrases: impl Iterator<Item = PhraseIdx>,
+  levels: &[VocabLevel],
+  ceiling: usize,
+) -> Result<Vec<PhraseIdx>> {
+  let mut wanted = Vec::new();
+  for idx in phrases.unique() {
+    if levels.contains(&vocab.phrases.value(idx).level) && store.snippet_count(idx)? < ceiling {
+      wanted.push(idx);
+    }
+  }
+  Ok(wanted)
+}
+
+/// Phrases at one of `levels` that can still be targets but have fewer than
+/// `target` snippets.
+fn under_target(
+  vocab: &Vocab,
+  store: &mut impl SnippetStore,
+  levels: &[VocabLevel],
+  target: usize,
+) -> Result<Vec<PhraseIdx>> {
+  let mut under = Vec::new();
+  for idx in vocab.phrases.indices() {
+    if !vocab.is_known(idx)
+      && levels.contains(&vocab.phrases.value(idx).level)
+      && store.snippet_count(idx)? < target
+    {
+      under.push(idx);
+    }
+  }
+  Ok(under)
+}
+
+struct CorpusOptions {
+  inputs: Vec<CorpusInput>,
+  width: PunctuationWidth,
+  matcher: Matcher,
+  /// Index each sentence under all of its phrases rather than only those
+  /// at its hardest level.
+  every_level: bool,
+  context_sentences: usize,
+  max_latin_ratio: Option<f64>,
+  min_score: f64,
+  min_length: usize,
+  max_length: Option<usize>,
+  /// Entries read from each input before moving on to the next.
+  max_lines: Option<usize>,
+  snippets_per_phrase: usize,
+  /// Only phrases at these levels collect snippets.
+  levels: Vec<VocabLevel>,
+  card_style: CardStyle,
+  resume: bool,
+  db_config: FileDbConfig,
+  sentence_delimiters: Regex,
+}
+
+impl CorpusOptions {
+  /// Everything besides the inputs and word list that decides which
+  /// snippets a build writes, so a checkpoint isn't resumed with others.
+  fn fingerprint(&self) -> String {
+    format!(
+      "{:?}",
+      (
+        (self.width, self.matcher, self.every_level),
+        (self.context_sentences, self.max_latin_ratio, self.min_score),
+        (self.min_length, self.max_length, self.max_lines),
+        (self.snippets_per_phrase, &self.levels, self.card_style),
+        self.sentence_delimiters.as_str(),
+      )
     )
   }
 }
 
-#[derive(Deserialize, Serialize)]
-struct Snippet {
-  prefix: Option<String>,
-  sentence: String,
-  suffix: Option<String>,
+/// What happened to the entries and sentences of one corpus input, logged
+/// when it's finished.
+#[derive(Default)]
+struct InputStats {
+  entries: usize,
+  malformed: usize,
+  low_score: usize,
+  /// Sentences dropped as markup, too short to mean anything, or too Latin.
+  filtered: usize,
+  /// Sentences without a word-list phrase that isn't known.
+  unanalyzable: usize,
+  /// Sentences outside the length limits.
+  bad_length: usize,
+  snippets: usize,
 }
 
-type PhraseFileIndex<'a> = DenseRefIndexMap<'a, HskPhrase, Vec<Range<u64>>>;
+/// Reads the corpus inputs from where `resume` left off, writing snippets of the
+/// phrases they contain to `store`.
+fn build_corpus<S: SnippetStore>(
+  vocab: &Vocab,
+  segmenter: &mut Segmenter,
+  opts: &CorpusOptions,
+  store: &mut S,
+  resume: BuildCursor,
+) -> Result<()> {
+  // Common phrases stop collecting snippets at a ceiling well above the
+  // target, which keeps the database bounded; the scan ends once every phrase
+  // that can still be a target has reached the target.
+  let target = opts.snippets_per_phrase;
+  let ceiling = target * SNIPPET_CEILING_FACTOR;
+  let mut entries_read = 0;
+  let mut satisfied = false;
+  let corpus_start = Instant::now();
 
-fn build_corpus(hsk: &'_ Hsk) -> Result<PhraseFileIndex<'_>> {
-  let mut phrase_map = DenseRefIndexMap::new(&&hsk.phrases, |_| Vec::<Range<u64>>::default());
+  // One bar counts finished inputs, and one below it the bytes read of the
+  // current input.
+  let multi = MultiProgress::new();
+  let files_progress = multi.add(progress_bar(opts.inputs.len()));
+  files_progress.inc(resume.input as u64);
 
-  let mut db_writer = FileDbWriter::new(PHRASES_PATH)?;
+  for (i, input) in opts.inputs.iter().enumerate().skip(resume.input) {
+    let source = &input.source;
+    let _span = info_span!("read_corpus", %source).entered();
+    let start = Instant::now();
+    match source.path() {
+      Some(path) => segmenter.load_cache(path)?,
+      None => segmenter.unload_cache(),
+    }
+    let bytes_progress = multi.add(match source.len()? {
+      Some(len) => ProgressBar::new(len).with_style(
+        ProgressStyle::with_template(
+          "[{elapsed_precise}] {bar:40.green/white} {bytes:>9}/{total_bytes:9} {eta} {msg}",
+        )
+        .unwrap(),
+      ),
+      None => ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template("[{elapsed_precise}] {spinner} {bytes} {msg}").unwrap(),
+      ),
+    });
+    bytes_progress.set_message(source.to_string());
+    let entries = input_entries(input, &bytes_progress);
+    let mut stats = InputStats::default();
+    store.start_input(i)?;
 
-  let segmenter = Jieba::new();
-  for path in CORPUS_PATHS
-    .iter()
-    .progress_with(progress_bar(CORPUS_PATHS.len()))
-  {
-    let file = BufReader::new(File::open(path)?);
+    let max_lines = opts.max_lines.unwrap_or(usize::MAX);
+    let entries_done = match i == resume.input {
+      true => resume.entries,
+      false => 0,
+    };
+    for (entry_no, entry_res) in entries.take(max_lines).enumerate().skip(entries_done) {
+      if entry_no > entries_done && entry_no % CHECKPOINT_INTERVAL == 0 {
+        store.checkpoint(BuildCursor {
+          input: i,
+          entries: entry_no,
+        })?;
+      }
+      entries_read += 1;
+      if entries_read % CHECK_INTERVAL == 0
+        && under_target(vocab, store, &opts.levels, target)?.is_empty()
+      {
+        satisfied = true;
+        break;
+      }
 
-    for line_res in file.lines().take(100000) {
-      let line = line_res?;
-      let entry: CorpusEntry = serde_json::from_str(&line)?;
-      if entry.score < SCORE_THRESHOLD {
+      let entry = match entry_res? {
+        Ok(entry) => entry,
+        Err(err) => {
+          let err = anyhow::Error::from(err);
+          warn!(
+            entry = entry_no + 1,
+            err = format!("{err:#}"),
+            "skipping malformed corpus entry"
+          );
+          stats.malformed += 1;
+          continue;
+        }
+      };
+      stats.entries += 1;
+      if entry.score < opts.min_score {
+        debug!(
+          entry = entry_no + 1,
+          score = entry.score,
+          "skipping low-score entry"
+        );
+        stats.low_score += 1;
         continue;
       }
 
-      let text = html_escape::encode_safe(&entry.text);
-      let sentences = split_sentences(text.as_ref());
+      let text = normalize(&clean_text(&mark_markup(&entry.text)), opts.width);
+      let sentences = split_delimited(&text, &opts.sentence_delimiters);
 
+      // Every phrase in the entry's sentences, for corpus frequencies, even
+      // in sentences that can't be snippets.
+      let mut occurring = Vec::new();
       let sentence_analysis = sentences
         .into_iter()
-        .map(|sentence| {
-          let words = segmenter.cut(sentence, false);
-          let phrases = hsk.analyze(&words)?.collect::<Vec<_>>();
-          Some((sentence, phrases))
+        .map(|(sentence, delimiter)| {
+          let Some(sentence) = strip_markup(sentence).filter(|sentence| {
+            sentence_is_meaningful(sentence)
+              && !opts
+                .max_latin_ratio
+                .is_some_and(|max| latin_ratio(sentence) > max)
+          }) else {
+            stats.filtered += 1;
+            return None;
+          };
+          let words = segmenter.cut(&sentence);
+          occurring.extend(vocab.phrases_in(&words));
+          // Words outside the word list don't reject the sentence, but its
+          // snippets rank lower the more of them it has.
+          let unknown = UnknownWords::Skip;
+          let phrases = match (opts.matcher, opts.every_level) {
+            (Matcher::Jieba, false) => vocab
+              .analyze(&words, unknown)
+              .map(|phrases| phrases.collect()),
+            (Matcher::Aho, false) => vocab.analyze_matches(&sentence, &words, unknown),
+            (Matcher::Jieba, true) => vocab
+              .analyze_all(&words, unknown)
+              .map(|phrases| phrases.into_iter().map(|(idx, _)| idx).collect()),
+            (Matcher::Aho, true) => vocab
+              .analyze_matches_all(&sentence, &words, unknown)
+              .map(|phrases| phrases.into_iter().map(|(idx, _)| idx).collect()),
+          };
+          let Some(phrases) = phrases else {
+            stats.unanalyzable += 1;
+            return None;
+          };
+          let tokens = token_starts(&words);
+          let unknown_ratio = vocab.unknown_ratio(&words);
+          Some((sentence, delimiter, phrases, tokens, unknown_ratio))
         })
         .collect::<Vec<_>>();
+      store.count_occurrences(&occurring)?;
+      let join = |sentences: &[Option<(String, &str, _, _, _)>]| {
+        join_delimited(
+          sentences
+            .iter()
+            .flatten()
+            .map(|(sentence, delimiter, ..)| (sentence.as_str(), *delimiter)),
+        )
+      };
+
+      if opts.card_style == CardStyle::ReadingPassage {
+        for passage in passages(&sentence_analysis) {
+          let phrases = passage
+            .iter()
+            .flatten()
+            .flat_map(|(_, _, phrases, _, _)| phrases)
+            .copied();
+          let wanted = wanted_phrases(vocab, store, phrases, &opts.levels, ceiling)?;
+          if wanted.is_empty() {
+            continue;
+          }
+
+          let unknown_ratio = passage
+            .iter()
+            .flatten()
+            .map(|(.., ratio)| ratio)
+            .sum::<f32>()
+            / passage.len() as f32;
+          let sentence = join(passage);
+          let passage = passage
+            .iter()
+            .flatten()
+            .map(|(sentence, ..)| sentence.clone())
+            .collect::<Vec<_>>();
+          let snippet = Snippet {
+            sentence,
+            prefix: None,
+            suffix: None,
+            translation: entry.translation.clone(),
+            source: Some(input.label.clone()),
+            passage,
+            tokens: Vec::new(),
+            source_file: Some(source.to_string()),
+            source_line: entry.line,
+            source_id: entry.id.clone(),
+            score: Some(entry.score),
+            unknown_ratio,
+          };
+
+          store.write(&snippet, &wanted)?;
+          stats.snippets += 1;
+        }
+        continue;
+      }
 
       for i in 0..sentence_analysis.len() {
-        let Some((sentence, phrases)) = &sentence_analysis[i] else {
+        let Some((sentence, _, phrases, tokens, unknown_ratio)) = &sentence_analysis[i] else {
           continue;
         };
 
-        if sentence.graphemes(true).count() < LEN_THRESHOLD {
+        let len = sentence.graphemes(true).count();
+        if len < opts.min_length || opts.max_length.is_some_and(|max| len > max) {
+          stats.bad_length += 1;
           continue;
         }
 
-        let prefix = if i > 0 {
-          sentence_analysis[i - 1]
-            .as_ref()
-            .map(|(sentence, _)| sentence.to_string())
-        } else {
-          None
-        };
+        let (before, after) = context(&sentence_analysis, i, opts.context_sentences);
+        let prefix = (!before.is_empty()).then(|| join(before));
+        let suffix = (!after.is_empty()).then(|| join(after));
 
-        let suffix = if i < sentence_analysis.len() - 1 {
-          sentence_analysis[i + 1]
-            .as_ref()
-            .map(|(sentence, _)| sentence.to_string())
-        } else {
-          None
-        };
+        let wanted = wanted_phrases(vocab, store, phrases.iter().copied(), &opts.levels, ceiling)?;
+        if wanted.is_empty() {
+          continue;
+        }
 
         let snippet = Snippet {
           sentence: sentence.to_string(),
           prefix,
           suffix,
+          translation: entry.translation.clone(),
+          source: Some(input.label.clone()),
+          passage: Vec::new(),
+          tokens: tokens.clone(),
+          source_file: Some(source.to_string()),
+          source_line: entry.line,
+          source_id: entry.id.clone(),
+          score: Some(entry.score),
+          unknown_ratio: *unknown_ratio,
         };
 
-        let range = db_writer.write(&snippet)?;
+        store.write(&snippet, &wanted)?;
+        stats.snippets += 1;
+      }
+    }
+
+    store.finish_input(i)?;
+    segmenter.save_cache()?;
+    info!(
+      elapsed = ?start.elapsed(),
+      entries = stats.entries,
+      malformed = stats.malformed,
+      low_score = stats.low_score,
+      filtered = stats.filtered,
+      unanalyzable = stats.unanalyzable,
+      bad_length = stats.bad_length,
+      snippets = stats.snippets,
+      "finished corpus file"
+    );
+    bytes_progress.finish_and_clear();
+    files_progress.inc(1);
+    if satisfied {
+      info!(entries_read, "every phrase reached its snippet target");
+      break;
+    }
+  }
+  files_progress.finish();
+  info!(
+    elapsed = ?corpus_start.elapsed(),
+    entries_read,
+    "finished reading corpus"
+  );
 
-        for idx in phrases {
-          phrase_map[*idx].push(range.clone());
+  if !satisfied {
+    let remaining = under_target(vocab, store, &opts.levels, target)?;
+    if !remaining.is_empty() {
+      warn!(
+        count = remaining.len(),
+        phrases = remaining
+          .iter()
+          .map(|idx| vocab.phrases.value(*idx).form.as_str())
+          .join(" "),
+        "corpus ran out before these phrases reached {target} snippets"
+      );
+    }
+  }
+
+  Ok(store.finish()?)
+}
+
+/// Ranks word-list forms by how often they occurred in the corpus, most
+/// first; forms with equal counts share a rank. Forms that never occurred are
+/// left out.
+fn corpus_frequency_ranks<'a>(vocab: &'a Vocab, occurrences: &[usize]) -> HashMap<&'a str, usize> {
+  let mut counts: HashMap<&str, usize> = HashMap::default();
+  for (phrase, occurrences) in vocab.phrases.iter().zip(occurrences) {
+    *counts.entry(phrase.form.as_str()).or_default() += occurrences;
+  }
+  let mut counts = counts
+    .into_iter()
+    .filter(|(_, count)| *count > 0)
+    .collect::<Vec<_>>();
+  counts.sort_by_key(|(_, count)| Reverse(*count));
+  let mut ranks = HashMap::default();
+  let mut rank = 0;
+  for (i, (form, count)) in counts.iter().enumerate() {
+    if i == 0 || counts[i - 1].1 != *count {
+      rank = i + 1;
+    }
+    ranks.insert(*form, rank);
+  }
+  ranks
+}
+
+/// Which backend holds the snippet database.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum StoreKind {
+  /// Sharded flat files plus phrases.idx.
+  File,
+  /// snippets.sqlite, which other tools can query by phrase.
+  Sqlite,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Toggle {
+  On,
+  Off,
+}
+
+/// Order in which a level's phrases take turns claiming cards.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PhraseOrder {
+  /// The order of the word list.
+  Csv,
+  /// Phrases with the most corpus snippets first.
+  Frequency,
+  /// Phrases most common in `--frequency-list` first, then unlisted ones.
+  Rank,
+}
+
+/// How snippets that rank equally for a phrase are ordered.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Tiebreak {
+  /// Higher corpus scores first.
+  Score,
+  /// Shorter sentences first, counted in graphemes like the length limits.
+  Length,
+  /// A random order, different on every run.
+  Random,
+}
+
+/// What a build writes once snippets are selected.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+  /// Anki packages in the decks directory.
+  Apkg,
+  /// A JSON array of the selected cards on stdout.
+  Json,
+}
+
+/// How snippets are chosen for each level's deck.
+struct SelectionOptions {
+  edge_penalty: bool,
+  allow_cross_level_repeat: bool,
+  approx_dedup: bool,
+  cards_per_deck: usize,
+  phrase_order: PhraseOrder,
+  /// Ranking multipliers by corpus source label, then level.
+  source_weights: HashMap<String, BTreeMap<usize, f64>>,
+  card_style: CardStyle,
+  tiebreak: Tiebreak,
+  /// Smallest share of a sentence's graphemes its cloze may hide.
+  min_cloze_ratio: Option<f64>,
+  /// Seeds the random tiebreak, so a build can be repeated exactly.
+  seed: Option<u64>,
+}
+
+/// Chooses the snippets that become cards in `level`'s deck, followed by a
+/// fallback snippet for each phrase the corpus never covered.
+///
+/// Levels must be selected in ascending order with the same `used_sentences`,
+/// which keeps a sentence used by one level out of the levels above it, even
+/// when another document has it too.
+fn select_snippets<'a, S: SnippetStore>(
+  vocab: &'a Vocab,
+  store: &mut S,
+  level: VocabLevel,
+  opts: &SelectionOptions,
+  used_sentences: &mut UsedKeys<String>,
+) -> Result<Vec<(Snippet, &'a VocabPhrase, SnippetOrigin)>> {
+  let rank = |snippet: &Snippet, phrase: &VocabPhrase| {
+    let mut score = 1.;
+    if snippet.prefix.is_some() {
+      score += 1.;
+    }
+    if snippet.suffix.is_some() {
+      score += 1.;
+    }
+    // Sentences mostly made of words outside the word list are kept, but
+    // only picked when nothing easier is left.
+    if snippet.unknown_ratio > MAX_UNKNOWN_RATIO {
+      score /= 2.;
+    }
+    let weight = snippet
+      .source
+      .as_ref()
+      .and_then(|source| opts.source_weights.get(source)?.get(&level.0))
+      .unwrap_or(&1.);
+    let at_edge =
+      opts.edge_penalty && phrase_at_edge(&snippet.sentence, &phrase.form, &snippet.tokens);
+    (score * weight, at_edge)
+  };
+
+  // Seeded per level, so a level's selection doesn't depend on how many
+  // levels came before it.
+  let mut rng = match opts.seed {
+    Some(seed) => StdRng::seed_from_u64(seed ^ level.0 as u64),
+    None => StdRng::from_entropy(),
+  };
+  let mut candidates = Vec::new();
+  let mut uncovered = Vec::new();
+  let (mut candidate_snippets, mut repeats, mut too_guessable) = (0, 0, 0);
+  for (phrase_idx, phrase) in vocab.phrases.iter_enumerated() {
+    if phrase.level != level {
+      continue;
+    }
+    let ranges = store.ranges_for(phrase_idx)?;
+    if ranges.is_empty() {
+      uncovered.push(phrase_idx);
+      continue;
+    }
+    let total = ranges.len();
+    let snippets = store.read_many::<Snippet>(&ranges)?;
+    let mut snippets = ranges
+      .into_iter()
+      .zip(snippets)
+      .filter(|(_, snippet)| !used_sentences.contains(&snippet.sentence))
+      .collect::<Vec<_>>();
+    candidate_snippets += total;
+    repeats += total - snippets.len();
+    // A passage clozes several phrases, so one phrase's share of it says
+    // little about how guessable its cards are.
+    if let (Some(min), CardStyle::Sentence) = (opts.min_cloze_ratio, opts.card_style) {
+      let before = snippets.len();
+      snippets.retain(|(_, snippet)| cloze_ratio(&snippet.sentence, &phrase.form) >= min);
+      too_guessable += before - snippets.len();
+      if before > 0 && snippets.is_empty() {
+        warn!(
+          level = level.0,
+          phrase = phrase.form,
+          snippets = before,
+          "every snippet of phrase is below --min-cloze-ratio"
+        );
+        uncovered.push(phrase_idx);
+        continue;
+      }
+    }
+    if opts.tiebreak == Tiebreak::Random {
+      snippets.shuffle(&mut rng);
+    }
+    // The sort is stable, so without a shuffle any remaining ties keep
+    // their order in the database.
+    snippets.sort_by(|(_, a), (_, b)| {
+      let ((a_score, a_edge), (b_score, b_edge)) = (rank(a, phrase), rank(b, phrase));
+      let tie = match opts.tiebreak {
+        Tiebreak::Score => b.score.unwrap_or(1.).total_cmp(&a.score.unwrap_or(1.)),
+        Tiebreak::Length => {
+          let len = |snippet: &Snippet| snippet.sentence.graphemes(true).count();
+          len(a).cmp(&len(b))
         }
+        Tiebreak::Random => Ordering::Equal,
+      };
+      b_score
+        .total_cmp(&a_score)
+        .then(a_edge.cmp(&b_edge))
+        .then(a.unknown_ratio.total_cmp(&b.unknown_ratio))
+        .then(tie)
+    });
+    // The same sentence can come from several documents, but it only makes
+    // one card for the phrase: the best-ranked copy.
+    let mut sentences = HashSet::default();
+    snippets.retain(|(_, snippet)| sentences.insert(snippet.sentence.clone()));
+    candidates.push((total, phrase, snippets.into_iter()));
+  }
+  info!(
+    level = level.0,
+    candidates = candidate_snippets,
+    repeats,
+    "candidate snippets, less those used by lower levels"
+  );
+  if too_guessable > 0 {
+    info!(
+      level = level.0,
+      snippets = too_guessable,
+      "dropped snippets below --min-cloze-ratio"
+    );
+  }
+  if opts.phrase_order == PhraseOrder::Frequency {
+    candidates.sort_by_key(|(total, _, _)| Reverse(*total));
+  }
+  if opts.phrase_order == PhraseOrder::Rank {
+    candidates.sort_by_key(|(_, phrase, _)| phrase.freq_rank.unwrap_or(u32::MAX));
+  }
+
+  // Round-robin over phrases: each pass takes the next-best snippet of every
+  // phrase that still has one, so the budget is spread across phrases
+  // instead of going to whichever have the most snippets.
+  // A sentence makes one card per level, for whichever phrase reaches it
+  // first. A passage clozes every phrase it contains, so it only needs one
+  // card however many phrases reached it.
+  let mut selected = Vec::new();
+  let mut level_sentences = Vec::new();
+  let mut sentences_taken = HashSet::default();
+  let mut passages_taken = HashSet::default();
+  let mut cards_per_phrase = vec![0; candidates.len()];
+  'passes: loop {
+    let mut progressed = false;
+    for ((_, phrase, snippets), count) in candidates.iter_mut().zip(&mut cards_per_phrase) {
+      if selected.len() == opts.cards_per_deck {
+        break 'passes;
       }
+      let next = match opts.card_style {
+        CardStyle::Sentence => {
+          snippets.find(|(_, snippet)| sentences_taken.insert(snippet.sentence.clone()))
+        }
+        CardStyle::ReadingPassage => {
+          snippets.find(|(range, _)| passages_taken.insert(range.clone()))
+        }
+      };
+      if let Some((_, snippet)) = next {
+        if !opts.allow_cross_level_repeat {
+          level_sentences.push(snippet.sentence.clone());
+        }
+        selected.push((snippet, *phrase, SnippetOrigin::Corpus));
+        *count += 1;
+        progressed = true;
+      }
+    }
+    if !progressed {
+      break;
     }
   }
+  used_sentences.extend(level_sentences);
+  // Once the deck is full, phrases later in the order may have been left
+  // with unused snippets and no card at all.
+  for ((_, phrase, snippets), count) in candidates.iter().zip(&cards_per_phrase) {
+    if *count > 0 {
+      info!(
+        level = level.0,
+        phrase = phrase.form,
+        cards = count,
+        "selected phrase"
+      );
+    } else if snippets.len() > 0 {
+      warn!(
+        level = level.0,
+        phrase = phrase.form,
+        snippets = snippets.len(),
+        "phrase has snippets but no card; the deck reached --cards-per-deck"
+      );
+    }
+  }
+  cards_per_phrase.sort_unstable();
+  if let (Some(min), Some(max)) = (cards_per_phrase.first(), cards_per_phrase.last()) {
+    info!(
+      level = level.0,
+      min,
+      max,
+      median = cards_per_phrase[cards_per_phrase.len() / 2],
+      "corpus cards per phrase"
+    );
+  }
+
+  // Phrases the corpus never covered still get a card from the word list's
+  // own example sentence, tagged so they can be told apart.
+  let fallbacks = uncovered
+    .into_iter()
+    .filter(|idx| !vocab.is_known(*idx))
+    .filter_map(|idx| {
+      let phrase = vocab.phrases.value(idx);
+      let example = phrase.example.as_ref()?;
+      if !example.contains(&phrase.form) {
+        return None;
+      }
+      let snippet = Snippet {
+        prefix: None,
+        sentence: example.clone(),
+        suffix: None,
+        translation: None,
+        source: None,
+        passage: Vec::new(),
+        tokens: Vec::new(),
+        source_file: None,
+        source_line: None,
+        source_id: None,
+        score: None,
+        unknown_ratio: 0.,
+      };
+      Some((snippet, phrase, SnippetOrigin::Fallback))
+    });
+  selected.extend(fallbacks);
+
+  Ok(selected)
+}
 
-  for idx in hsk.phrases.indices() {
-    phrase_map[idx].dedup();
+const DECK_DESCRIPTION: &str = "Corpus-generated Chinese Cloze cards";
+/// Deck IDs are the base, the level, and the subdeck, each given this many
+/// values, so no two decks share an ID however many levels there are.
+const DECK_ID_STRIDE: usize = 1000;
+/// Subdeck of a level's chengyu deck, past every part of speech.
+const CHENGYU_SUBDECK: usize = 99;
+/// Subdeck of the `--combined-deck` deck, with level 0, past every subdeck
+/// a level has.
+const COMBINED_DECK: usize = DECK_ID_STRIDE - 1;
+
+/// Where in a level's deck a note goes.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Subdeck {
+  /// The level deck itself.
+  Level,
+  Pos(PartOfSpeech),
+  Chengyu,
+}
+
+impl Subdeck {
+  /// The subdeck's part of its deck ID.
+  fn id_offset(self) -> usize {
+    match self {
+      Subdeck::Level => 0,
+      Subdeck::Pos(pos) => pos.ordinal(),
+      Subdeck::Chengyu => CHENGYU_SUBDECK,
+    }
   }
+}
 
-  Ok(phrase_map)
+/// The ID of a deck, checked by [`check_deck_ids`] not to overflow.
+fn deck_id(base: usize, level: usize, subdeck: usize) -> i64 {
+  ((base * DECK_ID_STRIDE + level) * DECK_ID_STRIDE + subdeck) as i64
 }
 
-const MODEL_ID: i64 = 1122338855;
+/// Fails if a deck of `levels` couldn't get its own ID.
+fn check_deck_ids(base: usize, levels: &[VocabLevel]) -> Result<()> {
+  if let Some(level) = levels.iter().find(|level| level.0 >= DECK_ID_STRIDE) {
+    bail!(
+      "level {} is too high to number its decks; levels must be below {DECK_ID_STRIDE}",
+      level.0
+    );
+  }
+  let fits = base
+    .checked_mul(DECK_ID_STRIDE * DECK_ID_STRIDE)
+    .and_then(|id| i64::try_from(id).ok())
+    .is_some_and(|id| {
+      id.checked_add((DECK_ID_STRIDE * DECK_ID_STRIDE) as i64)
+        .is_some()
+    });
+  if !fits {
+    bail!("deck id base {base} is too large");
+  }
+  Ok(())
+}
 
-static MODEL: LazyLock<Model> = LazyLock::new(|| {
-  Model::new_with_options(
-    MODEL_ID,
-    "Cloze (zhlearn)",
-    vec![
-        Field::new("Sentence"),
-        Field::new("Prefix"),
-        Field::new("Suffix"),
-    ],
-    vec![
-        Template::new("Cloze")
-            .qfmt("<div class=context>{{Prefix}}</div> {{cloze:Sentence}} <div class=context>{{Suffix}}</div>")
-            .afmt("<div class=context>{{Prefix}}</div> {{cloze:Sentence}} <div class=context>{{Suffix}}</div>"),
-    ],
-    Some(
-        r#"
-.card {
-  font-family: arial;
-  font-size: 24px;
-  text-align: center;
-  color: black;
-  background-color: white;
+struct DeckOptions {
+  card: CardOptions,
+  single_package: bool,
+  chengyu_subdeck: bool,
+  media: Vec<PathBuf>,
+  selection: SelectionOptions,
+  /// The levels to build decks for.
+  levels: Vec<VocabLevel>,
+  format: OutputFormat,
+  /// Print what each level's deck would hold instead of writing it.
+  dry_run: bool,
+  /// Also write every level's cards into one flat deck.
+  combined_deck: bool,
+  /// Write a level's deck even when it has no cards.
+  allow_empty_decks: bool,
+  /// Base of the level decks' IDs.
+  deck_id_base: usize,
+  /// Where the .apkg files are written.
+  output_dir: PathBuf,
+  #[cfg(feature = "audio")]
+  audio: Option<AudioCache>,
 }
 
-.context {
-  font-size: 80%;
-  padding: 0.5rem 0;
+/// genanki takes paths as strings.
+fn path_str(path: &Path) -> Result<&str> {
+  path
+    .to_str()
+    .with_context(|| format!("{} is not valid UTF-8", path.display()))
 }
 
-.cloze {
-  font-weight: bold;
-  color: blue;
+fn media_paths(paths: &[PathBuf]) -> Result<Vec<&str>> {
+  paths.iter().map(|path| path_str(path)).collect()
 }
 
-.nightMode .cloze {color: lightblue;}"#,
-    ),
-    Some(ModelType::Cloze),
-    None,
-    None,
-    None,
-  )
-});
+/// The synthesized audio for `sentence`, if `--audio` is on and synthesis
+/// succeeded.
+#[cfg(feature = "audio")]
+fn sentence_audio(opts: &DeckOptions, sentence: &str) -> Option<PathBuf> {
+  opts.audio.as_ref()?.get(sentence)
+}
 
-fn make_cloze(sentence: &str, phrase: &str, loc: usize) -> String {
-  let mut sentence = sentence.to_string();
-  let hole = format!("{{{{c1::{phrase}}}}}");
-  sentence.replace_range(loc..loc + phrase.len(), &hole);
-  sentence
+#[cfg(not(feature = "audio"))]
+fn sentence_audio(_opts: &DeckOptions, _sentence: &str) -> Option<PathBuf> {
+  None
 }
 
-fn build_card(snippet: &Snippet, phrase: &str) -> Note {
-  let sentences = split_sentences(&snippet.sentence);
-  let (i, loc) = sentences
-    .iter()
-    .enumerate()
-    .find_map(|(i, s)| Some((i, s.find(phrase)?)))
-    .unwrap();
-  let cloze = make_cloze(sentences[i], phrase, loc);
-  Note::new(
-    MODEL.clone(),
-    vec![
-      &cloze,
-      snippet.prefix.as_deref().unwrap_or(""),
-      snippet.suffix.as_deref().unwrap_or(""),
-    ],
-  )
-  .unwrap()
+/// How many snippets `store` has for each phrase, in phrase order.
+fn snippet_counts(vocab: &Vocab, store: &mut impl SnippetStore) -> Result<Vec<usize>> {
+  vocab
+    .phrases
+    .indices()
+    .map(|idx| Ok(store.snippet_count(idx)?))
+    .collect()
 }
 
-const DECK_ID_BASE: usize = 881199;
+/// Logs a selected snippet that gets no card because the cloze can't find
+/// its phrase, as when a word-list example only has it across sentences.
+fn skip_unclozable(snippet: &Snippet, phrase: &VocabPhrase) {
+  warn!(
+    phrase = phrase.form,
+    sentence = snippet.sentence,
+    "phrase not found in its sentence, skipping card"
+  );
+}
 
-fn build_decks(hsk: &Hsk, file_index: &PhraseFileIndex) -> Result<()> {
-  let mut reader = FileDbReader::load(PHRASES_PATH)?;
+/// The field a card for `phrase` in `snippet` would hide its phrases in, for
+/// the card style and type of `opts`, or `None` if it would get no card.
+fn card_cloze(
+  vocab: &Vocab,
+  level: VocabLevel,
+  snippet: &Snippet,
+  phrase: &VocabPhrase,
+  opts: &DeckOptions,
+) -> Option<String> {
+  match opts.selection.card_style {
+    CardStyle::Sentence => {
+      let cloze = cloze_field(snippet, &phrase.form, &opts.card);
+      if cloze.is_none() {
+        skip_unclozable(snippet, phrase);
+      }
+      cloze
+    }
+    CardStyle::ReadingPassage => {
+      let cloze = passage_field(snippet, vocab, level);
+      if cloze.is_none() {
+        debug!(passage = snippet.sentence, "no phrases to cloze in passage");
+      }
+      cloze
+    }
+  }
+}
 
-  for level in hsk_levels().progress_with(progress_bar(7)) {
-    let phrase_iter = hsk
-      .phrases
-      .iter_enumerated()
-      .filter(|(_, phrase)| phrase.level == level);
+/// Prints the cards selected from `store` as JSON instead of building decks.
+fn print_cards<S: SnippetStore>(vocab: &Vocab, store: &mut S, opts: &DeckOptions) -> Result<()> {
+  let mut used_sentences = UsedKeys::new(opts.selection.approx_dedup);
+  let mut cards = Vec::new();
+  for level in opts.levels.iter().copied() {
+    for (snippet, phrase, _) in
+      select_snippets(vocab, store, level, &opts.selection, &mut used_sentences)?
+    {
+      if let Some(cloze) = card_cloze(vocab, level, &snippet, phrase, opts) {
+        cards.push(Card::new(&snippet, phrase, cloze));
+      }
+    }
+  }
+  let mut stdout = io::stdout().lock();
+  serde_json::to_writer_pretty(&mut stdout, &cards)?;
+  writeln!(stdout)?;
+  info!(count = cards.len(), "printed cards");
+  Ok(())
+}
 
-    let mut snippets = phrase_iter
-      .flat_map(|(phrase_idx, phrase)| {
-        file_index[phrase_idx]
-          .iter()
-          .map(|range| (reader.read::<Snippet>(range.clone()).unwrap(), phrase))
-          .collect::<Vec<_>>()
-      })
-      .collect::<Vec<_>>();
+/// Builds decks from the snippets in `store`.
+fn build_decks<S: SnippetStore>(vocab: &Vocab, store: &mut S, opts: &DeckOptions) -> Result<()> {
+  if opts.format == OutputFormat::Json {
+    return print_cards(vocab, store, opts);
+  }
+  if !opts.dry_run {
+    fs::create_dir_all(&opts.output_dir)
+      .with_context(|| format!("creating {}", opts.output_dir.display()))?;
+  }
+  check_deck_ids(opts.deck_id_base, &opts.levels)?;
+  let _span = info_span!("build_decks", source = vocab.source.name()).entered();
+  let decks_start = Instant::now();
+  let mut decks = Vec::new();
+  let mut dry_run_levels = Vec::new();
+  let mut combined = opts.combined_deck.then(|| {
+    Deck::new(
+      deck_id(opts.deck_id_base, 0, COMBINED_DECK),
+      &format!("{}::All levels", vocab.source.name()),
+      DECK_DESCRIPTION,
+    )
+  });
+  let mut combined_media = opts.media.clone();
+  let mut media = opts.media.clone();
+  let mut used_sentences = UsedKeys::new(opts.selection.approx_dedup);
+  let occurrences = vocab
+    .phrases
+    .indices()
+    .map(|idx| Ok(store.occurrences(idx)?))
+    .collect::<Result<Vec<_>>>()?;
+  let frequency_ranks = corpus_frequency_ranks(vocab, &occurrences);
+  let form_count = vocab
+    .phrases
+    .iter()
+    .map(|phrase| &phrase.form)
+    .unique()
+    .count();
 
-    snippets.shuffle(&mut thread_rng());
+  for level in opts
+    .levels
+    .iter()
+    .copied()
+    .progress_with(progress_bar(opts.levels.len()))
+  {
+    let level_start = Instant::now();
+    let selected = select_snippets(vocab, store, level, &opts.selection, &mut used_sentences)?;
+    if opts.dry_run {
+      let cards = selected
+        .iter()
+        .filter_map(|(snippet, phrase, _)| {
+          let cloze = card_cloze(vocab, level, snippet, phrase, opts)?;
+          Some(Card::new(snippet, phrase, cloze))
+        })
+        .collect::<Vec<_>>();
+      dry_run_levels.push(serde_json::json!({
+        "level": level.0,
+        "deck": format!("{}::{}", vocab.source.name(), vocab.source.level_name(level)),
+        "cards": cards,
+      }));
+      continue;
+    }
+    let mut notes = Vec::new();
+    let mut level_media = Vec::new();
+    let mut source_cards: BTreeMap<String, usize> = BTreeMap::new();
+    for (snippet, phrase, origin) in selected {
+      let audio = match sentence_audio(opts, &snippet.sentence) {
+        Some(path) => {
+          let field = format!("[sound:{}]", path.file_name().unwrap().to_string_lossy());
+          level_media.push(path);
+          field
+        }
+        None => String::new(),
+      };
+      if origin == SnippetOrigin::Corpus {
+        let source = snippet.source.as_deref().unwrap_or("unlabeled");
+        *source_cards.entry(source.to_string()).or_default() += 1;
+      }
+      // Passages aren't about any one phrase, so they stay in the level deck.
+      let note = match opts.selection.card_style {
+        CardStyle::Sentence => {
+          let frequency = frequency_ranks
+            .get(phrase.form.as_str())
+            .map(|rank| format!("rank {rank} / {form_count}"))
+            .unwrap_or_default();
+          let subdeck = match phrase.pos {
+            _ if opts.chengyu_subdeck && is_chengyu(&phrase.form) => Subdeck::Chengyu,
+            Some(pos) => Subdeck::Pos(pos),
+            None => Subdeck::Level,
+          };
+          match build_card(&snippet, phrase, origin, &audio, &frequency, &opts.card) {
+            Some(note) => (subdeck, note),
+            None => {
+              skip_unclozable(&snippet, phrase);
+              continue;
+            }
+          }
+        }
+        CardStyle::ReadingPassage => {
+          match build_passage_card(&snippet, vocab, level, origin, &audio, &opts.card) {
+            Some(note) => (Subdeck::Level, note),
+            None => {
+              debug!(passage = snippet.sentence, "no phrases to cloze in passage");
+              continue;
+            }
+          }
+        }
+      };
+      notes.push(note);
+    }
+    for (corpus, cards) in source_cards {
+      info!(
+        level = level.0,
+        corpus, cards, "cards selected from corpus source"
+      );
+    }
+    info!(
+      level = level.0,
+      cards = notes.len(),
+      elapsed = ?level_start.elapsed(),
+      "built level"
+    );
 
-    snippets.sort_by_key(|(snippet, _)| {
-      let mut score = 0;
-      if snippet.prefix.is_some() {
-        score += 1;
+    // Anki imports an empty deck without complaint, which looks like a
+    // broken build, so one is only written on request.
+    if notes.is_empty() {
+      if !opts.allow_empty_decks {
+        warn!(
+          source = vocab.source.name(),
+          level = vocab.source.level_name(level),
+          "no cards for level, skipping its deck"
+        );
+        continue;
       }
-      if snippet.suffix.is_some() {
-        score += 1;
+      warn!(
+        source = vocab.source.name(),
+        level = vocab.source.level_name(level),
+        "no cards for level, writing an empty deck"
+      );
+    }
+
+    // Notes are split into one subdeck per part of speech. Phrases without
+    // POS data stay in the level deck itself, so word lists that lack the
+    // column get the flat per-level layout.
+    let level_name = format!(
+      "{}::{}",
+      vocab.source.name(),
+      vocab.source.level_name(level)
+    );
+    let new_deck = |subdeck: Subdeck| {
+      let name = match subdeck {
+        Subdeck::Level => level_name.clone(),
+        Subdeck::Pos(pos) => format!("{level_name}::{}", pos.label()),
+        Subdeck::Chengyu => format!("{level_name}::Chengyu"),
+      };
+      let id = deck_id(opts.deck_id_base, level.0, subdeck.id_offset());
+      Deck::new(id, &name, DECK_DESCRIPTION)
+    };
+    let mut level_decks = BTreeMap::new();
+    if notes.is_empty() {
+      level_decks.insert(Subdeck::Level, new_deck(Subdeck::Level));
+    }
+    for (subdeck, note) in notes {
+      if let Some(combined) = &mut combined {
+        combined.add_note(note.clone());
       }
-      -score
-    });
+      level_decks
+        .entry(subdeck)
+        .or_insert_with(|| new_deck(subdeck))
+        .add_note(note);
+    }
+
+    level_media.sort();
+    level_media.dedup();
+    if opts.combined_deck {
+      combined_media.extend(level_media.iter().cloned());
+    }
+    if opts.single_package {
+      decks.extend(level_decks.into_values());
+      media.extend(level_media);
+    } else {
+      Package::new(
+        level_decks.into_values().collect(),
+        media_paths(&level_media)?,
+      )?
+      .write_to_file(path_str(&opts.output_dir.join(format!(
+        "{}-{}.apkg",
+        vocab.source.slug(),
+        level.0
+      )))?)?;
+    }
+  }
+
+  if opts.dry_run {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &dry_run_levels)?;
+    writeln!(stdout)?;
+    return Ok(());
+  }
+  if opts.single_package {
+    media.sort();
+    media.dedup();
+    Package::new(decks, media_paths(&media)?)?
+      .write_to_file(path_str(&opts.output_dir.join("zhlearn.apkg"))?)?;
+  }
+  if let Some(combined) = combined {
+    combined_media.sort();
+    combined_media.dedup();
+    Package::new(vec![combined], media_paths(&combined_media)?)?.write_to_file(path_str(
+      &opts
+        .output_dir
+        .join(format!("{}-all.apkg", vocab.source.slug())),
+    )?)?;
+  }
+  info!(elapsed = ?decks_start.elapsed(), "built decks");
 
-    let mut deck = Deck::new(
-      (DECK_ID_BASE + level.0) as i64,
-      &format!("HSK Level {}", level.0),
-      "Corpus-generated Chinese Cloze cards",
+  Ok(())
+}
+
+fn parse_level_range(s: &str) -> Result<RangeInclusive<usize>> {
+  let (start, end) = match s.split_once("..=") {
+    Some((start, end)) => (start, end),
+    None => (s, s),
+  };
+  Ok(start.trim().parse()?..=end.trim().parse()?)
+}
+
+fn read_known_words(path: &Path, width: PunctuationWidth) -> Result<Vec<String>> {
+  let file = BufReader::new(File::open(path)?);
+  let mut words = Vec::new();
+  for line in file.lines() {
+    let line = line?;
+    // `#` starts a comment, on its own line or after a word.
+    let word = line.split('#').next().unwrap().trim();
+    if !word.is_empty() {
+      words.push(normalize(word, width));
+    }
+  }
+  Ok(words)
+}
+
+/// Logs to stderr, filtered by `--log-level` if given, else by `RUST_LOG`,
+/// else at `info`. With `json`, each event is one JSON object per line.
+fn init_logging(level: Option<&str>, json: bool) -> Result<()> {
+  let filter = match level {
+    Some(level) => EnvFilter::try_new(level)?,
+    None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+  };
+  let logger = tracing_subscriber::fmt()
+    .with_env_filter(filter)
+    .with_writer(std::io::stderr);
+  match json {
+    true => logger.json().init(),
+    false => logger.init(),
+  }
+  Ok(())
+}
+
+/// Prints per-level coverage from a report written by an earlier build,
+/// followed by the phrases that have no snippets and how many snippets each
+/// corpus source gave to the `store` in `data_dir`.
+fn print_stats(path: &Path, data_dir: &Path, store: StoreKind) -> Result<()> {
+  let report = CoverageReport::load(path)
+    .with_context(|| format!("reading {}; run a build first", path.display()))?;
+  println!("Level | Phrases | ≥1 snip | ≥5 snip | 0 snip");
+  for (level, phrases) in &report.levels {
+    let with_at_least = |n: usize| phrases.values().filter(|count| **count >= n).count();
+    println!(
+      "{level:>5} | {:>7} | {:>7} | {:>7} | {:>6}",
+      phrases.len(),
+      with_at_least(1),
+      with_at_least(5),
+      phrases.len() - with_at_least(1),
     );
-    for (snippet, phrase) in snippets.into_iter().take(50) {
-      let note = build_card(&snippet, &phrase.simplified);
-      deck.add_note(note);
+  }
+
+  println!();
+  println!("Phrases with no snippets:");
+  for (level, phrases) in &report.levels {
+    let uncovered = phrases
+      .iter()
+      .filter(|(_, count)| **count == 0)
+      .map(|(form, _)| form.as_str())
+      .collect::<Vec<_>>();
+    if !uncovered.is_empty() {
+      println!("{level:>5}: {}", uncovered.join(" "));
     }
+  }
 
-    deck.write_to_file(&format!("../decks/hsk-{}.apkg", level.0))?;
+  let mut sources = BTreeMap::<String, usize>::new();
+  let count = |snippet: Snippet| {
+    let source = snippet.source.unwrap_or_else(|| "(unlabeled)".into());
+    *sources.entry(source).or_default() += 1;
+  };
+  match store {
+    StoreKind::File => {
+      // Databases from before records had length prefixes can't be scanned.
+      let Ok(mut reader) =
+        ShardedFileDbReader::open(data_dir.join(PHRASES_PATH), FileDbReader::load)
+      else {
+        return Ok(());
+      };
+      if !reader
+        .shards()
+        .iter()
+        .all(|shard| shard.config().length_prefix)
+      {
+        return Ok(());
+      }
+      reader.scan_all(count)?;
+    }
+    StoreKind::Sqlite => {
+      let path = data_dir.join(SQLITE_PATH);
+      if !path.exists() {
+        return Ok(());
+      }
+      SqliteStore::scan_all(&path, count)?;
+    }
+  }
+  println!();
+  println!("Snippets per corpus source:");
+  for (source, count) in sources {
+    println!("{count:>7} {source}");
   }
+  Ok(())
+}
+
+fn export_snippets(
+  vocab: &Vocab,
+  store: &mut impl SnippetStore,
+  phrase: &str,
+  output: &Path,
+) -> Result<()> {
+  if !vocab.phrases.iter().any(|p| p.form == phrase) {
+    bail!("{phrase} is not in the word list");
+  }
+  let mut ranges = Vec::new();
+  for (idx, _) in vocab
+    .phrases
+    .iter_enumerated()
+    .filter(|(_, p)| p.form == phrase)
+  {
+    ranges.extend(store.ranges_for(idx)?);
+  }
+  let ranges = ranges.into_iter().unique().collect::<Vec<_>>();
 
+  let snippets = store.read_many::<Snippet>(&ranges)?;
+  serde_json::to_writer_pretty(BufWriter::new(File::create(output)?), &snippets)?;
+  info!(count = snippets.len(), path = %output.display(), "exported snippets");
   Ok(())
 }
 
-fn main() -> Result<()> {
-  let hsk = &read_hsk()?;
-  let file_index = &build_corpus(hsk)?;
+fn dump_db(data_dir: &Path) -> Result<()> {
+  let mut reader = ShardedFileDbReader::open(data_dir.join(PHRASES_PATH), FileDbReader::load)?;
+  let mut out = BufWriter::new(io::stdout().lock());
+  for record in reader.iter::<serde_json::Value>() {
+    let (range, record) = record?;
+    let line = serde_json::json!({
+      "shard": range.shard,
+      "start": range.range.start,
+      "end": range.range.end,
+      "record": record,
+    });
+    serde_json::to_writer(&mut out, &line)?;
+    writeln!(out)?;
+  }
+  out.flush()?;
+  Ok(())
+}
 
-  for level in hsk_levels() {
-    let phrase_iter = hsk
+fn parse_date(s: &str) -> Result<String> {
+  let parts = s.split('-').collect::<Vec<_>>();
+  let valid = matches!(parts[..], [y, m, d] if y.len() == 4 && m.len() == 2 && d.len() == 2)
+    && parts
+      .iter()
+      .all(|part| part.bytes().all(|b| b.is_ascii_digit()))
+    && (1..=12).contains(&parts[1].parse::<u32>()?)
+    && (1..=31).contains(&parts[2].parse::<u32>()?);
+  if !valid {
+    bail!("expected a date like 2024-01-15");
+  }
+  Ok(s.to_string())
+}
+
+/// A seed that depends only on `date` and `level`.
+fn daily_seed(date: &str, level: VocabLevel) -> u64 {
+  let mut hasher = Sha256::new();
+  hasher.update(date);
+  hasher.update(level.0.to_le_bytes());
+  u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap())
+}
+
+/// Picks one phrase per level that has snippets, seeded by `date`, and
+/// writes a self-contained HTML page showing each with its best snippet:
+/// the one with the most context, then the shortest sentence.
+fn phrase_of_day(
+  vocab: &Vocab,
+  store: &mut impl SnippetStore,
+  date: &str,
+  output: &Path,
+) -> Result<()> {
+  let counts = snippet_counts(vocab, store)?;
+  let mut sections = String::new();
+  for level in vocab.levels() {
+    let candidates = vocab
       .phrases
       .iter_enumerated()
-      .filter(|(_, phrase)| phrase.level == level);
-    println!(
-      "{level:?}: {}",
-      phrase_iter
-        .map(|(idx, _)| file_index[idx].len())
-        .sum::<usize>()
+      .filter(|(idx, phrase)| phrase.level == level && counts[idx.index()] > 0)
+      .collect::<Vec<_>>();
+    if candidates.is_empty() {
+      continue;
+    }
+    let seed = daily_seed(date, level);
+    let (idx, phrase) = candidates[(seed % candidates.len() as u64) as usize];
+    let ranges = store.ranges_for(idx)?;
+    let snippets = store.read_many::<Snippet>(&ranges)?;
+    let Some((snippet, loc)) = snippets
+      .iter()
+      .filter_map(|snippet| {
+        let loc = phrase_location(&snippet.sentence, &phrase.form, &snippet.tokens)?;
+        Some((snippet, loc))
+      })
+      .min_by_key(|(snippet, _)| {
+        let context = snippet.prefix.is_some() as usize + snippet.suffix.is_some() as usize;
+        (Reverse(context), snippet.sentence.len())
+      })
+    else {
+      continue;
+    };
+
+    let word = html_escape::encode_safe(&phrase.form);
+    let sentence = fill_hole(
+      &snippet.sentence,
+      &phrase.form,
+      loc,
+      &format!(r#"<b style="color: #1a5fb4">{word}</b>"#),
     );
+    let context = |text: &Option<String>| {
+      text
+        .as_deref()
+        .map(|text| format!(r#"<span style="color: #777">{}</span>"#, escape_text(text)))
+        .unwrap_or_default()
+    };
+    sections.push_str(&format!(
+      r#"<div style="margin: 24px 0; padding: 16px; border: 1px solid #ddd; border-radius: 8px">
+<div style="font-size: 13px; color: #777">{level}</div>
+<div style="font-size: 36px">{word}</div>
+<div style="font-size: 18px">{pinyin}</div>
+<div style="font-size: 15px; color: #333">{definition}</div>
+<p style="font-size: 22px; line-height: 1.6">{prefix}{sentence}{suffix}</p>
+</div>
+"#,
+      level = html_escape::encode_text(&vocab.source.level_name(level)),
+      pinyin = html_escape::encode_text(phrase.pinyin.as_deref().unwrap_or("")),
+      definition = html_escape::encode_text(phrase.definition.as_deref().unwrap_or("")),
+      prefix = context(&snippet.prefix),
+      suffix = context(&snippet.suffix),
+    ));
+  }
+
+  let title = format!("{} phrases of the day, {date}", vocab.source.name());
+  let html = format!(
+    r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+</head>
+<body style="margin: 0 auto; max-width: 600px; padding: 16px; font-family: sans-serif">
+<h1 style="font-size: 24px">{title}</h1>
+{sections}</body>
+</html>
+"#
+  );
+  fs::write(output, html)?;
+  info!(path = %output.display(), "wrote phrases of the day");
+  Ok(())
+}
+
+fn export_csv(
+  vocab: &Vocab,
+  store: &mut impl SnippetStore,
+  level: VocabLevel,
+  selection: &SelectionOptions,
+  output: &Path,
+) -> Result<()> {
+  if !vocab.levels().any(|l| l == level) {
+    bail!("the word list has no level {}", level.0);
+  }
+  let mut used_sentences = UsedKeys::new(selection.approx_dedup);
+
+  // Lower levels are selected first so cross-level repeats are excluded the
+  // same way they are when building decks.
+  let mut selected = Vec::new();
+  for l in vocab.levels().take_while(|l| *l <= level) {
+    selected = select_snippets(vocab, store, l, selection, &mut used_sentences)?;
   }
 
-  build_decks(hsk, file_index)?;
+  let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(output)?));
+  writer.write_record([
+    "phrase",
+    "sentence",
+    "prefix",
+    "suffix",
+    "pinyin",
+    "definition",
+  ])?;
+  for (snippet, phrase, _) in &selected {
+    writer.write_record([
+      phrase.form.as_str(),
+      &snippet.sentence,
+      snippet.prefix.as_deref().unwrap_or(""),
+      snippet.suffix.as_deref().unwrap_or(""),
+      phrase.pinyin.as_deref().unwrap_or(""),
+      phrase.definition.as_deref().unwrap_or(""),
+    ])?;
+  }
+  writer.flush()?;
+  info!(count = selected.len(), path = %output.display(), "exported cards");
   Ok(())
 }
+
+/// Writes Pleco's tab-delimited flashcard format: a `//` category line per
+/// level, then `headword<TAB>pinyin<TAB>definition` per phrase. Without a
+/// definition the headword is repeated so the card still has a back.
+fn export_pleco(vocab: &Vocab, output: &Path) -> Result<()> {
+  let clean = |field: &str| field.replace(['\t', '\n', '\r'], " ");
+  let mut writer = BufWriter::new(File::create(output)?);
+  for level in vocab.levels() {
+    writeln!(
+      writer,
+      "//{}/{}",
+      vocab.source.name(),
+      vocab.source.level_name(level)
+    )?;
+    let phrases = vocab
+      .phrases
+      .iter()
+      .filter(|phrase| phrase.level == level)
+      .unique_by(|phrase| &phrase.form);
+    for phrase in phrases {
+      writeln!(
+        writer,
+        "{}\t{}\t{}",
+        clean(&phrase.form),
+        clean(phrase.pinyin.as_deref().unwrap_or("")),
+        clean(phrase.definition.as_deref().unwrap_or(&phrase.form)),
+      )?;
+    }
+  }
+  writer.flush()?;
+  info!(path = %output.display(), "exported Pleco flashcards");
+  Ok(())
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Print coverage statistics from the last build's coverage report,
+  /// without rebuilding the corpus or decks.
+  Stats,
+  /// Write every snippet collected for a phrase by the last build to a JSON file.
+  ExportSnippets {
+    /// The phrase as written in the word list.
+    #[arg(long)]
+    phrase: String,
+    #[arg(long)]
+    output: PathBuf,
+  },
+  /// Write the word list as a Pleco flashcard file, one category per level.
+  ExportPleco {
+    #[arg(long)]
+    output: PathBuf,
+  },
+  /// Write the cards a build selects for one level to a CSV file. Selection
+  /// uses the same options as a build, so with the default random tiebreak
+  /// the `--seed` the build logged must be given to get its cards.
+  ExportCsv {
+    #[arg(long)]
+    level: usize,
+    #[arg(long)]
+    output: PathBuf,
+  },
+  /// Print every record in the snippet database as a JSON line, with the
+  /// shard and byte range it was read from.
+  Dump,
+  /// Write an HTML digest with one phrase per level, chosen from the date,
+  /// each shown with its best snippet from the last build.
+  PhraseOfDay {
+    /// The digest's date as YYYY-MM-DD; the same date always picks the same
+    /// phrases.
+    #[arg(long, value_parser = parse_date)]
+    date: String,
+    #[arg(long)]
+    output: PathBuf,
+  },
+}
+
+#[derive(Parser)]
+struct Args {
+  #[command(subcommand)]
+  command: Option<Command>,
+
+  /// Log filter such as `debug` or `card_builder=trace`; overrides `RUST_LOG`.
+  #[arg(long)]
+  log_level: Option<String>,
+
+  /// Log JSON lines instead of text, for comparing runs with other tools.
+  #[arg(long)]
+  log_json: bool,
+
+  /// Write Anki packages, or print the selected cards as JSON to stdout.
+  #[arg(long, value_enum, default_value_t = OutputFormat::Apkg)]
+  format: OutputFormat,
+
+  /// Select cards as usual, but print a JSON report of each level's deck
+  /// (phrase, sentence, context, and cloze) instead of writing packages.
+  #[arg(long)]
+  dry_run: bool,
+
+  /// Also write every level's cards to one flat deck, `<source>-all.apkg`,
+  /// ordered by level. Its notes are the per-level decks' notes, so import
+  /// one or the other.
+  #[arg(long)]
+  combined_deck: bool,
+
+  /// Write a deck for a level even when none of its phrases got a card.
+  /// Such levels are skipped with a warning by default.
+  #[arg(long)]
+  allow_empty_decks: bool,
+
+  /// Directory holding the word lists and corpus, where the snippet
+  /// database, coverage report, and decks are written. Paths not given on
+  /// the command line are found in it.
+  #[arg(long, global = true, default_value = "..")]
+  data_dir: PathBuf,
+
+  /// Where to write per-phrase snippet counts [default: DATA_DIR/coverage.json]
+  #[arg(long, global = true)]
+  coverage_path: Option<PathBuf>,
+
+  /// Exit with an error if fewer than this percentage of phrases have a snippet.
+  #[arg(long, value_name = "PCT")]
+  fail_on_uncovered: Option<f64>,
+
+  /// Configuration file; ignored if it doesn't exist [default:
+  /// DATA_DIR/zhlearn.toml]
+  #[arg(long)]
+  config: Option<PathBuf>,
+
+  /// Stylesheet that replaces the built-in card CSS, overriding the config's
+  /// `template.css_path`.
+  #[arg(long)]
+  css: Option<PathBuf>,
+
+  /// Directory with `front.html` and `back.html` templates for cloze cards,
+  /// and optionally a `style.css`, overriding the config's `template.dir`
+  /// and `[template.cloze]`.
+  #[arg(long)]
+  template_dir: Option<PathBuf>,
+
+  /// Id of the cloze note type. Anki recognizes note types by id, so give
+  /// customized templates their own.
+  #[arg(long)]
+  model_id: Option<i64>,
+
+  /// Bump to import the note types as new ones instead of reusing those of
+  /// an earlier import, e.g. after changing fields; overrides the config's
+  /// `template.model_version`.
+  #[arg(long)]
+  model_version: Option<i64>,
+
+  /// Kind of Anki note to generate for each snippet.
+  #[arg(long, value_enum, default_value_t = CardType::Cloze)]
+  card_type: CardType,
+
+  /// Single sentences or multi-sentence reading passages; defaults to the
+  /// config's `template.card_style`, then to sentences. The snippet database
+  /// must be rebuilt when this changes.
+  #[arg(long, value_enum)]
+  card_style: Option<CardStyle>,
+
+  /// Write every level into one zhlearn.apkg instead of one file per level.
+  #[arg(long)]
+  single_package: bool,
+
+  /// Put chengyu cards in a Chengyu subdeck of each level instead of their
+  /// part-of-speech subdeck. They are tagged `zhlearn::chengyu` either way.
+  #[arg(long)]
+  chengyu_subdeck: bool,
+
+  /// Media file to bundle into the single package (repeatable).
+  #[arg(long, requires = "single_package")]
+  media: Vec<PathBuf>,
+
+  /// Don't rank snippets lower when the phrase starts or ends the sentence.
+  #[arg(long)]
+  no_edge_penalty: bool,
+
+  /// Most corpus cards to put in each level's deck [default: 50]
+  #[arg(long)]
+  cards_per_deck: Option<usize>,
+
+  /// Which phrases get cards first when `--cards-per-deck` runs out.
+  #[arg(long, value_enum, default_value_t = PhraseOrder::Csv)]
+  phrase_order: PhraseOrder,
+
+  /// How snippets that rank equally for a phrase are ordered; `score` and
+  /// `length` make the selection reproducible.
+  #[arg(long, value_enum, default_value_t = Tiebreak::Random)]
+  tiebreak: Tiebreak,
+
+  /// Seed for the `random` tiebreak, to make its selection reproducible.
+  /// Builds without one draw a seed and log it.
+  #[arg(long)]
+  seed: Option<u64>,
+
+  /// Let a snippet used in one level's deck be used again in a higher level's.
+  #[arg(long)]
+  allow_cross_level_repeat: bool,
+
+  /// Remember snippets used by lower levels in a bloom filter rather than
+  /// exactly, bounding memory on huge corpora. About 1 in 1000 unused
+  /// snippets is wrongly treated as used and skipped.
+  #[arg(long)]
+  approx_dedup: bool,
+
+  /// Skip corpus snippets where the clozed phrase is less than this fraction
+  /// of the sentence, counted in graphemes, e.g. `0.1`.
+  #[arg(long)]
+  min_cloze_ratio: Option<f64>,
+
+  /// Synthesize each card's sentence and add it to the answer side.
+  #[cfg(feature = "audio")]
+  #[arg(long, requires = "tts_command")]
+  audio: bool,
+
+  /// Text-to-speech program for `--audio`; it reads the sentence on stdin and
+  /// writes the file given by its `{output}` argument.
+  #[cfg(feature = "audio")]
+  #[arg(long)]
+  tts_command: Option<String>,
+
+  /// Directory where synthesized audio is cached between runs.
+  #[cfg(feature = "audio")]
+  #[arg(long)]
+  audio_dir: Option<PathBuf>,
+
+  /// File extension of the audio the TTS command writes.
+  #[cfg(feature = "audio")]
+  #[arg(long, default_value = "mp3")]
+  audio_ext: String,
+
+  /// Vocabulary standard to build decks for.
+  #[arg(long, value_enum, default_value_t = VocabSource::Hsk3)]
+  vocab: VocabSource,
+
+  /// Word list file; defaults to the standard location for `--vocab`.
+  #[arg(long)]
+  vocab_path: Option<PathBuf>,
+
+  /// Fail on a word list row that can't be read, like one with an unknown
+  /// level, instead of skipping it with a warning.
+  #[arg(long)]
+  strict: bool,
+
+  /// Encoding of the word list; defaults to JSON for `.json` files and CSV
+  /// otherwise.
+  #[arg(long, value_enum)]
+  vocab_format: Option<VocabFormat>,
+
+  /// Width that punctuation in corpus text and word lists is normalized to.
+  #[arg(long, value_enum, default_value_t = PunctuationWidth::Full)]
+  punctuation_width: PunctuationWidth,
+
+  /// Which level keeps a word that the word list puts at several levels, so
+  /// it is only carded once. An HSK "7-9" band counts as one level, whose
+  /// words stay at all three.
+  #[arg(long, value_enum, default_value_t = DedupPolicy::Lowest)]
+  dedup_phrases: DedupPolicy,
+
+  /// How vocabulary phrases are located in corpus sentences.
+  #[arg(long, value_enum, default_value_t = Matcher::Jieba)]
+  matcher: Matcher,
+
+  /// Index each sentence under every phrase in it, not only the phrases at
+  /// its hardest level. Easy phrases get far more snippets, but a card's
+  /// sentence may then use words above the phrase's level.
+  #[arg(long)]
+  index_every_level: bool,
+
+  /// Where snippets are stored; subcommands read the same store.
+  #[arg(long, global = true, value_enum, default_value_t = StoreKind::File)]
+  store: StoreKind,
+
+  /// Skip the corpus scan and build decks from the snippet database (and
+  /// phrases.idx with `--store file`) left by the last run. That run must
+  /// have scanned the same corpus inputs, unchanged since.
+  #[arg(long)]
+  reuse_index: bool,
+
+  /// Continue an interrupted corpus scan from phrases.checkpoint, skipping
+  /// the corpus inputs and entries it had finished. The scan must be given
+  /// the same corpus and options.
+  #[arg(long, conflicts_with = "reuse_index")]
+  resume: bool,
+
+  /// Compress each snippet in the database with zstd, using a dictionary
+  /// trained on the first few thousand.
+  #[arg(long, value_enum, default_value_t = Toggle::Off)]
+  db_compression: Toggle,
+
+  /// Number of snippets kept in memory while building decks, so snippets
+  /// shared by many phrases are read from disk once; 0 disables the cache.
+  #[arg(long, default_value_t = DEFAULT_CACHE_CAPACITY)]
+  reader_cache: usize,
+
+  /// Memory-map the snippet database while building decks instead of reading it
+  /// through a cache, which is much faster for large databases.
+  #[arg(long, conflicts_with = "reader_cache")]
+  mmap: bool,
+
+  /// Corpus files of JSON lines, plain or gzip/zstd-compressed, or `-` for
+  /// stdin (repeatable).
+  ///
+  /// A format prefix such as `text:`, `paragraphs:`, or `tatoeba:` overrides
+  /// `--corpus-format` for one path.
+  ///
+  /// Defaults to the corpus/part-*.jsonl files in the data directory.
+  #[arg(long)]
+  corpus: Vec<String>,
+
+  /// Format of corpus inputs, unless overridden per path.
+  #[arg(long, value_enum, default_value_t = CorpusFormat::Jsonl)]
+  corpus_format: CorpusFormat,
+
+  /// Snippets to collect per phrase before the corpus scan can stop early
+  /// [default: 50]
+  #[arg(long)]
+  snippets_per_phrase: Option<usize>,
+
+  /// Number of neighboring sentences kept as context on each side of a snippet.
+  #[arg(long, default_value_t = 1)]
+  context_sentences: usize,
+
+  /// Characters that end a sentence. Add e.g. `；…，` to split dense text
+  /// into shorter snippets.
+  #[arg(long, default_value = "。！？｡!?")]
+  sentence_delimiters: String,
+
+  /// File of words already learned, one per line, with `#` comments. They
+  /// may appear in snippets but are never clozed.
+  #[arg(long, visible_alias = "known")]
+  known_words: Option<PathBuf>,
+
+  /// Levels already learned, e.g. `1..=3`; treated like `--known-words`.
+  #[arg(long, value_parser = parse_level_range)]
+  known_levels: Option<RangeInclusive<usize>>,
+
+  /// Only build decks for these levels, e.g. `4,5`. Other levels collect
+  /// no snippets, which makes iterating on one level quick, but a
+  /// `--reuse-index` build later has none for them.
+  #[arg(long, visible_alias = "filter-level", value_delimiter = ',')]
+  levels: Vec<usize>,
+
+  /// Reject sentences with too many Latin letters or digits (URLs, product
+  /// codes, English fragments).
+  #[arg(long)]
+  cjk_only: bool,
+
+  /// Highest fraction of Latin letters and digits `--cjk-only` lets through.
+  #[arg(long, default_value_t = 0.0, requires = "cjk_only")]
+  max_latin_ratio: f64,
+
+  /// Longest sentence, in graphemes, that can become a card. Unlimited by
+  /// default.
+  #[arg(long)]
+  max_length: Option<usize>,
+
+  /// Read at most this many entries of each corpus file, for quick test
+  /// builds. Every entry is read by default.
+  #[arg(long, visible_alias = "max-corpus-lines-per-file")]
+  max_lines: Option<usize>,
+
+  /// Cache jieba segmentation results on disk between runs.
+  #[arg(long)]
+  seg_cache: bool,
+
+  /// Directory holding the segmentation cache [default: DATA_DIR/seg-cache]
+  #[arg(long)]
+  seg_cache_dir: Option<PathBuf>,
+
+  /// Extra jieba dictionary to load; changing it invalidates the segmentation cache.
+  #[arg(long)]
+  user_dict: Option<PathBuf>,
+
+  /// Word frequency list, most common first, used by `--phrase-order rank`.
+  #[arg(long)]
+  frequency_list: Option<PathBuf>,
+}
+
+impl Args {
+  fn selection_options(&self, config: &Config) -> SelectionOptions {
+    SelectionOptions {
+      edge_penalty: !self.no_edge_penalty,
+      allow_cross_level_repeat: self.allow_cross_level_repeat,
+      approx_dedup: self.approx_dedup,
+      cards_per_deck: self
+        .cards_per_deck
+        .or(config.deck.cards_per_deck)
+        .unwrap_or(DEFAULT_CARDS_PER_DECK),
+      phrase_order: self.phrase_order,
+      source_weights: config
+        .sources
+        .iter()
+        .map(|source| (source.label.clone(), source.weights.clone()))
+        .collect(),
+      card_style: self.card_style(config),
+      tiebreak: self.tiebreak,
+      min_cloze_ratio: self.min_cloze_ratio,
+      seed: self.seed,
+    }
+  }
+
+  fn card_style(&self, config: &Config) -> CardStyle {
+    self
+      .card_style
+      .or(config.template.card_style)
+      .unwrap_or(CardStyle::Sentence)
+  }
+
+  /// The templates of each note type. Cloze cards use `--template-dir`
+  /// alone if it's given, and otherwise the config's `template.dir` with
+  /// the sides of `[template.cloze]` replaced; the others start from the
+  /// built-in templates.
+  fn card_templates(&self, config: &Config) -> Result<CardTemplates> {
+    let config_path = self.data_path(&self.config, "zhlearn.toml");
+    let cloze = match (&self.template_dir, &config.template.dir) {
+      (Some(dir), _) => CardTemplate::load(NoteKind::Cloze, dir)?,
+      (None, dir) => {
+        let template = match dir {
+          Some(dir) => CardTemplate::load(NoteKind::Cloze, dir)?,
+          None => CardTemplate::builtin(NoteKind::Cloze),
+        };
+        config
+          .template
+          .cloze
+          .apply(template, NoteKind::Cloze, &config_path)?
+      }
+    };
+    let apply = |kind, template: &NoteTemplateConfig| {
+      template.apply(CardTemplate::builtin(kind), kind, &config_path)
+    };
+    Ok(CardTemplates {
+      cloze,
+      basic: apply(NoteKind::Basic, &config.template.basic)?,
+      reading: apply(NoteKind::Reading, &config.template.reading)?,
+    })
+  }
+
+  fn corpus_options(&self, config: &Config, levels: &[VocabLevel]) -> Result<CorpusOptions> {
+    Ok(CorpusOptions {
+      inputs: self.corpus_inputs(config),
+      width: self.punctuation_width,
+      matcher: self.matcher,
+      every_level: self.index_every_level,
+      context_sentences: self.context_sentences,
+      max_latin_ratio: self.cjk_only.then_some(self.max_latin_ratio),
+      min_score: config.corpus.min_score.unwrap_or(SCORE_THRESHOLD),
+      min_length: config.corpus.min_length.unwrap_or(LEN_THRESHOLD),
+      max_length: self.max_length.or(config.corpus.max_length),
+      max_lines: self.max_lines,
+      card_style: self.card_style(config),
+      resume: self.resume,
+      db_config: FileDbConfig {
+        compression: self.db_compression == Toggle::On,
+        length_prefix: true,
+        ..FileDbConfig::default()
+      },
+      snippets_per_phrase: self
+        .snippets_per_phrase
+        .or(config.corpus.snippets_per_phrase)
+        .unwrap_or(DEFAULT_SNIPPETS_PER_PHRASE),
+      levels: levels.to_vec(),
+      sentence_delimiters: sentence_delimiters(&self.sentence_delimiters)
+        .context("invalid --sentence-delimiters")?,
+    })
+  }
+
+  /// Fingerprints of the corpus inputs, recorded with the index so
+  /// `--reuse-index` can tell if they've changed.
+  fn corpus_fingerprints(&self, config: &Config) -> Result<Vec<String>> {
+    self
+      .corpus_inputs(config)
+      .iter()
+      .map(|input| Ok(input.fingerprint()?))
+      .collect()
+  }
+
+  fn segmenter(&self) -> Result<Segmenter> {
+    Ok(Segmenter::new(
+      self.user_dict.as_deref(),
+      self
+        .seg_cache
+        .then(|| self.data_path(&self.seg_cache_dir, "seg-cache")),
+    )?)
+  }
+
+  /// The levels picked with `--levels`, or every level in the word list.
+  fn levels(&self, vocab: &Vocab) -> Result<Vec<VocabLevel>> {
+    if self.levels.is_empty() {
+      return Ok(vocab.levels().collect());
+    }
+    let available = vocab.levels().collect::<Vec<_>>();
+    let mut levels = Vec::new();
+    for level in self.levels.iter().copied().map(VocabLevel) {
+      if !available.contains(&level) {
+        bail!(
+          "--levels {}: the {} word list has levels {}",
+          level.0,
+          vocab.source.name(),
+          available.iter().map(|level| level.0).join(", ")
+        );
+      }
+      levels.push(level);
+    }
+    levels.sort_unstable();
+    levels.dedup();
+    Ok(levels)
+  }
+
+  /// `path` if it was given, otherwise `default` in the data directory.
+  fn data_path(&self, path: &Option<PathBuf>, default: &str) -> PathBuf {
+    path.clone().unwrap_or_else(|| self.data_dir.join(default))
+  }
+
+  fn coverage_path(&self) -> PathBuf {
+    self.data_path(&self.coverage_path, "coverage.json")
+  }
+
+  /// The `--corpus` inputs, labeled from the config's `[[sources]]`.
+  fn corpus_inputs(&self, config: &Config) -> Vec<CorpusInput> {
+    let defaults = match config.paths.corpus.is_empty() {
+      true => CORPUS_PATHS
+        .iter()
+        .map(|path| self.data_dir.join(path))
+        .collect(),
+      false => config.paths.corpus.clone(),
+    };
+    let defaults = defaults
+      .iter()
+      .map(|path| path.to_string_lossy().into_owned())
+      .collect::<Vec<_>>();
+    let corpus = match self.corpus.is_empty() {
+      true => &defaults,
+      false => &self.corpus,
+    };
+    corpus
+      .iter()
+      .map(|arg| {
+        let mut input = CorpusInput::parse(arg, self.corpus_format);
+        if let Some(source) = input.source.path().and_then(|path| config.source(path)) {
+          input.label = source.label.clone();
+        }
+        input
+      })
+      .collect()
+  }
+}
+
+fn main() -> Result<()> {
+  let args = Args::parse();
+  init_logging(args.log_level.as_deref(), args.log_json)?;
+  match args.command {
+    Some(Command::Stats) => return print_stats(&args.coverage_path(), &args.data_dir, args.store),
+    Some(Command::Dump) => return dump_db(&args.data_dir),
+    _ => {}
+  }
+  let config = Config::load(&args.data_path(&args.config, "zhlearn.toml"))?;
+  let vocab_path = args
+    .vocab_path
+    .clone()
+    .or(config.paths.vocab.clone())
+    .unwrap_or_else(|| args.data_dir.join(args.vocab.default_path()));
+  let vocab_format = args
+    .vocab_format
+    .unwrap_or_else(|| VocabFormat::detect(&vocab_path));
+  let mut vocab = read_vocab(
+    &vocab_path,
+    args.vocab,
+    vocab_format,
+    args.punctuation_width,
+    args.strict,
+  )
+  .with_context(|| format!("reading {}", vocab_path.display()))?;
+  let duplicates = vocab.dedup_levels(args.dedup_phrases)?;
+  if duplicates > 0 {
+    info!(
+      duplicates,
+      policy = ?args.dedup_phrases,
+      "collapsed phrases listed at several levels"
+    );
+  }
+  match &args.frequency_list {
+    Some(path) => {
+      let ranks = read_frequency_list(path, args.punctuation_width)
+        .with_context(|| format!("reading {}", path.display()))?;
+      let ranked = vocab.set_frequency_ranks(&ranks)?;
+      info!(
+        ranked,
+        unranked = vocab.phrases.len() - ranked,
+        "ranked phrases by frequency"
+      );
+    }
+    None if args.phrase_order == PhraseOrder::Rank => {
+      bail!("--phrase-order rank needs a --frequency-list");
+    }
+    None => {}
+  }
+  let known_words = match &args.known_words {
+    Some(path) => read_known_words(path, args.punctuation_width)?,
+    None => Vec::new(),
+  };
+  let known_levels = args
+    .known_levels
+    .clone()
+    .map(|levels| levels.map(VocabLevel).collect::<Vec<_>>())
+    .unwrap_or_default();
+  vocab.set_known(known_words, &known_levels);
+  let known = vocab
+    .phrases
+    .indices()
+    .filter(|idx| vocab.is_known(*idx))
+    .count();
+  if known > 0 {
+    info!(known, "excluding known phrases from cards");
+  }
+  match &args.command {
+    Some(Command::ExportPleco { output }) => return export_pleco(&vocab, output),
+    Some(command) => {
+      return match args.store {
+        StoreKind::File => {
+          let mut store = FileStore::load(
+            &vocab,
+            file_store_paths(&args.data_dir),
+            None,
+            Box::new(FileDbReader::load),
+          )?;
+          run_export(&vocab, &mut store, command, &args, &config)
+        }
+        StoreKind::Sqlite => {
+          let mut store = SqliteStore::open(&args.data_dir.join(SQLITE_PATH), &vocab, None)?;
+          run_export(&vocab, &mut store, command, &args, &config)
+        }
+      };
+    }
+    None => {}
+  }
+  let template_dir = args.template_dir.clone().or(config.template.dir.clone());
+  let templates = args.card_templates(&config)?;
+  let model_id = args.model_id.or(config.template.model_id);
+  if (template_dir.is_some() || config.template.cloze.is_set()) && model_id.is_none() {
+    warn!(
+      "custom templates keep the built-in note type id, which Anki will treat as the same note \
+       type; pass --model-id to import them as a separate one"
+    );
+  }
+  let model_version = args.model_version.or(config.template.model_version);
+  if (config.template.basic.is_set() || config.template.reading.is_set()) && model_version.is_none()
+  {
+    warn!(
+      "custom basic or reading templates keep the built-in note type ids, which Anki will treat \
+       as the same note types; pass --model-version to import them as new ones"
+    );
+  }
+  let css_file = match (&args.css, &template_dir) {
+    (Some(path), _) => Some(path.clone()),
+    (None, Some(dir)) => Some(dir.join("style.css")).filter(|path| path.exists()),
+    (None, None) => None,
+  };
+  let css = match css_file {
+    Some(path) => {
+      Some(fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?)
+    }
+    None => config.css()?,
+  };
+  let vocab = &vocab;
+
+  let mut selection = args.selection_options(&config);
+  if selection.tiebreak == Tiebreak::Random && selection.seed.is_none() {
+    let seed = rand::random();
+    info!(
+      seed,
+      "selecting with a random seed; pass --seed to repeat this selection"
+    );
+    selection.seed = Some(seed);
+  }
+  let deck_opts = DeckOptions {
+    card: CardOptions {
+      card_type: args.card_type,
+      models: CardModels::new(
+        css.as_deref().unwrap_or(CSS),
+        model_version.unwrap_or(0),
+        &templates,
+        model_id,
+      ),
+      sentence_delimiters: sentence_delimiters(&args.sentence_delimiters)
+        .context("invalid --sentence-delimiters")?,
+    },
+    single_package: args.single_package,
+    chengyu_subdeck: args.chengyu_subdeck,
+    media: args.media.clone(),
+    selection,
+    levels: args.levels(vocab)?,
+    format: args.format,
+    dry_run: args.dry_run,
+    combined_deck: args.combined_deck,
+    allow_empty_decks: args.allow_empty_decks,
+    deck_id_base: config.deck.id_base.unwrap_or(vocab.source.deck_id_base()),
+    output_dir: args.data_dir.join(DECKS_DIR),
+    #[cfg(feature = "audio")]
+    audio: match &args.tts_command {
+      Some(command) if args.audio => Some(AudioCache::new(
+        Box::new(CommandBackend::parse(command)?),
+        args.data_path(&args.audio_dir, "media"),
+        args.audio_ext.clone(),
+      )?),
+      _ => None,
+    },
+  };
+  match args.store {
+    StoreKind::File => {
+      let (mmap, cache) = (args.mmap, args.reader_cache);
+      let open: OpenShard = Box::new(move |path| match mmap {
+        true => FileDbReader::load_mmap(path),
+        false => FileDbReader::with_cache_capacity(path, cache),
+      });
+      let mut store = if args.reuse_index {
+        info!(
+          path = %args.data_dir.join(INDEX_PATH).display(),
+          "reusing the existing phrase index"
+        );
+        let inputs = args.corpus_fingerprints(&config)?;
+        FileStore::load(vocab, file_store_paths(&args.data_dir), Some(&inputs), open)?
+      } else {
+        let corpus_opts = args.corpus_options(&config, &deck_opts.levels)?;
+        let (mut store, resume) = FileStore::create(
+          vocab,
+          file_store_paths(&args.data_dir),
+          args.corpus_fingerprints(&config)?,
+          corpus_opts.fingerprint(),
+          corpus_opts.resume,
+          corpus_opts.db_config,
+          open,
+        )?;
+        build_corpus(
+          vocab,
+          &mut args.segmenter()?,
+          &corpus_opts,
+          &mut store,
+          resume,
+        )?;
+        store
+      };
+      report_and_build_decks(vocab, &mut store, &args, &deck_opts)
+    }
+    StoreKind::Sqlite => {
+      let path = args.data_dir.join(SQLITE_PATH);
+      let mut store = if args.reuse_index {
+        info!(path = %path.display(), "reusing the existing snippet database");
+        let inputs = args.corpus_fingerprints(&config)?;
+        SqliteStore::open(&path, vocab, Some(&inputs))?
+      } else {
+        if args.resume {
+          bail!("--resume needs --store file");
+        }
+        let corpus_opts = args.corpus_options(&config, &deck_opts.levels)?;
+        let inputs = args.corpus_fingerprints(&config)?;
+        let mut store = SqliteStore::create(&path, vocab, &inputs)?;
+        build_corpus(
+          vocab,
+          &mut args.segmenter()?,
+          &corpus_opts,
+          &mut store,
+          BuildCursor::default(),
+        )?;
+        info!(path = %path.display(), "wrote snippet database");
+        store
+      };
+      report_and_build_decks(vocab, &mut store, &args, &deck_opts)
+    }
+  }
+}
+
+/// Runs a subcommand that reads the snippets of an earlier build.
+fn run_export<S: SnippetStore>(
+  vocab: &Vocab,
+  store: &mut S,
+  command: &Command,
+  args: &Args,
+  config: &Config,
+) -> Result<()> {
+  match command {
+    Command::ExportSnippets { phrase, output } => {
+      let phrase = normalize(phrase, args.punctuation_width);
+      export_snippets(vocab, store, &phrase, output)
+    }
+    Command::PhraseOfDay { date, output } => phrase_of_day(vocab, store, date, output),
+    Command::ExportCsv { level, output } => {
+      let selection = args.selection_options(config);
+      if selection.tiebreak == Tiebreak::Random && selection.seed.is_none() {
+        bail!(
+          "export-csv selects cards at random without --seed; pass the seed the build logged, or \
+           another --tiebreak"
+        );
+      }
+      export_csv(vocab, store, VocabLevel(*level), &selection, output)
+    }
+    Command::Stats | Command::Dump | Command::ExportPleco { .. } => unreachable!(),
+  }
+}
+
+/// Writes the coverage report for the snippets in `store`, then builds decks
+/// from them.
+fn report_and_build_decks<S: SnippetStore>(
+  vocab: &Vocab,
+  store: &mut S,
+  args: &Args,
+  deck_opts: &DeckOptions,
+) -> Result<()> {
+  let counts = snippet_counts(vocab, store)?;
+  let coverage = CoverageReport::new(vocab, &deck_opts.levels, |idx| counts[idx.index()]);
+  coverage.write(&args.coverage_path())?;
+  let stats = &coverage.stats;
+  info!(
+    total = stats.total_phrases,
+    covered = stats.covered,
+    median_snippets = stats.median_snippets,
+    path = %args.coverage_path().display(),
+    "wrote coverage report"
+  );
+  // The report leaves known phrases out, so these are what's left to learn.
+  for (level, phrases) in &coverage.levels {
+    info!(
+      level,
+      unknown = phrases.len(),
+      snippets = phrases.values().sum::<usize>(),
+      "level summary"
+    );
+  }
+  if let Some(min_pct) = args.fail_on_uncovered {
+    if stats.covered_pct() < min_pct {
+      bail!(
+        "corpus covers {:.1}% of phrases, below the required {min_pct}%",
+        stats.covered_pct()
+      );
+    }
+  }
+
+  build_decks(vocab, store, deck_opts)
+}
@@ -0,0 +1,111 @@
+use clap::ValueEnum;
+use unicode_normalization::UnicodeNormalization;
+
+/// Which width sentence punctuation is normalized to.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, ValueEnum)]
+pub enum PunctuationWidth {
+  /// Map ASCII and half-width punctuation next to CJK text to full-width.
+  Full,
+  /// Map full-width punctuation to ASCII and half-width forms.
+  Half,
+  /// Leave punctuation as-is.
+  Keep,
+}
+
+const FULL_HALF: &[(char, char)] = &[
+  ('！', '!'),
+  ('？', '?'),
+  ('，', ','),
+  ('；', ';'),
+  ('：', ':'),
+  ('。', '｡'),
+  ('、', '､'),
+];
+
+fn is_cjk(c: char) -> bool {
+  matches!(c,
+    '\u{3000}'..='\u{303F}'
+    | '\u{3400}'..='\u{4DBF}'
+    | '\u{4E00}'..='\u{9FFF}'
+    | '\u{F900}'..='\u{FAFF}'
+    | '\u{FF00}'..='\u{FFEF}'
+    | '\u{20000}'..='\u{2FA1F}')
+}
+
+/// Variants of the middle dot used in transliterated names, e.g. 马克·吐温.
+fn is_middle_dot(c: char) -> bool {
+  matches!(
+    c,
+    '\u{00B7}' | '\u{2022}' | '\u{2027}' | '\u{30FB}' | '\u{FF65}'
+  )
+}
+
+/// Normalizes text to NFC, unifies middle dots, and maps punctuation to the
+/// requested width.
+///
+/// ASCII punctuation is only widened when it touches a CJK character, so
+/// numbers like `1,000` and URLs are left alone.
+pub fn normalize(text: &str, width: PunctuationWidth) -> String {
+  let chars = text.nfc().collect::<Vec<_>>();
+  chars
+    .iter()
+    .enumerate()
+    .map(|(i, &c)| {
+      if is_middle_dot(c) {
+        return '\u{00B7}';
+      }
+      match width {
+        PunctuationWidth::Keep => c,
+        PunctuationWidth::Half => FULL_HALF
+          .iter()
+          .find(|(full, _)| *full == c)
+          .map_or(c, |(_, half)| *half),
+        PunctuationWidth::Full => {
+          let Some((full, _)) = FULL_HALF.iter().find(|(_, half)| *half == c) else {
+            return c;
+          };
+          let touches_cjk =
+            (i > 0 && is_cjk(chars[i - 1])) || chars.get(i + 1).is_some_and(|&next| is_cjk(next));
+          if c.is_ascii() && !touches_cjk {
+            c
+          } else {
+            *full
+          }
+        }
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn middle_dots_match_after_normalizing() {
+    let phrase = "马克·吐温";
+    let sentence = "我喜欢马克・吐温的书。";
+    assert!(!sentence.contains(phrase));
+    let (phrase, sentence) = (
+      normalize(phrase, PunctuationWidth::Full),
+      normalize(sentence, PunctuationWidth::Full),
+    );
+    assert!(sentence.contains(&phrase));
+  }
+
+  #[test]
+  fn decomposed_and_compatibility_characters_match_after_normalizing() {
+    // ü as u plus a combining diaeresis, and the compatibility ideograph 豈.
+    let sentence = "nu\u{0308}和\u{F900}";
+    assert!(!sentence.contains("nü") && !sentence.contains("\u{8C48}"));
+    let sentence = normalize(sentence, PunctuationWidth::Keep);
+    assert!(sentence.contains("nü") && sentence.contains("\u{8C48}"));
+  }
+
+  #[test]
+  fn widens_punctuation_next_to_cjk_only() {
+    assert_eq!(normalize("你好!", PunctuationWidth::Full), "你好！");
+    assert_eq!(normalize("1,000", PunctuationWidth::Full), "1,000");
+    assert_eq!(normalize("你好！", PunctuationWidth::Half), "你好!");
+  }
+}
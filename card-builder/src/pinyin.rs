@@ -0,0 +1,181 @@
+const VOWELS: &str = "aeiouüvāáǎàēéěèīíǐìōóǒòūúǔùǖǘǚǜ";
+
+/// Tone marks in order of tone, one group per base vowel, then the same
+/// marks as combining characters for pinyin that isn't in NFC.
+const TONE_MARKS: [&str; 7] = [
+  "āáǎà",
+  "ēéěè",
+  "īíǐì",
+  "ōóǒò",
+  "ūúǔù",
+  "ǖǘǚǜ",
+  "\u{304}\u{301}\u{30C}\u{300}",
+];
+
+fn is_vowel(c: char) -> bool {
+  c.to_lowercase().any(|c| VOWELS.contains(c))
+}
+
+fn is_combining_mark(c: char) -> bool {
+  ('\u{300}'..='\u{36F}').contains(&c)
+}
+
+/// The nasals that can be syllables of their own, as in ńg (嗯) and hm (噷).
+fn is_nasal(c: char) -> bool {
+  matches!(c, 'n' | 'm' | 'ń' | 'ň' | 'ǹ' | 'ḿ')
+}
+
+fn mark_tone(c: char) -> Option<usize> {
+  let c = c.to_lowercase().next()?;
+  let nasal_tone = match c {
+    'ń' | 'ḿ' => Some(2),
+    'ň' => Some(3),
+    'ǹ' => Some(4),
+    _ => None,
+  };
+  TONE_MARKS
+    .iter()
+    .find_map(|marks| marks.chars().position(|m| m == c))
+    .map(|i| i + 1)
+    .or(nasal_tone)
+}
+
+/// Splits pinyin into syllables. Each syllable is an optional initial, a run
+/// of vowels, an optional final -n or -ng, and an optional erhua -r; the
+/// final and the -r are only taken when no vowel follows them. A syllable
+/// without vowels is a syllabic nasal (ng, m, hng). Tone numbers (`ni3`)
+/// stay attached to their syllable.
+///
+/// Anything else (spaces, apostrophes, punctuation) comes back as its own
+/// piece with `is_syllable` false.
+fn syllables(pinyin: &str) -> Vec<(&str, bool)> {
+  let chars = pinyin.char_indices().collect::<Vec<_>>();
+  let at = |i: usize| chars.get(i).map(|(_, c)| c.to_lowercase().next().unwrap());
+  let offset = |i: usize| chars.get(i).map_or(pinyin.len(), |(pos, _)| *pos);
+  let vowel_at = |i: usize| at(i).is_some_and(|c| is_vowel(c) || is_combining_mark(c));
+
+  let mut pieces = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let start = i;
+    if !chars[i].1.is_alphabetic() {
+      while i < chars.len() && !chars[i].1.is_alphabetic() {
+        i += 1;
+      }
+      pieces.push((&pinyin[offset(start)..offset(i)], false));
+      continue;
+    }
+
+    if matches!((at(i), at(i + 1)), (Some('z' | 'c' | 's'), Some('h'))) {
+      i += 2;
+    } else if !vowel_at(i) {
+      i += 1;
+    }
+    let vowels = i;
+    while vowel_at(i) {
+      i += 1;
+    }
+    if !(vowels..i).any(|j| at(j).is_some_and(is_vowel)) {
+      // The nasal was read as the initial, unless it follows h as in hng.
+      if at(start) == Some('h') && at(i).is_some_and(is_nasal) {
+        i += 1;
+      }
+      while at(i).is_some_and(is_combining_mark) {
+        i += 1;
+      }
+      if at(i - 1).is_some_and(|c| c != 'm') && at(i) == Some('g') && !vowel_at(i + 1) {
+        i += 1;
+      }
+    } else if at(i) == Some('n') && at(i + 1) == Some('g') && !vowel_at(i + 2) {
+      i += 2;
+    } else if at(i) == Some('n') && !vowel_at(i + 1) {
+      i += 1;
+    }
+    if at(i) == Some('r') && !vowel_at(i + 1) {
+      i += 1;
+    }
+    if at(i).is_some_and(|c| ('0'..='5').contains(&c)) {
+      i += 1;
+    }
+    pieces.push((&pinyin[offset(start)..offset(i)], true));
+  }
+  pieces
+}
+
+/// The tone of one syllable: 1-4 from a tone mark or number, and 0 for the
+/// neutral tone (no mark, or the number 0 or 5).
+fn tone(syllable: &str) -> usize {
+  if let Some(n) = syllable.chars().last().and_then(|c| c.to_digit(10)) {
+    return match n {
+      1..=4 => n as usize,
+      _ => 0,
+    };
+  }
+  syllable.chars().find_map(mark_tone).unwrap_or(0)
+}
+
+/// Wraps each syllable of `pinyin` in `<span class="tone-N">`, with N from 0
+/// (neutral) to 4. An erhua -r stays in the span of the syllable it modifies.
+pub fn pinyin_to_colored_html(pinyin: &str) -> String {
+  syllables(pinyin)
+    .into_iter()
+    .map(|(piece, is_syllable)| {
+      let escaped = html_escape::encode_safe(piece);
+      if is_syllable {
+        format!("<span class=\"tone-{}\">{escaped}</span>", tone(piece))
+      } else {
+        escaped.into_owned()
+      }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn split(pinyin: &str) -> Vec<&str> {
+    syllables(pinyin)
+      .into_iter()
+      .filter(|(_, is_syllable)| *is_syllable)
+      .map(|(piece, _)| piece)
+      .collect()
+  }
+
+  #[test]
+  fn splits_syllables() {
+    assert_eq!(split("Zhōngguó"), ["Zhōng", "guó"]);
+    assert_eq!(split("xī'ān"), ["xī", "ān"]);
+    assert_eq!(split("ni3 hao3"), ["ni3", "hao3"]);
+    assert_eq!(split("shēngrì"), ["shēng", "rì"]);
+  }
+
+  #[test]
+  fn neutral_tone() {
+    assert_eq!(
+      pinyin_to_colored_html("māma"),
+      "<span class=\"tone-1\">mā</span><span class=\"tone-0\">ma</span>"
+    );
+    assert_eq!(tone("ma5"), 0);
+    assert_eq!(tone("ma0"), 0);
+  }
+
+  #[test]
+  fn erhua_stays_with_its_syllable() {
+    assert_eq!(split("yìdiǎnr"), ["yì", "diǎnr"]);
+    assert_eq!(split("wánr"), ["wánr"]);
+    assert_eq!(tone("diǎnr"), 3);
+  }
+
+  #[test]
+  fn syllabic_nasals() {
+    assert_eq!(split("ňg"), ["ňg"]);
+    assert_eq!(tone("ňg"), 3);
+    assert_eq!(split("hm hng"), ["hm", "hng"]);
+    assert_eq!(split("ḿ"), ["ḿ"]);
+    assert_eq!(tone("ḿ"), 2);
+    // ǹg with a combining grave accent rather than ǹ.
+    assert_eq!(split("n\u{300}g"), ["n\u{300}g"]);
+    assert_eq!(tone("n\u{300}g"), 4);
+  }
+}
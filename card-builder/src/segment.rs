@@ -0,0 +1,183 @@
+use crate::error::Result;
+use ahash::HashMap;
+use jieba_rs::Jieba;
+use sha2::{Digest, Sha256};
+use std::{
+  fs::{self, File},
+  io::{self, BufReader, BufWriter},
+  path::{Path, PathBuf},
+};
+
+/// Everything about the segmenter's configuration that affects its output,
+/// other than the user dictionary.
+const JIEBA_CONFIG: &str = "jieba-rs 0.7; hmm = false";
+
+/// Sentence segmentation cached per corpus file.
+///
+/// Each sentence maps to the byte offsets where its words end. A cache file
+/// is named after a hash of the corpus file contents and the segmenter
+/// configuration, so any change to either simply misses the cache.
+struct SegmentCache {
+  path: PathBuf,
+  entries: HashMap<String, Vec<u32>>,
+  dirty: bool,
+}
+
+pub struct Segmenter {
+  jieba: Jieba,
+  fingerprint: Sha256,
+  cache_dir: Option<PathBuf>,
+  cache: Option<SegmentCache>,
+}
+
+impl Segmenter {
+  pub fn new(user_dict: Option<&Path>, cache_dir: Option<PathBuf>) -> Result<Self> {
+    let mut jieba = Jieba::new();
+    let mut fingerprint = Sha256::new();
+    fingerprint.update(JIEBA_CONFIG);
+    if let Some(path) = user_dict {
+      let contents = fs::read(path)?;
+      jieba.load_dict(&mut contents.as_slice())?;
+      fingerprint.update(&contents);
+    }
+    if let Some(dir) = &cache_dir {
+      fs::create_dir_all(dir)?;
+    }
+    Ok(Segmenter {
+      jieba,
+      fingerprint,
+      cache_dir,
+      cache: None,
+    })
+  }
+
+  /// Loads the cache for `corpus_path`, if caching is enabled.
+  pub fn load_cache(&mut self, corpus_path: &Path) -> Result<()> {
+    self.cache = None;
+    let Some(dir) = &self.cache_dir else {
+      return Ok(());
+    };
+
+    let mut hasher = self.fingerprint.clone();
+    io::copy(&mut BufReader::new(File::open(corpus_path)?), &mut hasher)?;
+    let key = hasher
+      .finalize()
+      .iter()
+      .map(|b| format!("{b:02x}"))
+      .collect::<String>();
+    let path = dir.join(format!("{key}.json"));
+
+    let entries = match File::open(&path) {
+      Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::default(),
+      Err(e) => return Err(e.into()),
+    };
+    self.cache = Some(SegmentCache {
+      path,
+      entries,
+      dirty: false,
+    });
+    Ok(())
+  }
+
+  /// Stops caching, for input that can't be fingerprinted such as stdin.
+  pub fn unload_cache(&mut self) {
+    self.cache = None;
+  }
+
+  /// Writes out any segmentations added since the cache was loaded.
+  pub fn save_cache(&mut self) -> Result<()> {
+    if let Some(cache) = &mut self.cache {
+      if cache.dirty {
+        let writer = BufWriter::new(File::create(&cache.path)?);
+        serde_json::to_writer(writer, &cache.entries)?;
+        cache.dirty = false;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn cut<'a>(&mut self, sentence: &'a str) -> Vec<&'a str> {
+    let Some(cache) = &mut self.cache else {
+      return self.jieba.cut(sentence, false);
+    };
+
+    let ends = match cache.entries.get(sentence) {
+      Some(ends) => ends,
+      None => {
+        let words = self.jieba.cut(sentence, false);
+        let ends = words
+          .iter()
+          .map(|word| (word.as_ptr() as usize - sentence.as_ptr() as usize + word.len()) as u32)
+          .collect();
+        cache.dirty = true;
+        cache.entries.entry(sentence.to_string()).or_insert(ends)
+      }
+    };
+
+    let mut start = 0;
+    ends
+      .iter()
+      .map(|&end| {
+        let word = &sentence[start..end as usize];
+        start = end as usize;
+        word
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SENTENCE: &str = "我们明天去图书馆学习中文";
+
+  fn cached(segmenter: &Segmenter) -> usize {
+    segmenter.cache.as_ref().unwrap().entries.len()
+  }
+
+  #[test]
+  fn saved_cache_gives_the_same_cut() {
+    let dir = tempfile::tempdir().unwrap();
+    let corpus = dir.path().join("corpus.jsonl");
+    fs::write(&corpus, SENTENCE).unwrap();
+    let cache_dir = dir.path().join("cache");
+
+    let mut segmenter = Segmenter::new(None, Some(cache_dir.clone())).unwrap();
+    segmenter.load_cache(&corpus).unwrap();
+    let words = segmenter.cut(SENTENCE);
+    segmenter.save_cache().unwrap();
+
+    let mut segmenter = Segmenter::new(None, Some(cache_dir)).unwrap();
+    segmenter.load_cache(&corpus).unwrap();
+    assert_eq!(cached(&segmenter), 1);
+    assert_eq!(segmenter.cut(SENTENCE), words);
+    assert_eq!(words, Jieba::new().cut(SENTENCE, false));
+  }
+
+  #[test]
+  fn other_dictionaries_and_corpora_miss_the_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let corpus = dir.path().join("corpus.jsonl");
+    fs::write(&corpus, SENTENCE).unwrap();
+    let user_dict = dir.path().join("dict.txt");
+    fs::write(&user_dict, "图书馆学习 10 n\n").unwrap();
+    let cache_dir = dir.path().join("cache");
+
+    let mut segmenter = Segmenter::new(None, Some(cache_dir.clone())).unwrap();
+    segmenter.load_cache(&corpus).unwrap();
+    segmenter.cut(SENTENCE);
+    segmenter.save_cache().unwrap();
+
+    let mut segmenter = Segmenter::new(Some(&user_dict), Some(cache_dir.clone())).unwrap();
+    segmenter.load_cache(&corpus).unwrap();
+    assert_eq!(cached(&segmenter), 0);
+    assert!(segmenter.cut(SENTENCE).contains(&"图书馆学习"));
+
+    fs::write(&corpus, format!("{SENTENCE}\n")).unwrap();
+    let mut segmenter = Segmenter::new(None, Some(cache_dir)).unwrap();
+    segmenter.load_cache(&corpus).unwrap();
+    assert_eq!(cached(&segmenter), 0);
+  }
+}
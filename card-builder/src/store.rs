@@ -0,0 +1,977 @@
+use crate::{
+  error::{Context, Error, Result},
+  file_db::{
+    read_manifest, remove_shards, shard_path, FileDbConfig, FileDbReader, FileDbWriter, ShardRange,
+    ShardWriter, ShardedFileDbReader,
+  },
+  vocab::{PhraseIdx, Vocab, VocabLevel, VocabPhrase},
+};
+use ahash::{HashMap, HashSet};
+use growable_bloom_filter::GrowableBloom;
+use indexical::map::DenseRefIndexMap;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  fs::{self, File},
+  hash::Hash,
+  io::{self, BufReader, BufWriter, Write},
+  path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Where corpus snippets are kept, along with which phrases each is for.
+///
+/// A corpus build calls [`SnippetStore::start_input`] and
+/// [`SnippetStore::finish_input`] around each corpus input's snippets, with
+/// [`SnippetStore::checkpoint`] every so often in between, then
+/// [`SnippetStore::finish`] once every input is read.
+pub trait SnippetStore {
+  /// Identifies one stored snippet.
+  type Key: Clone + Eq + Hash;
+
+  /// Stores `record` as a snippet of each of `phrases`.
+  fn write<T: Serialize>(&mut self, record: &T, phrases: &[PhraseIdx]) -> Result<Self::Key>;
+
+  fn read<T: DeserializeOwned>(&mut self, key: &Self::Key) -> Result<T>;
+
+  fn read_many<T: DeserializeOwned>(&mut self, keys: &[Self::Key]) -> Result<Vec<T>> {
+    keys.iter().map(|key| self.read(key)).collect()
+  }
+
+  /// The snippets stored for `phrase`, in the order they were written.
+  fn ranges_for(&mut self, phrase: PhraseIdx) -> Result<Vec<Self::Key>>;
+
+  fn snippet_count(&mut self, phrase: PhraseIdx) -> Result<usize> {
+    Ok(self.ranges_for(phrase)?.len())
+  }
+
+  /// Counts an occurrence in the corpus of each of `phrases`, whether or not
+  /// a snippet is stored for it.
+  fn count_occurrences(&mut self, phrases: &[PhraseIdx]) -> Result<()>;
+
+  /// How often `phrase` occurred in the sentences the build read. Unlike
+  /// [`SnippetStore::snippet_count`], this isn't capped.
+  fn occurrences(&mut self, phrase: PhraseIdx) -> Result<usize>;
+
+  fn start_input(&mut self, _input: usize) -> Result<()> {
+    Ok(())
+  }
+
+  /// Records that the build got as far as `cursor`, partway through an
+  /// input, so it can be resumed from there.
+  fn checkpoint(&mut self, _cursor: BuildCursor) -> Result<()> {
+    Ok(())
+  }
+
+  fn finish_input(&mut self, _input: usize) -> Result<()> {
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// How far a corpus build got: every input before `input` is finished, as
+/// are the first `entries` entries of `input`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildCursor {
+  pub input: usize,
+  pub entries: usize,
+}
+
+/// Chance that [`UsedKeys::Approx`] reports a key as used when it wasn't.
+///
+/// A false positive only ever drops a snippet that could have been a card;
+/// a used snippet is never reported unused, so it can't be repeated.
+pub const APPROX_DEDUP_ERROR_RATE: f64 = 0.001;
+
+/// The snippets already chosen for a deck, so they aren't chosen again.
+pub enum UsedKeys<K> {
+  Exact(HashSet<K>),
+  /// A bloom filter of the keys, which grows as it fills so memory stays
+  /// proportional to the keys inserted, at a few bytes per key.
+  Approx(GrowableBloom),
+}
+
+impl<K: Eq + Hash> UsedKeys<K> {
+  pub fn new(approx: bool) -> Self {
+    if approx {
+      UsedKeys::Approx(GrowableBloom::new(APPROX_DEDUP_ERROR_RATE, 100_000))
+    } else {
+      UsedKeys::Exact(HashSet::default())
+    }
+  }
+
+  pub fn contains(&self, key: &K) -> bool {
+    match self {
+      UsedKeys::Exact(keys) => keys.contains(key),
+      UsedKeys::Approx(filter) => filter.contains(key),
+    }
+  }
+
+  pub fn extend(&mut self, keys: impl IntoIterator<Item = K>) {
+    match self {
+      UsedKeys::Exact(set) => set.extend(keys),
+      UsedKeys::Approx(filter) => {
+        for key in keys {
+          filter.insert(&key);
+        }
+      }
+    }
+  }
+}
+
+pub type PhraseFileIndex<'a> = DenseRefIndexMap<'a, VocabPhrase, Vec<ShardRange>>;
+
+/// A count for each phrase, such as how often it occurred in the corpus.
+pub type PhraseCounts<'a> = DenseRefIndexMap<'a, VocabPhrase, usize>;
+
+/// The index as saved next to the snippet database: one list of ranges per
+/// phrase, in phrase order, tagged with the word list and corpus inputs it
+/// was built from and the database shards it points into.
+#[derive(Serialize, Deserialize)]
+struct PhraseIndexFile {
+  domain: String,
+  ranges: Vec<Vec<ShardRange>>,
+  /// SHA-256 of each shard, from its manifest.
+  shards: Vec<String>,
+  /// [`CorpusInput::fingerprint`](crate::corpus::CorpusInput::fingerprint)
+  /// of each corpus input.
+  inputs: Vec<String>,
+  /// How often each phrase occurred in the sentences read, in phrase order.
+  occurrences: Vec<usize>,
+}
+
+/// Identifies the phrase domain, so an index built from a different or
+/// edited word list is rejected rather than misread.
+fn domain_fingerprint(vocab: &Vocab) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(vocab.source.slug());
+  for phrase in vocab.phrases.iter() {
+    hasher.update([0]);
+    hasher.update(&phrase.form);
+    hasher.update(phrase.level.0.to_le_bytes());
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+impl PhraseIndexFile {
+  fn new(store: &FileStore, build: &FileBuild) -> Self {
+    PhraseIndexFile {
+      domain: domain_fingerprint(store.vocab),
+      ranges: store.index.values().cloned().collect(),
+      shards: build.shards.clone(),
+      inputs: build.inputs.clone(),
+      occurrences: store.occurrences.values().copied().collect(),
+    }
+  }
+
+  /// The index and occurrence counts, if they were built for `vocab` and the
+  /// database shards at `db` are the ones they were built with. `path` is
+  /// only used in errors.
+  fn into_index<'a>(
+    self,
+    vocab: &'a Vocab,
+    db: &Path,
+    path: &Path,
+  ) -> Result<(PhraseFileIndex<'a>, PhraseCounts<'a>)> {
+    if self.domain != domain_fingerprint(vocab)
+      || self.ranges.len() != vocab.phrases.len()
+      || self.occurrences.len() != vocab.phrases.len()
+    {
+      return Err(mismatch(
+        path,
+        "was built for a different word list; rebuild the corpus",
+      ));
+    }
+    for (shard, sha256) in self.shards.iter().enumerate() {
+      let manifest = read_manifest(shard_path(db, u16::try_from(shard)?))?;
+      if manifest.map(|manifest| manifest.sha256).as_ref() != Some(sha256) {
+        return Err(mismatch(
+          path,
+          "doesn't match the snippet database it was built with; rebuild the corpus",
+        ));
+      }
+    }
+    let mut ranges = self.ranges.into_iter();
+    let mut occurrences = self.occurrences.into_iter();
+    Ok((
+      DenseRefIndexMap::new(&&vocab.phrases, |_| ranges.next().unwrap()),
+      DenseRefIndexMap::new(&&vocab.phrases, |_| occurrences.next().unwrap()),
+    ))
+  }
+}
+
+fn mismatch(path: &Path, reason: &str) -> Error {
+  Error::Mismatch {
+    path: path.to_owned(),
+    reason: reason.to_string(),
+  }
+}
+
+/// The corpus inputs an index or database was built from differ from the
+/// ones it's being used with.
+const INPUTS_CHANGED: &str =
+  "was built from other corpus inputs, or they have changed since; rebuild the corpus";
+
+fn write_phrase_index(path: &Path, store: &FileStore, build: &FileBuild) -> Result<()> {
+  let mut writer = BufWriter::new(File::create(path)?);
+  bincode::serialize_into(&mut writer, &PhraseIndexFile::new(store, build))?;
+  writer.flush()?;
+  Ok(())
+}
+
+/// Reads the index, checking it was built from `inputs` if given.
+fn read_phrase_index<'a>(
+  paths: &FileStorePaths,
+  vocab: &'a Vocab,
+  inputs: Option<&[String]>,
+) -> Result<(PhraseFileIndex<'a>, PhraseCounts<'a>)> {
+  let path = &paths.index;
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+      return Err(Error::MissingDatabase {
+        path: path.to_owned(),
+      })
+    }
+    Err(err) => return Err(err).with_context(|| format!("opening {}", path.display())),
+  };
+  let file: PhraseIndexFile = bincode::deserialize_from(BufReader::new(file))
+    .with_context(|| format!("{} is corrupt or from an older version", path.display()))?;
+  if inputs.is_some_and(|inputs| inputs != file.inputs) {
+    return Err(mismatch(path, INPUTS_CHANGED));
+  }
+  file.into_index(vocab, &paths.db, path)
+}
+
+/// Progress of a corpus build, saved after each corpus input and every so
+/// often within one, so an interrupted build can be resumed with `--resume`.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+  /// The index so far, whose inputs keep the build from being resumed with
+  /// other corpus inputs.
+  index: PhraseIndexFile,
+  /// The options the build was started with, so it isn't resumed with
+  /// others.
+  options: String,
+  /// How far the build got. `cursor.input` is also how many database shards
+  /// are complete.
+  cursor: BuildCursor,
+  /// How long the unfinished input's shard was at the checkpoint, if it was
+  /// started. Anything written after is cut off when resuming.
+  shard_len: Option<u64>,
+}
+
+impl Checkpoint {
+  fn write(&self, path: &Path) -> Result<()> {
+    // Written aside and renamed, so a crash mid-write leaves the previous
+    // checkpoint intact.
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    bincode::serialize_into(&mut writer, self)?;
+    writer.flush()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+  }
+
+  fn read(path: &Path) -> Result<Option<Self>> {
+    if !path.exists() {
+      return Ok(None);
+    }
+    let reader = BufReader::new(File::open(path)?);
+    let checkpoint = bincode::deserialize_from(reader)
+      .with_context(|| format!("{} is corrupt or from an older version", path.display()))?;
+    Ok(Some(checkpoint))
+  }
+}
+
+/// Opens one shard of a [`FileStore`]'s database, e.g. with
+/// [`FileDbReader::load`].
+pub type OpenShard = Box<dyn FnMut(PathBuf) -> Result<FileDbReader>>;
+
+/// Where a [`FileStore`] keeps its files.
+#[derive(Clone, Debug)]
+pub struct FileStorePaths {
+  /// The database, split into shards next to this path.
+  pub db: PathBuf,
+  /// The phrase index, written when a build finishes.
+  pub index: PathBuf,
+  /// Progress of an unfinished build.
+  pub checkpoint: PathBuf,
+}
+
+/// The sharded flat-file snippet database, with its phrase index in memory.
+pub struct FileStore<'a> {
+  vocab: &'a Vocab,
+  paths: FileStorePaths,
+  index: PhraseFileIndex<'a>,
+  occurrences: PhraseCounts<'a>,
+  /// Opened on the first read, once the shards are written.
+  reader: Option<ShardedFileDbReader>,
+  open: OpenShard,
+  build: Option<FileBuild>,
+}
+
+/// A corpus build in progress on a [`FileStore`].
+struct FileBuild {
+  db_config: FileDbConfig,
+  /// Fingerprints of the corpus inputs, recorded in checkpoints and the
+  /// index.
+  inputs: Vec<String>,
+  /// The build options, recorded in checkpoints.
+  options: String,
+  /// Each input writes its own shard.
+  writer: Option<ShardWriter>,
+  /// The input a resumed build stopped partway through, and the length its
+  /// shard is cut back to before writing more.
+  resumed_shard: Option<(usize, u64)>,
+  /// SHA-256 of each finished shard, from its manifest.
+  shards: Vec<String>,
+  db_bytes: u64,
+  record_bytes: u64,
+  json_bytes: u64,
+}
+
+impl<'a> FileStore<'a> {
+  /// Loads the index saved by the last build, opening shards with `open`.
+  /// With `inputs`, the build must have been of corpus inputs with those
+  /// fingerprints.
+  pub fn load(
+    vocab: &'a Vocab,
+    paths: FileStorePaths,
+    inputs: Option<&[String]>,
+    open: OpenShard,
+  ) -> Result<Self> {
+    let (index, occurrences) = read_phrase_index(&paths, vocab, inputs)?;
+    Ok(FileStore {
+      vocab,
+      index,
+      occurrences,
+      paths,
+      reader: None,
+      open,
+      build: None,
+    })
+  }
+
+  /// Starts a corpus build of the inputs fingerprinted `input_names` with
+  /// `options`, from the checkpoint if `resume` is set. Also returns how far
+  /// the checkpointed build got.
+  pub fn create(
+    vocab: &'a Vocab,
+    paths: FileStorePaths,
+    input_names: Vec<String>,
+    options: String,
+    resume: bool,
+    db_config: FileDbConfig,
+    open: OpenShard,
+  ) -> Result<(Self, BuildCursor)> {
+    let checkpoint = match resume {
+      true => Checkpoint::read(&paths.checkpoint)?,
+      false => None,
+    };
+    let ((index, occurrences), shards, cursor, resumed_shard) = match checkpoint {
+      Some(checkpoint) => {
+        if checkpoint.index.inputs != input_names {
+          return Err(mismatch(
+            &paths.checkpoint,
+            "is for different corpus inputs; start the build over",
+          ));
+        }
+        if checkpoint.options != options {
+          return Err(mismatch(
+            &paths.checkpoint,
+            "is for a build with different options; start the build over",
+          ));
+        }
+        let cursor = checkpoint.cursor;
+        info!(
+          inputs_done = cursor.input,
+          entries_done = cursor.entries,
+          "resuming corpus build from checkpoint"
+        );
+        let shards = checkpoint.index.shards.clone();
+        (
+          checkpoint
+            .index
+            .into_index(vocab, &paths.db, &paths.checkpoint)?,
+          shards,
+          cursor,
+          checkpoint.shard_len.map(|len| (cursor.input, len)),
+        )
+      }
+      None => {
+        if resume {
+          warn!(
+            path = %paths.checkpoint.display(),
+            "no checkpoint found, starting from scratch"
+          );
+        }
+        remove_shards(&paths.db, 0)?;
+        (
+          (
+            DenseRefIndexMap::new(&&vocab.phrases, |_| Vec::new()),
+            DenseRefIndexMap::new(&&vocab.phrases, |_| 0),
+          ),
+          Vec::new(),
+          BuildCursor::default(),
+          None,
+        )
+      }
+    };
+    let store = FileStore {
+      vocab,
+      paths,
+      index,
+      occurrences,
+      reader: None,
+      open,
+      build: Some(FileBuild {
+        db_config,
+        inputs: input_names,
+        options,
+        writer: None,
+        resumed_shard,
+        shards,
+        db_bytes: 0,
+        record_bytes: 0,
+        json_bytes: 0,
+      }),
+    };
+    Ok((store, cursor))
+  }
+
+  fn reader(&mut self) -> Result<&mut ShardedFileDbReader> {
+    if self.reader.is_none() {
+      self.reader = Some(ShardedFileDbReader::open(&self.paths.db, &mut self.open)?);
+    }
+    Ok(self.reader.as_mut().unwrap())
+  }
+
+  fn build(&mut self) -> Result<&mut FileBuild> {
+    self
+      .build
+      .as_mut()
+      .context("the snippet database was opened for reading")
+  }
+
+  fn write_checkpoint(&self, cursor: BuildCursor, shard_len: Option<u64>) -> Result<()> {
+    let build = self.build.as_ref().unwrap();
+    Checkpoint {
+      index: PhraseIndexFile::new(self, build),
+      options: build.options.clone(),
+      cursor,
+      shard_len,
+    }
+    .write(&self.paths.checkpoint)
+  }
+}
+
+impl SnippetStore for FileStore<'_> {
+  type Key = ShardRange;
+
+  fn write<T: Serialize>(&mut self, record: &T, phrases: &[PhraseIdx]) -> Result<ShardRange> {
+    let range = self
+      .build()?
+      .writer
+      .as_mut()
+      .context("no corpus input was started")?
+      .write(record)?;
+    for idx in phrases {
+      self.index[*idx].push(range.clone());
+    }
+    Ok(range)
+  }
+
+  fn read<T: DeserializeOwned>(&mut self, key: &ShardRange) -> Result<T> {
+    self.reader()?.read(key.clone())
+  }
+
+  fn read_many<T: DeserializeOwned>(&mut self, keys: &[ShardRange]) -> Result<Vec<T>> {
+    self.reader()?.read_many(keys)
+  }
+
+  fn ranges_for(&mut self, phrase: PhraseIdx) -> Result<Vec<ShardRange>> {
+    Ok(self.index[phrase].clone())
+  }
+
+  fn snippet_count(&mut self, phrase: PhraseIdx) -> Result<usize> {
+    Ok(self.index[phrase].len())
+  }
+
+  fn count_occurrences(&mut self, phrases: &[PhraseIdx]) -> Result<()> {
+    for idx in phrases {
+      self.occurrences[*idx] += 1;
+    }
+    Ok(())
+  }
+
+  fn occurrences(&mut self, phrase: PhraseIdx) -> Result<usize> {
+    Ok(self.occurrences[phrase])
+  }
+
+  fn start_input(&mut self, input: usize) -> Result<()> {
+    let shard = u16::try_from(input).context("too many corpus inputs")?;
+    let path = shard_path(&self.paths.db, shard);
+    let build = self.build()?;
+    let writer = match build.resumed_shard.take() {
+      Some((resumed, len)) if resumed == input => {
+        File::options().write(true).open(&path)?.set_len(len)?;
+        FileDbWriter::append(&path, build.db_config)
+      }
+      _ => FileDbWriter::create(&path, build.db_config),
+    };
+    build.writer = Some(ShardWriter::new(
+      shard,
+      writer.with_context(|| format!("opening {}", path.display()))?,
+    ));
+    Ok(())
+  }
+
+  fn checkpoint(&mut self, cursor: BuildCursor) -> Result<()> {
+    let shard_len = self
+      .build()?
+      .writer
+      .as_mut()
+      .context("no corpus input was started")?
+      .flush()?;
+    self.write_checkpoint(cursor, Some(shard_len))
+  }
+
+  fn finish_input(&mut self, input: usize) -> Result<()> {
+    let build = self.build()?;
+    let writer = build
+      .writer
+      .take()
+      .context("no corpus input was started")?
+      .into_inner();
+    build.record_bytes += writer.record_bytes();
+    build.json_bytes += writer.json_bytes();
+    let manifest = writer.finish()?;
+    build.db_bytes += manifest.bytes;
+    build.shards.push(manifest.sha256);
+    let cursor = BuildCursor {
+      input: input + 1,
+      entries: 0,
+    };
+    self.write_checkpoint(cursor, None)
+  }
+
+  fn finish(&mut self) -> Result<()> {
+    let build = self.build.take().context("no corpus build to finish")?;
+    // Shards past the last one written are from an earlier build.
+    remove_shards(&self.paths.db, u16::try_from(build.shards.len())?)?;
+    info!(
+      bytes = build.db_bytes,
+      shards = build.shards.len(),
+      path = %self.paths.db.display(),
+      "wrote snippet database"
+    );
+    if build.db_config.compression {
+      info!(
+        compressed = build.record_bytes,
+        uncompressed = build.json_bytes,
+        ratio = format!(
+          "{:.2}",
+          build.json_bytes as f64 / build.record_bytes.max(1) as f64
+        ),
+        "compressed snippets"
+      );
+    }
+
+    write_phrase_index(&self.paths.index, self, &build)?;
+    if self.paths.checkpoint.exists() {
+      fs::remove_file(&self.paths.checkpoint)?;
+    }
+    self.reader = None;
+    Ok(())
+  }
+}
+
+const SCHEMA: &str = "
+  CREATE TABLE snippets (
+    id INTEGER PRIMARY KEY,
+    json TEXT NOT NULL
+  );
+  CREATE TABLE phrase_snippets (
+    form TEXT NOT NULL,
+    level INTEGER NOT NULL,
+    snippet_id INTEGER NOT NULL REFERENCES snippets (id)
+  );
+  CREATE INDEX phrase_snippets_phrase ON phrase_snippets (form, level);
+  CREATE TABLE phrase_occurrences (
+    form TEXT NOT NULL,
+    level INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (form, level)
+  );
+  CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+  );
+";
+
+/// Snippets in an SQLite database: a `snippets` table of JSON records, a
+/// `phrase_snippets` table linking each phrase, by its form and level, to
+/// its snippets, and a `phrase_occurrences` table of how often each phrase
+/// occurred in the corpus. Other tools can query it without knowing the
+/// flat-file format.
+pub struct SqliteStore<'a> {
+  conn: Connection,
+  vocab: &'a Vocab,
+  /// The first phrase of each form and level. A word list can list a form
+  /// at one level several times, but only the first collects snippets.
+  first: HashMap<(&'a str, VocabLevel), PhraseIdx>,
+  /// Whether a build's writes are still uncommitted.
+  writing: bool,
+}
+
+impl<'a> SqliteStore<'a> {
+  /// Creates an empty database at `path`, replacing any already there, for
+  /// a build of the corpus inputs fingerprinted `inputs`.
+  pub fn create(path: &Path, vocab: &'a Vocab, inputs: &[String]) -> Result<Self> {
+    if path.exists() {
+      fs::remove_file(path)?;
+    }
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    conn.execute(
+      "INSERT INTO meta (key, value) VALUES ('inputs', ?)",
+      params![serde_json::to_string(inputs)?],
+    )?;
+    // One transaction for the whole build; committing every insert would
+    // make it far slower.
+    conn.execute_batch("BEGIN")?;
+    let mut store = Self::new(conn, vocab);
+    store.writing = true;
+    Ok(store)
+  }
+
+  /// Calls `f` on every snippet of the database at `path`, in the order
+  /// they were written. Unlike [`SqliteStore::open`], this needs no word list.
+  pub fn scan_all<T: DeserializeOwned, F: FnMut(T)>(path: &Path, mut f: F) -> Result<()> {
+    if !path.exists() {
+      return Err(Error::MissingDatabase {
+        path: path.to_owned(),
+      });
+    }
+    let conn = Connection::open(path)?;
+    let mut statement = conn.prepare("SELECT json FROM snippets ORDER BY id")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+      let json: String = row.get(0)?;
+      f(serde_json::from_str(&json)?);
+    }
+    Ok(())
+  }
+
+  /// Opens the database written by an earlier build. With `inputs`, the
+  /// build must have been of corpus inputs with those fingerprints.
+  pub fn open(path: &Path, vocab: &'a Vocab, inputs: Option<&[String]>) -> Result<Self> {
+    if !path.exists() {
+      return Err(Error::MissingDatabase {
+        path: path.to_owned(),
+      });
+    }
+    let conn = Connection::open(path)?;
+    if let Some(inputs) = inputs {
+      let built: String = conn
+        .query_row("SELECT value FROM meta WHERE key = 'inputs'", [], |row| {
+          row.get(0)
+        })
+        .with_context(|| format!("{} is from an older version", path.display()))?;
+      if serde_json::from_str::<Vec<String>>(&built)? != inputs {
+        return Err(mismatch(path, INPUTS_CHANGED));
+      }
+    }
+    Ok(Self::new(conn, vocab))
+  }
+
+  fn new(conn: Connection, vocab: &'a Vocab) -> Self {
+    let mut first = HashMap::default();
+    for (idx, phrase) in vocab.phrases.iter_enumerated() {
+      first
+        .entry((phrase.form.as_str(), phrase.level))
+        .or_insert(idx);
+    }
+    SqliteStore {
+      conn,
+      vocab,
+      first,
+      writing: false,
+    }
+  }
+
+  /// The form and level `phrase` is stored under, or `None` if an earlier
+  /// phrase already has them.
+  fn key(&self, phrase: PhraseIdx) -> Option<(&'a str, i64)> {
+    let phrase_value = self.vocab.phrases.value(phrase);
+    let key = (phrase_value.form.as_str(), phrase_value.level);
+    (self.first[&key] == phrase).then_some((key.0, key.1 .0 as i64))
+  }
+}
+
+impl SnippetStore for SqliteStore<'_> {
+  type Key = i64;
+
+  fn write<T: Serialize>(&mut self, record: &T, phrases: &[PhraseIdx]) -> Result<i64> {
+    self
+      .conn
+      .prepare_cached("INSERT INTO snippets (json) VALUES (?)")?
+      .execute(params![serde_json::to_string(record)?])?;
+    let id = self.conn.last_insert_rowid();
+    let mut link = self
+      .conn
+      .prepare_cached("INSERT INTO phrase_snippets (form, level, snippet_id) VALUES (?, ?, ?)")?;
+    for phrase in phrases {
+      let phrase = self.vocab.phrases.value(*phrase);
+      link.execute(params![phrase.form, phrase.level.0 as i64, id])?;
+    }
+    Ok(id)
+  }
+
+  fn read<T: DeserializeOwned>(&mut self, key: &i64) -> Result<T> {
+    let json: String = self
+      .conn
+      .prepare_cached("SELECT json FROM snippets WHERE id = ?")?
+      .query_row(params![key], |row| row.get(0))?;
+    Ok(serde_json::from_str(&json)?)
+  }
+
+  fn ranges_for(&mut self, phrase: PhraseIdx) -> Result<Vec<i64>> {
+    let Some((form, level)) = self.key(phrase) else {
+      return Ok(Vec::new());
+    };
+    let mut query = self.conn.prepare_cached(
+      "SELECT snippet_id FROM phrase_snippets WHERE form = ? AND level = ? ORDER BY snippet_id",
+    )?;
+    let ids = query
+      .query_map(params![form, level], |row| row.get(0))?
+      .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(ids)
+  }
+
+  fn snippet_count(&mut self, phrase: PhraseIdx) -> Result<usize> {
+    let Some((form, level)) = self.key(phrase) else {
+      return Ok(0);
+    };
+    let count: i64 = self
+      .conn
+      .prepare_cached("SELECT COUNT(*) FROM phrase_snippets WHERE form = ? AND level = ?")?
+      .query_row(params![form, level], |row| row.get(0))?;
+    Ok(count as usize)
+  }
+
+  fn count_occurrences(&mut self, phrases: &[PhraseIdx]) -> Result<()> {
+    let mut count = self.conn.prepare_cached(
+      "INSERT INTO phrase_occurrences (form, level, count) VALUES (?, ?, 1)
+        ON CONFLICT (form, level) DO UPDATE SET count = count + 1",
+    )?;
+    for phrase in phrases {
+      let phrase = self.vocab.phrases.value(*phrase);
+      count.execute(params![phrase.form, phrase.level.0 as i64])?;
+    }
+    Ok(())
+  }
+
+  fn occurrences(&mut self, phrase: PhraseIdx) -> Result<usize> {
+    let phrase = self.vocab.phrases.value(phrase);
+    let count: Option<i64> = self
+      .conn
+      .prepare_cached("SELECT count FROM phrase_occurrences WHERE form = ? AND level = ?")?
+      .query_row(params![phrase.form, phrase.level.0 as i64], |row| {
+        row.get(0)
+      })
+      .optional()?;
+    Ok(count.unwrap_or(0) as usize)
+  }
+
+  fn finish(&mut self) -> Result<()> {
+    if self.writing {
+      self.conn.execute_batch("COMMIT")?;
+      self.writing = false;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    normalize::PunctuationWidth,
+    vocab::{read_vocab_from, VocabFormat, VocabSource},
+  };
+
+  fn vocab() -> Vocab {
+    read_vocab_from(
+      "Simplified,Level\n我,1\n你,1\n".as_bytes(),
+      VocabSource::Hsk3,
+      VocabFormat::Csv,
+      PunctuationWidth::Full,
+      true,
+    )
+    .unwrap()
+  }
+
+  fn paths(dir: &Path) -> FileStorePaths {
+    FileStorePaths {
+      db: dir.join("phrases.txt"),
+      index: dir.join("phrases.idx"),
+      checkpoint: dir.join("phrases.checkpoint"),
+    }
+  }
+
+  fn create<'a>(
+    vocab: &'a Vocab,
+    dir: &Path,
+    options: &str,
+    resume: bool,
+  ) -> Result<(FileStore<'a>, BuildCursor)> {
+    FileStore::create(
+      vocab,
+      paths(dir),
+      vec!["corpus".to_string()],
+      options.to_string(),
+      resume,
+      FileDbConfig::default(),
+      Box::new(FileDbReader::load),
+    )
+  }
+
+  #[test]
+  fn sqlite_store_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snippets.sqlite");
+    let vocab = vocab();
+    assert!(matches!(
+      SqliteStore::open(&path, &vocab, None),
+      Err(Error::MissingDatabase { .. })
+    ));
+    let [wo, ni] = [0, 1].map(|i| vocab.phrases.indices().nth(i).unwrap());
+    let inputs = ["corpus".to_string()];
+
+    let mut store = SqliteStore::create(&path, &vocab, &inputs).unwrap();
+    let both = store.write(&"我你", &[wo, ni]).unwrap();
+    let one = store.write(&"你", &[ni]).unwrap();
+    store.count_occurrences(&[wo, ni, ni]).unwrap();
+    store.finish().unwrap();
+    drop(store);
+
+    assert!(matches!(
+      SqliteStore::open(&path, &vocab, Some(&["other".to_string()])),
+      Err(Error::Mismatch { .. })
+    ));
+    let mut store = SqliteStore::open(&path, &vocab, Some(&inputs)).unwrap();
+    assert_eq!(store.ranges_for(wo).unwrap(), [both]);
+    assert_eq!(store.ranges_for(ni).unwrap(), [both, one]);
+    assert_eq!(store.snippet_count(ni).unwrap(), 2);
+    assert_eq!(store.read::<String>(&one).unwrap(), "你");
+    assert_eq!(
+      store.read_many::<String>(&[one, both]).unwrap(),
+      ["你", "我你"]
+    );
+    assert_eq!(store.occurrences(wo).unwrap(), 1);
+    assert_eq!(store.occurrences(ni).unwrap(), 2);
+  }
+
+  #[test]
+  fn sqlite_store_keys_phrases_by_form_and_level() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snippets.sqlite");
+    // 学习 at two levels, and listed twice at the second.
+    let vocab = read_vocab_from(
+      "Simplified,Level\n学习,1\n学习,2\n学习,2\n".as_bytes(),
+      VocabSource::Hsk3,
+      VocabFormat::Csv,
+      PunctuationWidth::Full,
+      true,
+    )
+    .unwrap();
+    let phrases = vocab.phrases.indices().collect::<Vec<_>>();
+    let levels = phrases
+      .iter()
+      .map(|idx| vocab.phrases.value(*idx).level.0)
+      .collect::<Vec<_>>();
+    assert_eq!(levels, [1, 2, 2]);
+
+    let mut store = SqliteStore::create(&path, &vocab, &[]).unwrap();
+    let key = store.write(&"学习", &[phrases[1]]).unwrap();
+    store.finish().unwrap();
+    assert!(store.ranges_for(phrases[0]).unwrap().is_empty());
+    assert_eq!(store.ranges_for(phrases[1]).unwrap(), [key]);
+    // Only the first listing of a form and level has snippets.
+    assert!(store.ranges_for(phrases[2]).unwrap().is_empty());
+    assert_eq!(store.snippet_count(phrases[2]).unwrap(), 0);
+  }
+
+  #[test]
+  fn resumes_from_a_checkpoint_within_an_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let vocab = vocab();
+    let phrase = vocab.phrases.indices().next().unwrap();
+
+    let (mut store, cursor) = create(&vocab, dir.path(), "options", false).unwrap();
+    assert_eq!(cursor, BuildCursor::default());
+    store.start_input(0).unwrap();
+    store.write(&"first", &[phrase]).unwrap();
+    let cursor = BuildCursor {
+      input: 0,
+      entries: 1,
+    };
+    store.checkpoint(cursor).unwrap();
+    // Written after the checkpoint, then lost to a crash.
+    store.write(&"lost", &[phrase]).unwrap();
+    drop(store);
+
+    assert!(matches!(
+      create(&vocab, dir.path(), "other options", true),
+      Err(Error::Mismatch { .. })
+    ));
+    let (mut store, resumed) = create(&vocab, dir.path(), "options", true).unwrap();
+    assert_eq!(resumed, cursor);
+    store.start_input(0).unwrap();
+    store.write(&"second", &[phrase]).unwrap();
+    store.finish_input(0).unwrap();
+    store.finish().unwrap();
+    assert!(!dir.path().join("phrases.checkpoint").exists());
+
+    let mut store = FileStore::load(
+      &vocab,
+      paths(dir.path()),
+      Some(&["corpus".to_string()]),
+      Box::new(FileDbReader::load),
+    )
+    .unwrap();
+    let ranges = store.ranges_for(phrase).unwrap();
+    assert_eq!(
+      store.read_many::<String>(&ranges).unwrap(),
+      ["first", "second"]
+    );
+  }
+
+  #[test]
+  fn approx_dedup_matches_exact() {
+    let used = (0..20_000).step_by(2).collect::<Vec<i64>>();
+    for approx in [false, true] {
+      let mut keys = UsedKeys::new(approx);
+      keys.extend(used.iter().copied());
+      assert!(used.iter().all(|key| keys.contains(key)));
+
+      let false_positives = (1..20_000)
+        .step_by(2)
+        .filter(|key| keys.contains(key))
+        .count();
+      if approx {
+        // 10 times the expected rate, so the test isn't flaky.
+        assert!(false_positives <= (10_000. * APPROX_DEDUP_ERROR_RATE * 10.) as usize);
+      } else {
+        assert_eq!(false_positives, 0);
+      }
+    }
+  }
+}
@@ -0,0 +1,106 @@
+use crate::error::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+};
+use tracing::warn;
+
+/// Something that can turn a sentence into an audio file.
+pub trait TtsBackend {
+  fn synthesize(&self, text: &str, output: &Path) -> Result<()>;
+}
+
+/// Runs a local program such as `piper` or `espeak-ng`, passing the text on
+/// stdin. Any `{output}` argument is replaced with the file to write.
+pub struct CommandBackend {
+  program: String,
+  args: Vec<String>,
+}
+
+impl CommandBackend {
+  /// Parses a whitespace-separated command line, e.g.
+  /// `piper --model zh_CN.onnx --output_file {output}`.
+  pub fn parse(command: &str) -> Result<Self> {
+    let mut words = command.split_whitespace().map(String::from);
+    let program = words.next().context("empty TTS command")?;
+    Ok(CommandBackend {
+      program,
+      args: words.collect(),
+    })
+  }
+}
+
+impl TtsBackend for CommandBackend {
+  fn synthesize(&self, text: &str, output: &Path) -> Result<()> {
+    let output = output.to_str().context("audio path is not valid UTF-8")?;
+    let mut child = Command::new(&self.program)
+      .args(self.args.iter().map(|arg| arg.replace("{output}", output)))
+      .stdin(Stdio::piped())
+      .stdout(Stdio::null())
+      .stderr(Stdio::piped())
+      .spawn()
+      .with_context(|| format!("running {}", self.program))?;
+    // Programs that don't read the text close stdin early; that's their
+    // business, and the exit status below still reports real failures.
+    match child.stdin.take().unwrap().write_all(text.as_bytes()) {
+      Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+      _ => {}
+    }
+    let result = child.wait_with_output()?;
+    if !result.status.success() {
+      bail!(
+        "{} exited with {}: {}",
+        self.program,
+        result.status,
+        String::from_utf8_lossy(&result.stderr).trim()
+      );
+    }
+    Ok(())
+  }
+}
+
+/// Synthesized sentences stored in a directory by content hash, so re-runs
+/// only synthesize sentences they haven't seen.
+pub struct AudioCache {
+  backend: Box<dyn TtsBackend>,
+  dir: PathBuf,
+  extension: String,
+}
+
+impl AudioCache {
+  pub fn new(backend: Box<dyn TtsBackend>, dir: PathBuf, extension: String) -> Result<Self> {
+    fs::create_dir_all(&dir)?;
+    Ok(AudioCache {
+      backend,
+      dir,
+      extension,
+    })
+  }
+
+  /// The audio file for `text`, synthesizing it if it isn't cached. A failed
+  /// synthesis is logged and yields `None`, so the card is just silent.
+  pub fn get(&self, text: &str) -> Option<PathBuf> {
+    let hash = format!("{:x}", Sha256::digest(text));
+    let path = self
+      .dir
+      .join(format!("zhlearn-{}.{}", &hash[..16], self.extension));
+    if path.exists() {
+      return Some(path);
+    }
+    match self.backend.synthesize(text, &path) {
+      Ok(()) if path.exists() => Some(path),
+      Ok(()) => {
+        warn!(text, "TTS backend produced no audio file");
+        None
+      }
+      Err(err) => {
+        warn!(text, %err, "TTS failed, leaving the card silent");
+        let _ = fs::remove_file(&path);
+        None
+      }
+    }
+  }
+}
@@ -0,0 +1,1084 @@
+use crate::{
+  error::{bail, Context, Error, Result},
+  normalize::{normalize, PunctuationWidth},
+};
+use ahash::{HashMap, HashSet};
+use aho_corasick::{AhoCorasick, MatchKind};
+use clap::ValueEnum;
+use indexical::{define_index_type, IndexedDomain};
+use itertools::Itertools;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use smallvec::SmallVec;
+use std::{
+  collections::BTreeMap,
+  fs::File,
+  io::{BufRead, BufReader, Read},
+  ops::Range,
+  path::Path,
+};
+use tracing::warn;
+
+#[derive(PartialEq, Debug, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct VocabLevel(pub usize);
+
+/// The vocabulary standard a word list comes from.
+#[derive(PartialEq, Debug, Eq, Hash, Clone, Copy, ValueEnum)]
+pub enum VocabSource {
+  /// HSK 2.0, the older six-level standard (simplified characters).
+  Hsk2,
+  /// HSK 3.0 (simplified characters).
+  #[value(alias = "hsk")]
+  Hsk3,
+  /// TOCFL (traditional characters).
+  Tocfl,
+  /// A plain list with one `word[,level]` per line; the level defaults to 1.
+  Wordlist,
+}
+
+impl VocabSource {
+  pub fn name(self) -> &'static str {
+    match self {
+      VocabSource::Hsk2 => "HSK 2.0",
+      VocabSource::Hsk3 => "HSK",
+      VocabSource::Tocfl => "TOCFL",
+      VocabSource::Wordlist => "Wordlist",
+    }
+  }
+
+  /// Lowercase name used in file names.
+  pub fn slug(self) -> &'static str {
+    match self {
+      VocabSource::Hsk2 => "hsk2",
+      VocabSource::Hsk3 => "hsk",
+      VocabSource::Tocfl => "tocfl",
+      VocabSource::Wordlist => "wordlist",
+    }
+  }
+
+  /// Where the word list is by default, relative to the data directory.
+  pub fn default_path(self) -> &'static str {
+    match self {
+      VocabSource::Hsk2 => "hsk20.csv",
+      VocabSource::Hsk3 => "hsk30-expanded.csv",
+      VocabSource::Tocfl => "tocfl.csv",
+      VocabSource::Wordlist => "wordlist.txt",
+    }
+  }
+
+  /// Base for Anki deck IDs, distinct per source so decks can share a collection.
+  pub fn deck_id_base(self) -> usize {
+    match self {
+      VocabSource::Hsk2 => 883199,
+      VocabSource::Hsk3 => 881199,
+      VocabSource::Tocfl => 882199,
+      VocabSource::Wordlist => 884199,
+    }
+  }
+
+  /// Human-readable name of a level, e.g. "Level 3" or "Novice 1".
+  pub fn level_name(self, level: VocabLevel) -> String {
+    match (self, level.0) {
+      (VocabSource::Tocfl, n @ 1..=2) => format!("Novice {n}"),
+      (VocabSource::Tocfl, n) => format!("Level {}", n - 2),
+      (VocabSource::Hsk2 | VocabSource::Hsk3 | VocabSource::Wordlist, n) => format!("Level {n}"),
+    }
+  }
+}
+
+/// How a word list file is encoded.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, ValueEnum)]
+pub enum VocabFormat {
+  /// A CSV file with a header row.
+  Csv,
+  /// A JSON array of objects with the same keys as the CSV columns.
+  Json,
+}
+
+impl VocabFormat {
+  /// JSON for `.json` files, CSV otherwise.
+  pub fn detect(path: &Path) -> Self {
+    match path.extension().and_then(|ext| ext.to_str()) {
+      Some(ext) if ext.eq_ignore_ascii_case("json") => VocabFormat::Json,
+      _ => VocabFormat::Csv,
+    }
+  }
+}
+
+/// Coarse part of speech, used for tagging and grouping notes.
+#[derive(PartialEq, Debug, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub enum PartOfSpeech {
+  Noun,
+  Verb,
+  Adjective,
+  Adverb,
+  Pronoun,
+  Numeral,
+  Measure,
+  Preposition,
+  Conjunction,
+  Particle,
+  Interjection,
+  Other,
+}
+
+impl PartOfSpeech {
+  /// Parses a POS column, which may use Chinese (名, 动) or English (N, V)
+  /// abbreviations and may list several; only the first is kept.
+  fn parse(s: &str) -> Option<Self> {
+    let first = s
+      .split(['、', '/', ',', '，', ' ', ';'])
+      .map(str::trim)
+      .find(|s| !s.is_empty())?;
+    Some(match first.to_lowercase().as_str() {
+      "名" | "n" | "noun" => PartOfSpeech::Noun,
+      "动" | "動" | "v" | "verb" => PartOfSpeech::Verb,
+      "形" | "adj" | "a" | "adjective" => PartOfSpeech::Adjective,
+      "副" | "adv" | "d" | "adverb" => PartOfSpeech::Adverb,
+      "代" | "pron" | "r" | "pronoun" => PartOfSpeech::Pronoun,
+      "数" | "數" | "num" | "m" | "numeral" => PartOfSpeech::Numeral,
+      "量" | "mw" | "q" | "measure" => PartOfSpeech::Measure,
+      "介" | "prep" | "p" | "preposition" => PartOfSpeech::Preposition,
+      "连" | "連" | "conj" | "c" | "conjunction" => PartOfSpeech::Conjunction,
+      "助" | "part" | "u" | "particle" => PartOfSpeech::Particle,
+      "叹" | "嘆" | "int" | "e" | "interjection" => PartOfSpeech::Interjection,
+      _ => PartOfSpeech::Other,
+    })
+  }
+
+  /// Position in declaration order, starting at 1; used to derive deck IDs.
+  pub fn ordinal(self) -> usize {
+    self as usize + 1
+  }
+
+  /// Capitalized name used for subdecks.
+  pub fn label(self) -> &'static str {
+    match self {
+      PartOfSpeech::Noun => "Noun",
+      PartOfSpeech::Verb => "Verb",
+      PartOfSpeech::Adjective => "Adjective",
+      PartOfSpeech::Adverb => "Adverb",
+      PartOfSpeech::Pronoun => "Pronoun",
+      PartOfSpeech::Numeral => "Numeral",
+      PartOfSpeech::Measure => "Measure Word",
+      PartOfSpeech::Preposition => "Preposition",
+      PartOfSpeech::Conjunction => "Conjunction",
+      PartOfSpeech::Particle => "Particle",
+      PartOfSpeech::Interjection => "Interjection",
+      PartOfSpeech::Other => "Other",
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      PartOfSpeech::Noun => "noun",
+      PartOfSpeech::Verb => "verb",
+      PartOfSpeech::Adjective => "adjective",
+      PartOfSpeech::Adverb => "adverb",
+      PartOfSpeech::Pronoun => "pronoun",
+      PartOfSpeech::Numeral => "numeral",
+      PartOfSpeech::Measure => "measure-word",
+      PartOfSpeech::Preposition => "preposition",
+      PartOfSpeech::Conjunction => "conjunction",
+      PartOfSpeech::Particle => "particle",
+      PartOfSpeech::Interjection => "interjection",
+      PartOfSpeech::Other => "other",
+    }
+  }
+}
+
+fn parse_pos<'de, D>(deserializer: D) -> Result<Option<PartOfSpeech>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let buf = Option::<String>::deserialize(deserializer)?;
+  Ok(buf.as_deref().and_then(PartOfSpeech::parse))
+}
+
+/// A level as written in the word list. JSON lists may give numeric levels
+/// as numbers rather than strings.
+fn level_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Level {
+    Number(u64),
+    String(String),
+  }
+  Ok(match Level::deserialize(deserializer)? {
+    Level::Number(n) => n.to_string(),
+    Level::String(s) => s,
+  })
+}
+
+/// HSK 3.0 has levels 1-9. Some lists give the last three as one "7-9"
+/// band, whose words are put at each of levels 7, 8, and 9. `--dedup-phrases`
+/// treats the band as one level, so its words keep all three. HSK 2.0 only
+/// has levels 1-6.
+fn parse_hsk_level(level: &str, source: VocabSource) -> Result<Vec<VocabLevel>> {
+  let level = level.trim();
+  let advanced = source == VocabSource::Hsk3;
+  if advanced && level == "7-9" {
+    return Ok((7..=9).map(VocabLevel).collect());
+  }
+  let max = if advanced { 9 } else { 6 };
+  match level.parse::<usize>() {
+    Ok(n) if (1..=max).contains(&n) => Ok(vec![VocabLevel(n)]),
+    _ => Err(unknown_level(source, level)),
+  }
+}
+
+/// TOCFL levels are numbered 1-7 in order: Novice 1, Novice 2, then Levels 1-5.
+/// The list may use either that number or the band's Chinese name.
+fn parse_tocfl_level(level: &str) -> Result<VocabLevel> {
+  const BANDS: [&str; 7] = [
+    "準備一級",
+    "準備二級",
+    "入門級",
+    "基礎級",
+    "進階級",
+    "高階級",
+    "流利級",
+  ];
+  let level = level.trim();
+  if let Some(i) = BANDS.iter().position(|band| *band == level) {
+    return Ok(VocabLevel(i + 1));
+  }
+  match level.parse::<usize>() {
+    Ok(n @ 1..=7) => Ok(VocabLevel(n)),
+    _ => Err(unknown_level(VocabSource::Tocfl, level)),
+  }
+}
+
+fn unknown_level(source: VocabSource, level: &str) -> Error {
+  Error::UnknownLevel {
+    list: source.name(),
+    level: level.to_string(),
+  }
+}
+
+/// A row of an HSK 2.0 or 3.0 list, which share their columns.
+#[derive(Deserialize)]
+struct HskRow {
+  #[serde(rename = "Simplified")]
+  simplified: String,
+  #[serde(rename = "Level", deserialize_with = "level_string")]
+  level: String,
+  #[serde(rename = "POS", default, deserialize_with = "parse_pos")]
+  pos: Option<PartOfSpeech>,
+  #[serde(rename = "Pinyin", default)]
+  pinyin: Option<String>,
+  #[serde(rename = "Definition", default)]
+  definition: Option<String>,
+  #[serde(rename = "Example", default)]
+  example: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TocflRow {
+  #[serde(rename = "Word")]
+  word: String,
+  #[serde(rename = "Level", deserialize_with = "level_string")]
+  level: String,
+  #[serde(rename = "POS", default, deserialize_with = "parse_pos")]
+  pos: Option<PartOfSpeech>,
+  #[serde(rename = "Pinyin", default)]
+  pinyin: Option<String>,
+  #[serde(rename = "Definition", default)]
+  definition: Option<String>,
+  #[serde(rename = "Example", default)]
+  example: Option<String>,
+}
+
+/// The fields every word list provides, whatever its column names.
+struct Entry {
+  form: String,
+  /// Usually one level, but a row can stand for several.
+  levels: Vec<VocabLevel>,
+  pos: Option<PartOfSpeech>,
+  pinyin: Option<String>,
+  definition: Option<String>,
+  example: Option<String>,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+pub struct VocabPhrase {
+  /// The written form, simplified or traditional depending on `source`.
+  pub form: String,
+  pub level: VocabLevel,
+  pub source: VocabSource,
+  pub pos: Option<PartOfSpeech>,
+  pub pinyin: Option<String>,
+  /// An English gloss, if the word list has one.
+  pub definition: Option<String>,
+  /// An official example sentence, used when the corpus has none.
+  pub example: Option<String>,
+  /// Position in the word frequency list, 1 being the most common word, if
+  /// the list has the phrase.
+  pub freq_rank: Option<u32>,
+  /// The first level of the band the word list gives the phrase, as 7 for
+  /// "7-9", if it gives one.
+  pub band: Option<VocabLevel>,
+}
+
+define_index_type! {
+  pub struct PhraseIdx for VocabPhrase = u32;
+}
+
+// Combined word lists can run past 65535 phrases, so indices are 32 bits.
+const _: () = assert!(PhraseIdx::MAX_INDEX == u32::MAX as usize);
+
+/// How phrases are located in a sentence.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, ValueEnum)]
+pub enum Matcher {
+  /// Look up each jieba word in the vocabulary.
+  Jieba,
+  /// Match vocabulary forms against the sentence directly, using jieba only
+  /// to reject matches that cross word boundaries.
+  Aho,
+}
+
+/// What analysis does with a sentence's words that are neither in the word
+/// list nor known.
+#[derive(PartialEq, Debug, Eq, Clone, Copy)]
+pub enum UnknownWords {
+  /// Reject the sentence.
+  Reject,
+  /// Leave the words out and analyze the rest.
+  Skip,
+}
+
+/// Which level keeps a form that the word list puts at several levels.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, ValueEnum)]
+pub enum DedupPolicy {
+  /// The level of its first row in the file.
+  First,
+  Lowest,
+  Highest,
+}
+
+pub struct Vocab {
+  pub source: VocabSource,
+  pub phrases: IndexedDomain<VocabPhrase>,
+  /// Forms can repeat both across levels and within one (separate senses or
+  /// readings), so every phrase for a form is kept, in file order.
+  levels: BTreeMap<VocabLevel, HashMap<String, SmallVec<[PhraseIdx; 2]>>>,
+  automaton: AhoCorasick,
+  /// The phrase each automaton pattern resolves to.
+  patterns: Vec<PhraseIdx>,
+  /// Words the learner already knows. These are accepted as sentence context
+  /// but never chosen as targets, whether or not they are in the word list.
+  known: HashSet<String>,
+}
+
+/// Word list rows that couldn't be read. Unless `strict`, they are skipped
+/// with a warning, so a stray header or note doesn't stop the build.
+struct BadRows {
+  strict: bool,
+  skipped: usize,
+}
+
+impl BadRows {
+  fn new(strict: bool) -> Self {
+    BadRows { strict, skipped: 0 }
+  }
+
+  fn skip(&mut self, row: usize, err: impl Into<Error>) -> Result<()> {
+    let err = err.into();
+    if self.strict {
+      return Err(err).with_context(|| format!("row {row}"));
+    }
+    warn!(row, %err, "skipping word list row");
+    self.skipped += 1;
+    Ok(())
+  }
+
+  fn finish(self) {
+    if self.skipped > 0 {
+      warn!(
+        skipped = self.skipped,
+        "skipped word list rows that couldn't be read"
+      );
+    }
+  }
+}
+
+fn read_rows<R: DeserializeOwned>(
+  reader: impl Read,
+  format: VocabFormat,
+  strict: bool,
+  f: impl Fn(R) -> Result<Entry>,
+) -> Result<Vec<Entry>> {
+  let mut bad_rows = BadRows::new(strict);
+  let mut entries = Vec::new();
+  match format {
+    VocabFormat::Csv => {
+      for (i, row) in csv::Reader::from_reader(reader).deserialize().enumerate() {
+        match row {
+          Ok(row) => match f(row) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => bad_rows.skip(i + 2, err)?,
+          },
+          Err(err)
+            if matches!(
+              err.kind(),
+              csv::ErrorKind::Deserialize { .. } | csv::ErrorKind::UnequalLengths { .. }
+            ) =>
+          {
+            // Rows are counted from the header, as in a spreadsheet.
+            bad_rows.skip(i + 2, err)?
+          }
+          Err(err) => return Err(err.into()),
+        }
+      }
+    }
+    VocabFormat::Json => {
+      let rows: Vec<serde_json::Value> = serde_json::from_reader(BufReader::new(reader))?;
+      for (i, row) in rows.into_iter().enumerate() {
+        match serde_json::from_value(row) {
+          Ok(row) => match f(row) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => bad_rows.skip(i + 1, err)?,
+          },
+          Err(err) => bad_rows.skip(i + 1, err)?,
+        }
+      }
+    }
+  }
+  bad_rows.finish();
+  Ok(entries)
+}
+
+fn read_wordlist(reader: impl Read, strict: bool) -> Result<Vec<Entry>> {
+  let mut bad_rows = BadRows::new(strict);
+  let mut entries = Vec::new();
+  for (i, line) in BufReader::new(reader).lines().enumerate() {
+    let line = line?;
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    let (form, level) = match line.split_once(',') {
+      Some((form, level)) => match level.trim().parse() {
+        Ok(level) => (form.trim(), level),
+        Err(_) => {
+          bad_rows.skip(i + 1, unknown_level(VocabSource::Wordlist, level.trim()))?;
+          continue;
+        }
+      },
+      None => (line, 1),
+    };
+    entries.push(Entry {
+      form: form.to_string(),
+      levels: vec![VocabLevel(level)],
+      pos: None,
+      pinyin: None,
+      definition: None,
+      example: None,
+    });
+  }
+  bad_rows.finish();
+  Ok(entries)
+}
+
+pub fn read_vocab(
+  path: impl AsRef<Path>,
+  source: VocabSource,
+  format: VocabFormat,
+  width: PunctuationWidth,
+  strict: bool,
+) -> Result<Vocab> {
+  read_vocab_from(File::open(path)?, source, format, width, strict)
+}
+
+/// Reads a word list, normalizing each form the same way corpus text is so
+/// the two can be matched exactly. Rows that can't be read, like one with
+/// an unknown level, are skipped with a warning unless `strict`.
+pub fn read_vocab_from(
+  reader: impl Read,
+  source: VocabSource,
+  format: VocabFormat,
+  width: PunctuationWidth,
+  strict: bool,
+) -> Result<Vocab> {
+  let phrases = match source {
+    VocabSource::Wordlist if format == VocabFormat::Json => {
+      bail!("plain word lists can't be read as JSON")
+    }
+    VocabSource::Wordlist => read_wordlist(reader, strict),
+    VocabSource::Hsk2 | VocabSource::Hsk3 => read_rows(reader, format, strict, |row: HskRow| {
+      Ok(Entry {
+        form: row.simplified,
+        levels: parse_hsk_level(&row.level, source)?,
+        pos: row.pos,
+        pinyin: row.pinyin,
+        definition: row.definition,
+        example: row.example,
+      })
+    }),
+    VocabSource::Tocfl => read_rows(reader, format, strict, |row: TocflRow| {
+      Ok(Entry {
+        form: row.word,
+        levels: vec![parse_tocfl_level(&row.level)?],
+        pos: row.pos,
+        pinyin: row.pinyin,
+        definition: row.definition,
+        example: row.example,
+      })
+    }),
+  }?
+  .into_iter()
+  .flat_map(|entry| {
+    let phrase = VocabPhrase {
+      form: normalize(&entry.form, width),
+      level: VocabLevel(0),
+      source,
+      pos: entry.pos,
+      pinyin: entry.pinyin.filter(|pinyin| !pinyin.trim().is_empty()),
+      definition: entry
+        .definition
+        .filter(|definition| !definition.trim().is_empty()),
+      example: entry
+        .example
+        .filter(|example| !example.trim().is_empty())
+        .map(|example| normalize(example.trim(), width)),
+      freq_rank: None,
+      band: (entry.levels.len() > 1).then(|| entry.levels[0]),
+    };
+    entry.levels.into_iter().map(move |level| VocabPhrase {
+      level,
+      ..phrase.clone()
+    })
+  })
+  .collect::<Vec<_>>();
+  Vocab::from_phrases(source, phrases)
+}
+
+/// Reads a word frequency list, most common word first, into each word's
+/// 1-based rank.
+///
+/// Each line starts with a word, optionally followed by comma- or
+/// tab-separated columns such as a count (as in SUBTLEX-CH or the BLCU
+/// corpus lists), which are ignored. A first line whose second column isn't
+/// a number is taken as a header.
+pub fn read_frequency_list(path: &Path, width: PunctuationWidth) -> Result<HashMap<String, u32>> {
+  let file = BufReader::new(File::open(path)?);
+  let mut ranks = HashMap::default();
+  let mut rank = 0;
+  for (i, line) in file.lines().enumerate() {
+    let line = line?;
+    let mut columns = line.split(['\t', ',']).map(str::trim);
+    let word = columns.next().unwrap();
+    if word.is_empty() || word.starts_with('#') {
+      continue;
+    }
+    let is_header = i == 0
+      && columns
+        .next()
+        .is_some_and(|count| count.parse::<f64>().is_err());
+    if is_header {
+      continue;
+    }
+    rank += 1;
+    ranks.entry(normalize(word, width)).or_insert(rank);
+  }
+  Ok(ranks)
+}
+
+impl Vocab {
+  fn from_phrases(source: VocabSource, phrases: Vec<VocabPhrase>) -> Result<Self> {
+    if phrases.len() > PhraseIdx::MAX_INDEX {
+      bail!(
+        "the word list has {} phrases, more than can be indexed",
+        phrases.len()
+      );
+    }
+    let phrases = IndexedDomain::from_iter(phrases);
+    let levels = phrases
+      .iter()
+      .map(|phrase| phrase.level)
+      .unique()
+      .map(|level| {
+        let mut level_phrases: HashMap<String, SmallVec<[PhraseIdx; 2]>> = HashMap::default();
+        for (idx, phrase) in phrases.iter_enumerated() {
+          if phrase.level == level {
+            level_phrases
+              .entry(phrase.form.clone())
+              .or_default()
+              .push(idx);
+          }
+        }
+        (level, level_phrases)
+      })
+      .collect::<BTreeMap<_, _>>();
+
+    let mut vocab = Vocab {
+      source,
+      phrases,
+      levels,
+      automaton: AhoCorasick::new(Vec::<&str>::new())?,
+      patterns: Vec::new(),
+      known: HashSet::default(),
+    };
+    let forms = vocab
+      .phrases
+      .iter()
+      .map(|phrase| phrase.form.as_str())
+      .unique()
+      .collect::<Vec<_>>();
+    vocab.patterns = forms
+      .iter()
+      .map(|form| vocab.lookup(form).unwrap().1)
+      .collect();
+    vocab.automaton = AhoCorasick::builder()
+      .match_kind(MatchKind::LeftmostLongest)
+      .build(&forms)?;
+    Ok(vocab)
+  }
+
+  /// Keeps each form at a single level, chosen by `policy`, dropping its
+  /// entries at every other level. Returns how many entries were dropped.
+  ///
+  /// A band counts as the one level it starts at, so a form only listed in
+  /// "7-9" stays at all of 7, 8, and 9.
+  pub fn dedup_levels(&mut self, policy: DedupPolicy) -> Result<usize> {
+    let level = |phrase: &VocabPhrase| phrase.band.unwrap_or(phrase.level);
+    let mut keep: HashMap<&str, VocabLevel> = HashMap::default();
+    for phrase in self.phrases.iter() {
+      keep
+        .entry(phrase.form.as_str())
+        .and_modify(|kept| {
+          *kept = match policy {
+            DedupPolicy::First => *kept,
+            DedupPolicy::Lowest => (*kept).min(level(phrase)),
+            DedupPolicy::Highest => (*kept).max(level(phrase)),
+          }
+        })
+        .or_insert(level(phrase));
+    }
+    let phrases = self
+      .phrases
+      .iter()
+      .filter(|phrase| keep[phrase.form.as_str()] == level(phrase))
+      .cloned()
+      .collect::<Vec<_>>();
+    let removed = self.phrases.len() - phrases.len();
+    if removed > 0 {
+      let known = std::mem::take(&mut self.known);
+      *self = Vocab::from_phrases(self.source, phrases)?;
+      self.known = known;
+    }
+    Ok(removed)
+  }
+
+  /// Sets each phrase's `freq_rank` from `ranks`. Returns how many phrases
+  /// were ranked.
+  pub fn set_frequency_ranks(&mut self, ranks: &HashMap<String, u32>) -> Result<usize> {
+    let phrases = self
+      .phrases
+      .iter()
+      .map(|phrase| VocabPhrase {
+        freq_rank: ranks.get(&phrase.form).copied(),
+        ..phrase.clone()
+      })
+      .collect::<Vec<_>>();
+    let ranked = phrases
+      .iter()
+      .filter(|phrase| phrase.freq_rank.is_some())
+      .count();
+    let known = std::mem::take(&mut self.known);
+    *self = Vocab::from_phrases(self.source, phrases)?;
+    self.known = known;
+    Ok(ranked)
+  }
+
+  /// The lowest level `word` appears at, and the first phrase listed for it
+  /// there.
+  fn lookup(&self, word: &str) -> Option<(VocabLevel, PhraseIdx)> {
+    self
+      .levels
+      .iter()
+      .find_map(|(level, forms)| Some((*level, *forms.get(word)?.first()?)))
+  }
+
+  /// Every level present in the word list, in ascending order.
+  pub fn levels(&self) -> impl Iterator<Item = VocabLevel> + '_ {
+    self.levels.keys().copied()
+  }
+
+  /// Marks `words`, plus every phrase at one of `levels`, as already known.
+  pub fn set_known(&mut self, words: impl IntoIterator<Item = String>, levels: &[VocabLevel]) {
+    self.known.extend(words);
+    let level_forms = self
+      .phrases
+      .iter()
+      .filter(|phrase| levels.contains(&phrase.level))
+      .map(|phrase| phrase.form.clone())
+      .collect::<Vec<_>>();
+    self.known.extend(level_forms);
+  }
+
+  pub fn is_known(&self, idx: PhraseIdx) -> bool {
+    self.known.contains(&self.phrases.value(idx).form)
+  }
+
+  /// The share of `words` that are neither in the word list nor known.
+  pub fn unknown_ratio(&self, words: &[&str]) -> f32 {
+    if words.is_empty() {
+      return 0.;
+    }
+    let unknown = words
+      .iter()
+      .filter(|word| self.lookup(word).is_none() && !self.known.contains(**word))
+      .count();
+    unknown as f32 / words.len() as f32
+  }
+
+  /// Every phrase among `words`, repeats included, skipping words outside
+  /// the word list.
+  pub fn phrases_in<'a>(&'a self, words: &'a [&str]) -> impl Iterator<Item = PhraseIdx> + 'a {
+    words.iter().filter_map(|word| Some(self.lookup(word)?.1))
+  }
+
+  /// The word list entry of each word that isn't known, or `None` for a
+  /// word outside the list. `unknown` says whether those are kept as `None`
+  /// or left out.
+  fn lookup_all<'a>(
+    &'a self,
+    words: &'a [&str],
+    unknown: UnknownWords,
+  ) -> impl Iterator<Item = Option<(VocabLevel, PhraseIdx)>> + 'a {
+    words
+      .iter()
+      .filter(|word| !self.known.contains(**word))
+      .map(|word| self.lookup(word))
+      .filter(move |entry| entry.is_some() || unknown == UnknownWords::Reject)
+  }
+
+  /// Every phrase among `words` with its level, or `None` if a word is
+  /// neither in the word list nor known and `unknown` rejects it. Unlike
+  /// [`Vocab::analyze`], phrases below the sentence's hardest level are kept.
+  pub fn analyze_all(
+    &self,
+    words: &[&str],
+    unknown: UnknownWords,
+  ) -> Option<Vec<(PhraseIdx, VocabLevel)>> {
+    self
+      .lookup_all(words, unknown)
+      .map(|entry| {
+        let (level, idx) = entry?;
+        Some((idx, level))
+      })
+      .collect()
+  }
+
+  /// The phrases among `words` at the sentence's hardest level, or `None`
+  /// if it has none or a word is neither in the word list nor known and
+  /// `unknown` rejects it.
+  pub fn analyze<'a>(
+    &'a self,
+    words: &'a [&str],
+    unknown: UnknownWords,
+  ) -> Option<impl Iterator<Item = PhraseIdx> + 'a> {
+    let word_analysis = self
+      .lookup_all(words, unknown)
+      .collect::<Option<Vec<_>>>()?;
+    let level = *word_analysis.iter().map(|(level, _)| level).max()?;
+    Some(
+      word_analysis
+        .into_iter()
+        .map(|(_, idx)| idx)
+        .filter(move |idx| self.phrases.value(*idx).level == level),
+    )
+  }
+
+  /// Every vocabulary phrase in `sentence`, with leftmost-longest semantics.
+  pub fn find_phrases(&self, sentence: &str) -> Vec<(PhraseIdx, Range<usize>)> {
+    self
+      .automaton
+      .find_iter(sentence)
+      .map(|m| (self.patterns[m.pattern()], m.range()))
+      .collect()
+  }
+
+  /// Like [`Vocab::analyze`], but with phrases found by [`Vocab::find_phrases`].
+  ///
+  /// `words` is the jieba segmentation of `sentence`. Matches that don't start
+  /// and end on a word boundary are dropped, and a word that isn't covered
+  /// by a remaining match is unknown.
+  pub fn analyze_matches(
+    &self,
+    sentence: &str,
+    words: &[&str],
+    unknown: UnknownWords,
+  ) -> Option<Vec<PhraseIdx>> {
+    let matches = self.analyze_matches_all(sentence, words, unknown)?;
+    let level = matches.iter().map(|(_, level)| *level).max()?;
+    Some(
+      matches
+        .into_iter()
+        .filter(|(_, l)| *l == level)
+        .map(|(idx, _)| idx)
+        .collect(),
+    )
+  }
+
+  /// Like [`Vocab::analyze_all`], but with phrases found by
+  /// [`Vocab::find_phrases`] as in [`Vocab::analyze_matches`].
+  pub fn analyze_matches_all(
+    &self,
+    sentence: &str,
+    words: &[&str],
+    unknown: UnknownWords,
+  ) -> Option<Vec<(PhraseIdx, VocabLevel)>> {
+    let mut boundaries = vec![0];
+    boundaries.extend(words.iter().scan(0, |end, word| {
+      *end += word.len();
+      Some(*end)
+    }));
+
+    let matches = self
+      .find_phrases(sentence)
+      .into_iter()
+      .filter(|(_, range)| {
+        boundaries.binary_search(&range.start).is_ok()
+          && boundaries.binary_search(&range.end).is_ok()
+      })
+      .collect::<Vec<_>>();
+
+    // Known words outside the word list never match, so they are counted as
+    // covered by their jieba word instead.
+    let mut covered = vec![false; words.len()];
+    for (_, range) in &matches {
+      let start = boundaries.binary_search(&range.start).unwrap();
+      let end = boundaries.binary_search(&range.end).unwrap();
+      covered[start..end].fill(true);
+    }
+    for (word, covered) in words.iter().zip(&mut covered) {
+      *covered |= self.known.contains(*word);
+    }
+    if unknown == UnknownWords::Reject && covered.contains(&false) {
+      return None;
+    }
+    Some(
+      matches
+        .into_iter()
+        .filter(|(idx, _)| !self.is_known(*idx))
+        .map(|(idx, _)| (idx, self.phrases.value(idx).level))
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WORDS: &str = "Simplified,Level\n我,1\n喜欢,1\n你,1\n学习,2\n中文,2\n";
+
+  fn vocab() -> Vocab {
+    read_vocab_from(
+      WORDS.as_bytes(),
+      VocabSource::Hsk3,
+      VocabFormat::Csv,
+      PunctuationWidth::Full,
+      true,
+    )
+    .unwrap()
+  }
+
+  fn forms(vocab: &Vocab, words: &[&str]) -> Option<Vec<String>> {
+    let phrases = vocab.analyze(words, UnknownWords::Reject)?;
+    Some(
+      phrases
+        .map(|idx| vocab.phrases.value(idx).form.clone())
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn analyze_returns_phrases_at_the_hardest_level() {
+    let vocab = vocab();
+    assert_eq!(
+      forms(&vocab, &["我", "喜欢", "学习", "中文"]),
+      Some(vec!["学习".to_string(), "中文".to_string()])
+    );
+    assert_eq!(
+      forms(&vocab, &["我", "喜欢", "你"]),
+      Some(vec!["我".to_string(), "喜欢".to_string(), "你".to_string()])
+    );
+  }
+
+  #[test]
+  fn unreadable_rows_are_skipped_unless_strict() {
+    let words = "Simplified,Level\n我,1\nSimplified,Level\n注：,附录\n学习,2\n";
+    let read = |strict| {
+      read_vocab_from(
+        words.as_bytes(),
+        VocabSource::Hsk3,
+        VocabFormat::Csv,
+        PunctuationWidth::Full,
+        strict,
+      )
+    };
+    let vocab = read(false).unwrap();
+    let forms = vocab
+      .phrases
+      .iter()
+      .map(|phrase| phrase.form.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(forms, ["我", "学习"]);
+
+    let err = read(true).err().unwrap();
+    assert_eq!(err.to_string(), "row 3");
+    assert!(
+      matches!(err.root(), Error::UnknownLevel { level, .. } if level == "Level"),
+      "{:?}",
+      err.root()
+    );
+  }
+
+  #[test]
+  fn hsk3_advanced_levels() {
+    let words = "Simplified,Level\n经济,7-9\n哲学,8\n宇宙,9\n";
+    let vocab = read_vocab_from(
+      words.as_bytes(),
+      VocabSource::Hsk3,
+      VocabFormat::Csv,
+      PunctuationWidth::Full,
+      true,
+    )
+    .unwrap();
+    let phrases = vocab
+      .phrases
+      .iter()
+      .map(|phrase| (phrase.form.as_str(), phrase.level.0))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      phrases,
+      [
+        ("经济", 7),
+        ("经济", 8),
+        ("经济", 9),
+        ("哲学", 8),
+        ("宇宙", 9)
+      ]
+    );
+    assert_eq!(
+      vocab.levels().collect::<Vec<_>>(),
+      [VocabLevel(7), VocabLevel(8), VocabLevel(9)]
+    );
+  }
+
+  #[test]
+  fn dedup_keeps_bands_whole() {
+    let words = "Simplified,Level
+经济,7-9
+哲学,8
+哲学,7-9
+学习,2
+学习,7-9
+";
+    let mut vocab = read_vocab_from(
+      words.as_bytes(),
+      VocabSource::Hsk3,
+      VocabFormat::Csv,
+      PunctuationWidth::Full,
+      true,
+    )
+    .unwrap();
+    assert_eq!(vocab.dedup_levels(DedupPolicy::Lowest).unwrap(), 4);
+    let phrases = vocab
+      .phrases
+      .iter()
+      .map(|phrase| (phrase.form.as_str(), phrase.level.0))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      phrases,
+      [
+        ("经济", 7),
+        ("经济", 8),
+        ("经济", 9),
+        ("哲学", 7),
+        ("哲学", 8),
+        ("哲学", 9),
+        ("学习", 2)
+      ]
+    );
+  }
+
+  #[test]
+  fn words_at_several_levels_count_at_the_lowest() {
+    let words = "Simplified,Level\n我,1\n学习,5\n学习,2\n";
+    let vocab = read_vocab_from(
+      words.as_bytes(),
+      VocabSource::Hsk3,
+      VocabFormat::Csv,
+      PunctuationWidth::Full,
+      true,
+    )
+    .unwrap();
+    let phrases = vocab
+      .analyze(&["我", "学习"], UnknownWords::Reject)
+      .unwrap()
+      .collect::<Vec<_>>();
+    let levels = phrases
+      .iter()
+      .map(|idx| vocab.phrases.value(*idx).level.0)
+      .collect::<Vec<_>>();
+    assert_eq!(levels, [2]);
+  }
+
+  #[test]
+  fn analyze_all_keeps_every_level() {
+    let vocab = vocab();
+    let phrases = vocab
+      .analyze_all(&["我", "喜欢", "学习", "中文"], UnknownWords::Reject)
+      .unwrap();
+    let forms = phrases
+      .iter()
+      .map(|(idx, level)| (vocab.phrases.value(*idx).form.as_str(), level.0))
+      .collect::<Vec<_>>();
+    assert_eq!(forms, [("我", 1), ("喜欢", 1), ("学习", 2), ("中文", 2)]);
+    assert_eq!(
+      vocab.analyze_all(&["我", "咖啡"], UnknownWords::Reject),
+      None
+    );
+  }
+
+  #[test]
+  fn analyze_rejects_unknown_words() {
+    let vocab = vocab();
+    assert_eq!(forms(&vocab, &["我", "喜欢", "咖啡"]), None);
+    assert_eq!(forms(&vocab, &[]), None);
+    let phrases = vocab
+      .analyze(&["我", "喜欢", "咖啡"], UnknownWords::Skip)
+      .unwrap()
+      .map(|idx| vocab.phrases.value(idx).form.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(phrases, ["我", "喜欢"]);
+    assert!(vocab.analyze(&["咖啡"], UnknownWords::Skip).is_none());
+  }
+
+  #[test]
+  fn unknown_ratio_counts_words_outside_the_list() {
+    let mut vocab = vocab();
+    assert_eq!(vocab.unknown_ratio(&["我", "喜欢", "咖啡", "茶"]), 0.5);
+    // Known words aren't unknown, even outside the word list.
+    vocab.set_known(["咖啡".to_string()], &[]);
+    assert_eq!(vocab.unknown_ratio(&["我", "喜欢", "咖啡", "茶"]), 0.25);
+    assert_eq!(vocab.unknown_ratio(&["我", "学习"]), 0.);
+    assert_eq!(vocab.unknown_ratio(&[]), 0.);
+  }
+
+  #[test]
+  fn analyze_skips_known_words() {
+    let mut vocab = vocab();
+    vocab.set_known(["咖啡".to_string()], &[VocabLevel(2)]);
+    assert_eq!(
+      forms(&vocab, &["我", "学习", "咖啡"]),
+      Some(vec!["我".to_string()])
+    );
+  }
+}
@@ -0,0 +1,219 @@
+use crate::error::Result;
+use quick_xml::{events::Event, Reader};
+use regex::Regex;
+use std::{io::BufRead, sync::LazyLock};
+
+/// Article text from a MediaWiki XML dump, read one page at a time so the
+/// dump never has to fit in memory. Only main-namespace pages are returned,
+/// and redirects are skipped.
+pub struct WikiPages<R> {
+  reader: Reader<R>,
+  buf: Vec<u8>,
+}
+
+impl<R: BufRead> WikiPages<R> {
+  pub fn new(reader: R) -> Self {
+    WikiPages {
+      reader: Reader::from_reader(reader),
+      buf: Vec::new(),
+    }
+  }
+
+  fn next_page(&mut self) -> Result<Option<String>> {
+    let mut field = None;
+    let mut namespace = String::new();
+    let mut text = String::new();
+    loop {
+      self.buf.clear();
+      match self.reader.read_event_into(&mut self.buf)? {
+        Event::Start(e) => match e.name().as_ref() {
+          b"page" => {
+            namespace.clear();
+            text.clear();
+          }
+          b"ns" => field = Some(Field::Namespace),
+          b"text" => field = Some(Field::Text),
+          _ => {}
+        },
+        Event::Text(e) => match field {
+          Some(Field::Namespace) => namespace.push_str(&e.unescape()?),
+          Some(Field::Text) => text.push_str(&e.unescape()?),
+          None => {}
+        },
+        Event::CData(e) if field == Some(Field::Text) => {
+          text.push_str(&String::from_utf8_lossy(&e));
+        }
+        Event::End(e) => match e.name().as_ref() {
+          b"ns" | b"text" => field = None,
+          b"page" => {
+            let is_redirect = text.trim_start().to_lowercase().starts_with("#redirect")
+              || text.trim_start().starts_with("#重定向");
+            if namespace.trim() == "0" && !is_redirect {
+              return Ok(Some(std::mem::take(&mut text)));
+            }
+          }
+          _ => {}
+        },
+        Event::Eof => return Ok(None),
+        _ => {}
+      }
+    }
+  }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Field {
+  Namespace,
+  Text,
+}
+
+impl<R: BufRead> Iterator for WikiPages<R> {
+  type Item = Result<String>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_page().transpose()
+  }
+}
+
+/// Removes every span delimited by `open` and `close`, including nested ones.
+fn remove_nested(text: &str, open: &str, close: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut depth = 0;
+  let mut rest = text;
+  while !rest.is_empty() {
+    if rest.starts_with(open) {
+      depth += 1;
+      rest = &rest[open.len()..];
+    } else if depth > 0 && rest.starts_with(close) {
+      depth -= 1;
+      rest = &rest[close.len()..];
+    } else {
+      let c = rest.chars().next().unwrap();
+      if depth == 0 {
+        out.push(c);
+      }
+      rest = &rest[c.len_utf8()..];
+    }
+  }
+  out
+}
+
+/// Replaces language-conversion markup such as `-{zh-hans:计算机;zh-hant:電腦;}-`
+/// with the text of its first variant, or with its body when it has no
+/// variants. Rules that only affect conversion elsewhere (`H`, `T`, `-`) are
+/// dropped.
+fn strip_conversions(text: &str) -> String {
+  static CONVERSION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)-\{(?:([A-Za-z;\-]*)\|)?(.*?)\}-").unwrap());
+  static VARIANT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*zh(?:-[A-Za-z]+)*\s*:([^;]*)").unwrap());
+  CONVERSION
+    .replace_all(text, |caps: &regex::Captures| {
+      let flags = caps.get(1).map_or("", |flags| flags.as_str());
+      if flags.contains(['H', 'T', '-']) {
+        return String::new();
+      }
+      let body = &caps[2];
+      match VARIANT.captures(body) {
+        Some(variant) => variant[1].trim().to_string(),
+        None => body.to_string(),
+      }
+    })
+    .into_owned()
+}
+
+/// Reduces MediaWiki markup to plain prose: templates, tables, references,
+/// comments, tags, and headings are dropped, links are replaced by their
+/// labels, language conversions keep their first variant, and bold/italic
+/// quotes and list markers are removed.
+pub fn strip_wikitext(text: &str) -> String {
+  static NOISE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<!--.*?-->|<ref[^>/]*/>|<ref[^>]*>.*?</ref>|<[^>]+>").unwrap()
+  });
+  static FILE_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\[\[(?:file|image|category|文件|檔案|图像|圖像|分类|分類):[^\[\]]*(?:\[\[[^\]]*\]\][^\[\]]*)*\]\]").unwrap()
+  });
+  static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[\[(?:[^\[\]|]*\|)?([^\[\]|]*)\]\]").unwrap());
+  static EXTERNAL_LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[(?:https?:)?//[^\s\]]*\s?([^\]]*)\]").unwrap());
+  static LINE_MARKUP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\s*=+.*=+\s*$|^[*#:;]+\s*|'{2,}").unwrap());
+
+  let text = remove_nested(text, "{{", "}}");
+  let text = remove_nested(&text, "{|", "|}");
+  let text = strip_conversions(&text);
+  let text = NOISE.replace_all(&text, "");
+  let text = FILE_LINK.replace_all(&text, "");
+  let text = LINK.replace_all(&text, "$1");
+  let text = EXTERNAL_LINK.replace_all(&text, "$1");
+  let text = LINE_MARKUP.replace_all(&text, "");
+  text
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strips_nested_templates_and_tables() {
+    let text = "北京{{lang|en|{{nowrap|Beijing}}}}是首都。\n{|\n| 表格 |}\n人口很多。";
+    assert_eq!(strip_wikitext(text), "北京是首都。\n人口很多。");
+  }
+
+  #[test]
+  fn keeps_link_labels() {
+    let text = "[[中华人民共和国|中国]]的[[首都]]，见[https://example.com 网站]。";
+    assert_eq!(strip_wikitext(text), "中国的首都，见网站。");
+  }
+
+  #[test]
+  fn drops_file_and_category_links() {
+    let text = "[[File:Beijing.jpg|thumb|[[天安门]]广场]]北京很大。[[Category:城市]][[分类:首都]]";
+    assert_eq!(strip_wikitext(text), "北京很大。");
+  }
+
+  #[test]
+  fn drops_references_comments_and_headings() {
+    let text = "== 历史 ==\n北京<ref name=\"a\">来源</ref>很老<ref name=\"b\"/>。<!-- 注释 -->\n* '''故宫'''";
+    assert_eq!(strip_wikitext(text), "北京很老。\n故宫");
+  }
+
+  #[test]
+  fn keeps_the_first_conversion_variant() {
+    let text = "他用-{zh-hans:计算机;zh-hant:電腦;}-写-{程序}-。-{H|zh-cn:网络;zh-tw:網路;}-";
+    assert_eq!(strip_wikitext(text), "他用计算机写程序。");
+  }
+
+  fn pages(xml: &str) -> Vec<String> {
+    WikiPages::new(xml.as_bytes())
+      .collect::<Result<Vec<_>>>()
+      .unwrap()
+  }
+
+  #[test]
+  fn returns_main_namespace_pages() {
+    let xml = r#"<mediawiki>
+      <page><title>北京</title><ns>0</ns><revision><text>北京是首都。</text></revision></page>
+      <page><title>Talk:北京</title><ns>1</ns><revision><text>讨论</text></revision></page>
+      <page><title>Category:城市</title><ns>14</ns><revision><text>分类</text></revision></page>
+      <page><title>上海</title><ns>0</ns><revision><text>上海&amp;很大。</text></revision></page>
+    </mediawiki>"#;
+    assert_eq!(pages(xml), ["北京是首都。", "上海&很大。"]);
+  }
+
+  #[test]
+  fn skips_redirects() {
+    let xml = r#"<mediawiki>
+      <page><title>北平</title><ns>0</ns><redirect title="北京" /><revision><text>#REDIRECT [[北京]]</text></revision></page>
+      <page><title>京师</title><ns>0</ns><revision><text>#重定向 [[北京]]</text></revision></page>
+      <page><title>北京</title><ns>0</ns><revision><text>北京是首都。</text></revision></page>
+    </mediawiki>"#;
+    assert_eq!(pages(xml), ["北京是首都。"]);
+  }
+}
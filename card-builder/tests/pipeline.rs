@@ -0,0 +1,557 @@
+//! Builds decks from a small fixture corpus with the real binary and checks
+//! them against known-good output, so changes that quietly alter which cards
+//! are made show up as test failures.
+
+use card_builder::coverage::CoverageReport;
+use rusqlite::Connection;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fs::File,
+  io,
+  path::{Path, PathBuf},
+  process::Command,
+};
+use tempfile::TempDir;
+
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/fixtures")
+    .join(name)
+}
+
+/// A full corpus and deck build of the fixtures into `data_dir`.
+fn command(data_dir: &TempDir, extra_args: &[&str]) -> Command {
+  let mut command = Command::new(env!("CARGO_BIN_EXE_card-builder"));
+  command
+    .arg("--data-dir")
+    .arg(data_dir.path())
+    .arg("--vocab-path")
+    .arg(fixture("hsk.csv"))
+    .arg("--corpus")
+    .arg(fixture("corpus.jsonl"))
+    .args(["--single-package", "--seed", "42", "--cards-per-deck", "20"])
+    .args(["--log-level", "error"])
+    .args(extra_args);
+  command
+}
+
+/// Runs a full corpus and deck build into a fresh data directory.
+fn build(extra_args: &[&str]) -> TempDir {
+  let data_dir = TempDir::new().unwrap();
+  let status = command(&data_dir, extra_args).status().unwrap();
+  assert!(status.success(), "card-builder exited with {status}");
+  data_dir
+}
+
+struct NoteRow {
+  fields: Vec<String>,
+  tags: Vec<String>,
+}
+
+impl NoteRow {
+  /// The phrase clozed in the note's first field.
+  fn cloze_answer(&self) -> &str {
+    let start = self.fields[0].find("{{c1::").unwrap() + "{{c1::".len();
+    let end = self.fields[0][start..].find("}}").unwrap();
+    &self.fields[0][start..start + end]
+  }
+
+  fn sentence(&self) -> String {
+    self.fields[0].replace(
+      &format!("{{{{c1::{}}}}}", self.cloze_answer()),
+      self.cloze_answer(),
+    )
+  }
+}
+
+/// The SQLite collection inside the .apkg at `path`, extracted to `scratch`.
+fn open_collection(path: &Path, scratch: &Path) -> Connection {
+  let mut package = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+  let collection = scratch.join("collection.anki2");
+  io::copy(
+    &mut package.by_name("collection.anki2").unwrap(),
+    &mut File::create(&collection).unwrap(),
+  )
+  .unwrap();
+  Connection::open(&collection).unwrap()
+}
+
+/// Every note in the .apkg at `path`.
+fn read_notes(path: &Path, scratch: &Path) -> Vec<NoteRow> {
+  let conn = open_collection(path, scratch);
+  let mut query = conn.prepare("SELECT flds, tags FROM notes").unwrap();
+  let notes = query
+    .query_map([], |row| {
+      let fields: String = row.get(0)?;
+      let tags: String = row.get(1)?;
+      Ok(NoteRow {
+        fields: fields.split(FIELD_SEPARATOR).map(str::to_string).collect(),
+        tags: tags.split_whitespace().map(str::to_string).collect(),
+      })
+    })
+    .unwrap()
+    .collect::<Result<Vec<_>, _>>()
+    .unwrap();
+  notes
+}
+
+/// The names of the decks in the .apkg at `path`.
+fn read_deck_names(path: &Path, scratch: &Path) -> Vec<String> {
+  let conn = open_collection(path, scratch);
+  let decks: String = conn
+    .query_row("SELECT decks FROM col", [], |row| row.get(0))
+    .unwrap();
+  let decks: BTreeMap<String, serde_json::Value> = serde_json::from_str(&decks).unwrap();
+  let mut names = decks
+    .into_values()
+    .map(|deck| deck["name"].as_str().unwrap().to_string())
+    .collect::<Vec<_>>();
+  names.sort();
+  names
+}
+
+fn deck_notes(data_dir: &TempDir) -> Vec<NoteRow> {
+  read_notes(&data_dir.path().join("decks/zhlearn.apkg"), data_dir.path())
+}
+
+#[test]
+fn snippets_per_phrase() {
+  let data_dir = build(&[]);
+  let report = CoverageReport::load(&data_dir.path().join("coverage.json")).unwrap();
+
+  let expected: BTreeMap<usize, BTreeMap<String, usize>> = [
+    (
+      1,
+      vec![
+        ("中文", 69),
+        ("了", 40),
+        ("今天", 42),
+        ("他", 10),
+        ("他们", 27),
+        ("你", 5),
+        ("去", 64),
+        ("喜欢", 24),
+        ("她", 12),
+        ("好", 0),
+        ("学习", 46),
+        ("学校", 87),
+        ("很", 24),
+        ("我", 12),
+        ("我们", 34),
+        ("明天", 22),
+        ("是", 23),
+        ("朋友", 20),
+        ("的", 51),
+        ("老师", 41),
+      ],
+    ),
+    (
+      2,
+      vec![
+        ("一起", 23),
+        ("准备", 45),
+        ("已经", 22),
+        ("漂亮", 22),
+        ("考试", 45),
+        ("非常", 22),
+      ],
+    ),
+    (
+      3,
+      vec![("图书馆", 48), ("经常", 23), ("认真", 23), ("问题", 23)],
+    ),
+  ]
+  .into_iter()
+  .map(|(level, counts)| {
+    let counts = counts
+      .into_iter()
+      .map(|(form, count)| (form.to_string(), count))
+      .collect();
+    (level, counts)
+  })
+  .collect();
+  assert_eq!(report.levels, expected);
+  assert_eq!(report.stats.total_phrases, 30);
+  assert_eq!(report.stats.covered, 29);
+}
+
+#[test]
+fn selected_sentences() {
+  let data_dir = build(&[]);
+  let notes = deck_notes(&data_dir);
+  assert_eq!(notes.len(), 61);
+
+  // No level repeats a sentence, even for different phrases.
+  let mut seen = BTreeSet::new();
+  for note in &notes {
+    let level = note.tags.iter().find(|tag| tag.starts_with("hsk::level-"));
+    assert!(
+      seen.insert((level.unwrap().clone(), note.sentence())),
+      "{level:?}: {}",
+      note.sentence()
+    );
+  }
+
+  let sentences_for = |phrase: &str| {
+    let mut sentences = notes
+      .iter()
+      .filter(|note| note.cloze_answer() == phrase)
+      .map(NoteRow::sentence)
+      .collect::<Vec<_>>();
+    sentences.sort();
+    sentences
+  };
+  assert_eq!(
+    sentences_for("图书馆"),
+    [
+      "他们的老师明天去图书馆准备考试了",
+      "你经常去图书馆学习中文",
+      "她的老师经常去图书馆学习中文",
+      "我们明天去图书馆准备考试了",
+      "我们的朋友明天去图书馆准备考试了",
+    ]
+  );
+  assert_eq!(
+    sentences_for("问题"),
+    [
+      "你已经认真准备了考试的问题",
+      "她已经认真准备了考试的问题",
+      "我们已经认真准备了考试的问题",
+      "我们的朋友已经认真准备了考试的问题",
+      "我的朋友已经认真准备了考试的问题",
+    ]
+  );
+
+  // 好 has no corpus sentence, so its card comes from the word list.
+  let fallback = notes
+    .iter()
+    .find(|note| note.cloze_answer() == "好")
+    .unwrap();
+  assert!(fallback.tags.contains(&"zhlearn::fallback".to_string()));
+}
+
+#[test]
+fn cloze_card_fields() {
+  let data_dir = build(&[]);
+  let notes = deck_notes(&data_dir);
+  let note = notes
+    .iter()
+    .find(|note| note.fields[0].starts_with("她的中文老师"))
+    .unwrap();
+  assert_eq!(note.fields[0], "她的中文老师非常非常{{c1::漂亮}}");
+  assert_eq!(note.fields[1], "");
+  assert_eq!(note.fields[2], "我们明天去图书馆准备考试了");
+  assert!(note.fields[6].ends_with("corpus.jsonl:124 #doc-123"));
+  assert_eq!(note.fields[7], "rank 28 / 30");
+  assert_eq!(note.fields[8], "漂亮");
+  // The fixture word list has no definitions.
+  assert_eq!(note.fields[9], "");
+  assert_eq!(
+    note.tags,
+    ["hsk::level-2", "zhlearn::corpus", "source::corpus"]
+  );
+}
+
+#[test]
+fn picked_levels() {
+  let data_dir = build(&["--levels", "3,2"]);
+  let report = CoverageReport::load(&data_dir.path().join("coverage.json")).unwrap();
+  assert_eq!(report.levels.keys().copied().collect::<Vec<_>>(), [2, 3]);
+  assert_eq!(report.stats.total_phrases, 10);
+
+  let notes = deck_notes(&data_dir);
+  assert!(!notes.is_empty());
+  assert!(notes
+    .iter()
+    .all(|note| !note.tags.contains(&"hsk::level-1".to_string())));
+
+  let data_dir = TempDir::new().unwrap();
+  let output = command(&data_dir, &["--levels", "10"]).output().unwrap();
+  assert!(!output.status.success());
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(stderr.contains("--levels 10"), "{stderr}");
+}
+
+#[test]
+fn json_output() {
+  let data_dir = TempDir::new().unwrap();
+  let output = command(&data_dir, &["--format", "json"]).output().unwrap();
+  assert!(output.status.success());
+  assert!(!data_dir.path().join("decks").exists());
+
+  let cards: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+  assert_eq!(cards.len(), 61);
+  let card = cards
+    .iter()
+    .find(|card| card["cloze"] == "她的中文老师非常非常{{c1::漂亮}}")
+    .unwrap();
+  assert_eq!(card["phrase"], "漂亮");
+  assert_eq!(card["level"], 2);
+  assert_eq!(card["suffix"], "我们明天去图书馆准备考试了");
+}
+
+#[test]
+fn dry_run_report() {
+  let data_dir = TempDir::new().unwrap();
+  let output = command(&data_dir, &["--dry-run"]).output().unwrap();
+  assert!(output.status.success());
+  assert!(!data_dir.path().join("decks").exists());
+
+  let levels: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+  let decks = levels
+    .iter()
+    .map(|level| level["deck"].as_str().unwrap())
+    .collect::<Vec<_>>();
+  assert_eq!(decks, ["HSK::Level 1", "HSK::Level 2", "HSK::Level 3"]);
+  let cards = levels
+    .iter()
+    .flat_map(|level| level["cards"].as_array().unwrap())
+    .collect::<Vec<_>>();
+  assert_eq!(cards.len(), 61);
+  let card = cards
+    .iter()
+    .find(|card| card["sentence"] == "她的中文老师非常非常漂亮")
+    .unwrap();
+  assert_eq!(card["cloze"], "她的中文老师非常非常{{c1::漂亮}}");
+  assert_eq!(card["has_context"], true);
+
+  // The cloze is the field the card type would hide the phrase in.
+  let output = command(&data_dir, &["--dry-run", "--card-type", "basic"])
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+  let levels: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+  let card = levels
+    .iter()
+    .flat_map(|level| level["cards"].as_array().unwrap())
+    .find(|card| card["sentence"] == "她的中文老师非常非常漂亮")
+    .unwrap();
+  assert_eq!(
+    card["cloze"],
+    "她的中文老师非常非常<span class=cloze>[...]</span>"
+  );
+}
+
+#[test]
+fn combined_deck() {
+  let data_dir = build(&["--combined-deck"]);
+  let combined = read_notes(&data_dir.path().join("decks/hsk-all.apkg"), data_dir.path());
+  assert_eq!(combined.len(), deck_notes(&data_dir).len());
+  for level in 1..=3 {
+    let tag = format!("hsk::level-{level}");
+    assert!(
+      combined.iter().any(|note| note.tags.contains(&tag)),
+      "{tag}"
+    );
+  }
+}
+
+#[test]
+fn empty_levels() {
+  // Knowing all of level 3 leaves it with no cards.
+  let package = |data_dir: &TempDir| {
+    read_deck_names(&data_dir.path().join("decks/zhlearn.apkg"), data_dir.path())
+  };
+  let data_dir = build(&["--known-levels", "3"]);
+  assert!(!package(&data_dir).contains(&"HSK::Level 3".to_string()));
+  assert!(deck_notes(&data_dir)
+    .iter()
+    .all(|note| !note.tags.contains(&"hsk::level-3".to_string())));
+
+  let data_dir = build(&["--known-levels", "3", "--allow-empty-decks"]);
+  assert!(package(&data_dir).contains(&"HSK::Level 3".to_string()));
+}
+
+#[test]
+fn filter_level_collects_one_level() {
+  let data_dir = build(&["--filter-level", "3"]);
+  let report = CoverageReport::load(&data_dir.path().join("coverage.json")).unwrap();
+  assert_eq!(report.levels.keys().copied().collect::<Vec<_>>(), [3]);
+  assert!(deck_notes(&data_dir)
+    .iter()
+    .all(|note| note.tags.contains(&"hsk::level-3".to_string())));
+}
+
+#[test]
+fn sentences_appear_in_one_level() {
+  // With every phrase indexed, many sentences qualify for both levels, and
+  // the fixture repeats sentences across documents.
+  let levels_by_sentence = |data_dir: &TempDir| {
+    let mut levels: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for note in deck_notes(data_dir) {
+      if !note.tags.contains(&"zhlearn::corpus".to_string()) {
+        continue;
+      }
+      let level = note.tags.iter().find(|tag| tag.starts_with("hsk::level-"));
+      levels
+        .entry(note.sentence())
+        .or_default()
+        .insert(level.unwrap().clone());
+    }
+    levels
+  };
+  let args = ["--levels", "1,2", "--index-every-level"];
+  let data_dir = build(&args);
+  let levels = levels_by_sentence(&data_dir);
+  assert!(levels.len() > 1);
+  assert!(
+    levels.values().all(|levels| levels.len() == 1),
+    "{levels:?}"
+  );
+
+  let data_dir = build(&[&args[..], &["--allow-cross-level-repeat"]].concat());
+  assert!(levels_by_sentence(&data_dir)
+    .values()
+    .any(|levels| levels.len() == 2));
+}
+
+#[test]
+fn min_cloze_ratio_falls_back_for_dropped_phrases() {
+  // Only a phrase that is the whole sentence reaches a ratio of 1, so every
+  // corpus snippet is dropped and the word list's examples take over.
+  let data_dir = build(&["--levels", "1", "--min-cloze-ratio", "1"]);
+  let notes = deck_notes(&data_dir);
+  assert!(notes
+    .iter()
+    .all(|note| note.tags.contains(&"zhlearn::fallback".to_string())));
+  let mut answers = notes.iter().map(NoteRow::cloze_answer).collect::<Vec<_>>();
+  answers.sort_unstable();
+  assert_eq!(answers, ["好", "学校"]);
+}
+
+#[test]
+fn unknown_words_are_counted_in_snippets() {
+  // 吃饭 isn't in the fixture word list, so its sentences are kept with a
+  // share of unknown words, unless it's known.
+  let unknown_ratios = |extra_args: &[&str]| {
+    let data_dir = build(extra_args);
+    let output = data_dir.path().join("snippets.json");
+    let export = ["export-snippets", "--phrase", "了", "--output"];
+    let status = command(
+      &data_dir,
+      &[&export[..], &[output.to_str().unwrap()]].concat(),
+    )
+    .status()
+    .unwrap();
+    assert!(status.success());
+    let snippets: Vec<serde_json::Value> =
+      serde_json::from_reader(File::open(&output).unwrap()).unwrap();
+    snippets
+      .iter()
+      .filter(|snippet| snippet["sentence"].as_str().unwrap().contains("吃饭"))
+      .map(|snippet| snippet["unknown_ratio"].as_f64().unwrap())
+      .collect::<Vec<_>>()
+  };
+  let ratios = unknown_ratios(&[]);
+  assert!(!ratios.is_empty());
+  assert!(ratios.iter().all(|ratio| *ratio > 0.), "{ratios:?}");
+
+  let data_dir = TempDir::new().unwrap();
+  let known = data_dir.path().join("known.txt");
+  std::fs::write(&known, "吃饭\n").unwrap();
+  let ratios = unknown_ratios(&["--known-words", known.to_str().unwrap()]);
+  assert!(!ratios.is_empty());
+  assert!(ratios.iter().all(|ratio| *ratio == 0.), "{ratios:?}");
+}
+
+#[test]
+fn reuse_index_needs_the_same_corpus() {
+  for store in ["file", "sqlite"] {
+    let data_dir = TempDir::new().unwrap();
+    let extra = data_dir.path().join("extra.jsonl");
+    std::fs::copy(fixture("corpus.jsonl"), &extra).unwrap();
+    let args = ["--store", store, "--corpus", extra.to_str().unwrap()];
+    let reuse = |args: &[&str]| {
+      command(&data_dir, &[args, &["--reuse-index"]].concat())
+        .status()
+        .unwrap()
+        .success()
+    };
+    assert!(command(&data_dir, &args).status().unwrap().success());
+    assert!(reuse(&args), "{store}");
+    assert!(!reuse(&args[..2]), "{store}");
+
+    let mut corpus = std::fs::read_to_string(&extra).unwrap();
+    corpus.push_str(&corpus.clone());
+    std::fs::write(&extra, corpus).unwrap();
+    assert!(!reuse(&args), "{store}");
+  }
+}
+
+#[test]
+fn stats_count_sources_in_the_selected_store() {
+  let data_dir = build(&["--store", "sqlite"]);
+  let output = command(&data_dir, &["--store", "sqlite", "stats"])
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  let (_, sources) = stdout
+    .split_once("Snippets per corpus source:\n")
+    .expect(&stdout);
+  let total = sources
+    .lines()
+    .map(|line| {
+      line
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .parse::<usize>()
+        .unwrap()
+    })
+    .sum::<usize>();
+  assert!(total > 0, "{stdout}");
+}
+
+#[test]
+fn export_csv_matches_the_build_with_known_levels() {
+  let known = ["--known-levels", "1..=1"];
+  let data_dir = build(&known);
+  let mut built = deck_notes(&data_dir)
+    .iter()
+    .filter(|note| note.tags.contains(&"hsk::level-2".to_string()))
+    .map(|note| (note.cloze_answer().to_string(), note.sentence()))
+    .collect::<Vec<_>>();
+  built.sort();
+  assert!(!built.is_empty());
+
+  let output = data_dir.path().join("level-2.csv");
+  let export = ["export-csv", "--level", "2", "--output"];
+  let args = [&known[..], &export, &[output.to_str().unwrap()]].concat();
+  assert!(command(&data_dir, &args).status().unwrap().success());
+  let mut exported = csv::Reader::from_path(&output)
+    .unwrap()
+    .records()
+    .map(|record| {
+      let record = record.unwrap();
+      (record[0].to_string(), record[1].to_string())
+    })
+    .collect::<Vec<_>>();
+  exported.sort();
+  assert_eq!(exported, built);
+}
+
+#[test]
+fn sqlite_store_reuses_its_index() {
+  let fields = |data_dir: &TempDir| {
+    let mut fields = deck_notes(data_dir)
+      .into_iter()
+      .map(|note| note.fields)
+      .collect::<Vec<_>>();
+    fields.sort();
+    fields
+  };
+  let data_dir = build(&["--store", "sqlite"]);
+  let built = fields(&data_dir);
+  assert!(!built.is_empty());
+  assert!(data_dir.path().join("snippets.sqlite").exists());
+  assert!(!data_dir.path().join("phrases.idx").exists());
+
+  let status = command(&data_dir, &["--store", "sqlite", "--reuse-index"])
+    .status()
+    .unwrap();
+  assert!(status.success());
+  assert_eq!(fields(&data_dir), built);
+}